@@ -1,11 +1,295 @@
+//lib.rs is compiled into the binary directly (see the symlinks in src/lib)
+#![allow(special_module_name)]
+
+//the binary only drives the benchmark, so most of the library api is unused here
+#[allow(dead_code)]
 mod lib;
 
-use std::ffi::{CString};
+use std::env;
+use std::fs;
+use std::process;
+use lib::{Times, Tests, OutputFormat, compare};
+use lib::crypto_pairing::PairCheck;
+
+const USAGE: &str = "usage: mybin [bench] [options]
+       mybin compare [compare options]
+
+Runs the punch card experiments from the paper. With no options this is the
+same run the Android app does (25519 group, 1000 iterations, 10 punches).
+
+options:
+  --scheme group|pairing|lookup   which experiment to run (default group)
+  --iterations N                  how many iterations to average over (default 1000)
+  --punches N                     punches before a card is redeemed (default 10)
+  --setup-rows N                  used cards in the database before each iteration (default 0)
+  --pair-check MODE               how the pairing server checks redeemed cards: both, product
+                                  or single (default both, single is the paper's unsafe check)
+  --format text|json|csv          output format (default text)
+  --save-json FILE                also write the results as json to FILE
+  --save-csv FILE                 also write the results as csv to FILE
+  -h, --help                      show this message
+
+compare reruns the configurations published in data.txt and shows how this machine compares:
+  --data FILE                     published results to compare against (default data.txt)
+  --device NAME                   which published device to compare with (default computer)
+  --iterations N                  run fewer iterations than were published, means are compared
+  --threshold PERCENT             flag operations that got slower by more than this (default 25)
+exits with status 1 if any operation is flagged";
+
+//what the compare subcommand was asked for
+struct CompareArgs {
+    data: String,
+    device: String,
+    iterations: Option<u32>,
+    //fraction, 0.25 is 25% slower
+    threshold: f64,
+}
+
+//what the command line asked for
+struct Args {
+    times: Times,
+    format: OutputFormat,
+    //extra copies of the results to write, alongside what goes to stdout
+    saves: Vec<(OutputFormat, String)>,
+}
+
+fn parse_number(flag: &str, value: Option<String>) -> Result<u32, String> {
+    let value = value.ok_or(format!("{} needs a value", flag))?;
+    value.parse::<u32>().map_err(|_| format!("{} expects a non-negative number, got '{}'", flag, value))
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut times = Times::new(Tests::Group, 1000, 10, 0);
+    let mut format = OutputFormat::Text;
+    let mut saves = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            //the only subcommand for now, also the default
+            "bench" => {},
+            "-h" | "--help" | "help" => return Ok(None),
+            "--scheme" => {
+                times.test_type = match args.next().as_deref() {
+                    Some("group") => Tests::Group,
+                    Some("pairing") => Tests::Pairing,
+                    Some("lookup") => Tests::Lookup,
+                    other => return Err(format!("unknown scheme {:?}", other.unwrap_or(""))),
+                };
+            },
+            "--iterations" => times.num_iterations = parse_number(&arg, args.next())?,
+            "--punches" => times.num_punches = parse_number(&arg, args.next())?,
+            "--setup-rows" => times.setup_rows = parse_number(&arg, args.next())?,
+            "--pair-check" => {
+                times.pair_check = match args.next().as_deref() {
+                    Some("single") => PairCheck::Single,
+                    Some("both") => PairCheck::Both,
+                    Some("product") => PairCheck::Product,
+                    other => return Err(format!("unknown pairing check {:?}", other.unwrap_or(""))),
+                };
+            },
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => OutputFormat::Text,
+                    Some("json") => OutputFormat::Json,
+                    Some("csv") => OutputFormat::Csv,
+                    other => return Err(format!("unknown format {:?}", other.unwrap_or(""))),
+                };
+            },
+            "--save-json" => saves.push((OutputFormat::Json, args.next().ok_or("--save-json needs a file")?)),
+            "--save-csv" => saves.push((OutputFormat::Csv, args.next().ok_or("--save-csv needs a file")?)),
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+
+    times.check()?;
+    Ok(Some(Args { times, format, saves }))
+}
+
+fn parse_compare_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<CompareArgs>, String> {
+    let mut compare = CompareArgs {
+        data: "data.txt".to_owned(),
+        device: "computer".to_owned(),
+        iterations: None,
+        threshold: 0.25,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" | "help" => return Ok(None),
+            "--data" => compare.data = args.next().ok_or("--data needs a file")?,
+            "--device" => compare.device = args.next().ok_or("--device needs a name")?,
+            "--iterations" => {
+                let n = parse_number(&arg, args.next())?;
+                if n == 0 {
+                    return Err("iterations must be at least 1".to_owned());
+                }
+                compare.iterations = Some(n);
+            },
+            "--threshold" => {
+                let value = args.next().ok_or("--threshold needs a value")?;
+                let percent = value.parse::<f64>().ok().filter(|p| *p >= 0.0)
+                    .ok_or(format!("--threshold expects a non-negative percentage, got '{}'", value))?;
+                compare.threshold = percent / 100.0;
+            },
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+    Ok(Some(compare))
+}
+
+//rerun every published run for the device and print how it compares
+//returns whether anything regressed
+fn run_compare(args: &CompareArgs) -> Result<bool, String> {
+    let text = fs::read_to_string(&args.data).map_err(|err| format!("couldn't read {}: {}", args.data, err))?;
+    let baselines: Vec<_> = compare::parse_baselines(&text).into_iter()
+        .filter(|b| b.device == args.device)
+        .collect();
+    if baselines.is_empty() {
+        return Err(format!("no published results for device '{}' in {}", args.device, args.data));
+    }
+
+    let mut regressed = false;
+    for baseline in &baselines {
+        let mut times = baseline.times(args.iterations);
+        times.check()?;
+        let name = lib::run_benchmark(&mut times);
+        let rows = compare::compare(baseline, &times, args.threshold);
+        regressed |= rows.iter().any(|row| row.regression);
+        println!("{}", compare::format_comparison(baseline, &name, &times, &rows));
+    }
+    if regressed {
+        println!("some operations are more than {}% slower than published", args.threshold * 100.0);
+    }
+    Ok(regressed)
+}
 
 fn main(){
-    let world = lib::benchmarkCode();
-    let world_ptr = unsafe { CString::from_raw(world) };
-    let rust_string = world_ptr.to_str().unwrap();
-    println!("{}", rust_string);
+    let mut raw_args = env::args().skip(1).peekable();
+    if raw_args.peek().map(String::as_str) == Some("compare") {
+        raw_args.next();
+        let args = match parse_compare_args(raw_args) {
+            Ok(Some(args)) => args,
+            Ok(None) => {
+                println!("{}", USAGE);
+                return;
+            },
+            Err(msg) => {
+                eprintln!("{}\n\n{}", msg, USAGE);
+                process::exit(2);
+            },
+        };
+        match run_compare(&args) {
+            Ok(false) => return,
+            Ok(true) => process::exit(1),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                process::exit(2);
+            },
+        }
+    }
+
+    let mut args = match parse_args(raw_args) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(2);
+        },
+    };
+
+    let name = lib::run_benchmark(&mut args.times);
+    print!("{}", lib::format_results(&args.times, &name, args.format));
+    
+    for (format, path) in &args.saves {
+        if let Err(err) = fs::write(path, lib::format_results(&args.times, &name, *format)) {
+            eprintln!("couldn't write {}: {}", path, err);
+            process::exit(1);
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> std::vec::IntoIter<String> {
+        line.split_whitespace().map(str::to_owned).collect::<Vec<_>>().into_iter()
+    }
+
+    fn parse(line: &str) -> Result<Args, String> {
+        parse_args(args(line)).map(|parsed| parsed.expect("not a help request"))
+    }
+
+    fn parse_compare(line: &str) -> Result<CompareArgs, String> {
+        parse_compare_args(args(line)).map(|parsed| parsed.expect("not a help request"))
+    }
+
+    #[test]
+    fn bench_args() {
+        let parsed = parse("").unwrap();
+        assert_eq!(parsed.times.test_type, Tests::Group);
+        assert_eq!((parsed.times.num_iterations, parsed.times.num_punches, parsed.times.setup_rows), (1000, 10, 0));
+        assert_eq!(parsed.times.pair_check, PairCheck::Both);
+        assert_eq!(parsed.format, OutputFormat::Text);
+        assert!(parsed.saves.is_empty());
+
+        let parsed = parse("bench --scheme lookup --iterations 5 --punches 3 --setup-rows 100 --pair-check product --format csv --save-json out.json --save-csv out.csv").unwrap();
+        assert_eq!(parsed.times.test_type, Tests::Lookup);
+        assert_eq!((parsed.times.num_iterations, parsed.times.num_punches, parsed.times.setup_rows), (5, 3, 100));
+        assert_eq!(parsed.times.pair_check, PairCheck::Product);
+        assert_eq!(parsed.format, OutputFormat::Csv);
+        assert_eq!(parsed.saves, vec![(OutputFormat::Json, "out.json".to_owned()), (OutputFormat::Csv, "out.csv".to_owned())]);
+
+        //the last --format wins
+        assert_eq!(parse("--format json --format text").unwrap().format, OutputFormat::Text);
+        assert!(parse_args(args("--iterations 5 --help")).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_bench_args() {
+        assert_eq!(parse("--verbose").err().unwrap(), "unknown argument '--verbose'");
+        assert_eq!(parse("--iterations").err().unwrap(), "--iterations needs a value");
+        assert_eq!(parse("--punches ten").err().unwrap(), "--punches expects a non-negative number, got 'ten'");
+        assert_eq!(parse("--setup-rows -1").err().unwrap(), "--setup-rows expects a non-negative number, got '-1'");
+        assert_eq!(parse("--scheme").err().unwrap(), "unknown scheme \"\"");
+        assert_eq!(parse("--scheme rsa").err().unwrap(), "unknown scheme \"rsa\"");
+        assert_eq!(parse("--pair-check all").err().unwrap(), "unknown pairing check \"all\"");
+        assert_eq!(parse("--format xml").err().unwrap(), "unknown format \"xml\"");
+        assert_eq!(parse("--save-csv").err().unwrap(), "--save-csv needs a file");
+        assert_eq!(parse("--iterations 0").err().unwrap(), "iterations must be at least 1");
+        assert_eq!(parse("--scheme lookup").err().unwrap(), "the lookup test needs setup rows to look up");
+        //compare is only a subcommand when it comes first
+        assert_eq!(parse("bench compare").err().unwrap(), "unknown argument 'compare'");
+    }
+
+    #[test]
+    fn compare_args() {
+        let parsed = parse_compare("").unwrap();
+        assert_eq!((parsed.data.as_str(), parsed.device.as_str()), ("data.txt", "computer"));
+        assert_eq!(parsed.iterations, None);
+        assert_eq!(parsed.threshold, 0.25);
+
+        let parsed = parse_compare("--data other.txt --device phone --iterations 20 --threshold 10").unwrap();
+        assert_eq!((parsed.data.as_str(), parsed.device.as_str()), ("other.txt", "phone"));
+        assert_eq!(parsed.iterations, Some(20));
+        assert_eq!(parsed.threshold, 0.1);
+        assert!(parse_compare_args(args("--device phone -h")).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_compare_args() {
+        //the output flags only apply to bench
+        assert_eq!(parse_compare("--format json").err().unwrap(), "unknown argument '--format'");
+        assert_eq!(parse_compare("--save-csv out.csv").err().unwrap(), "unknown argument '--save-csv'");
+        assert_eq!(parse_compare("--data").err().unwrap(), "--data needs a file");
+        assert_eq!(parse_compare("--device").err().unwrap(), "--device needs a name");
+        assert_eq!(parse_compare("--iterations 0").err().unwrap(), "iterations must be at least 1");
+        assert_eq!(parse_compare("--iterations many").err().unwrap(), "--iterations expects a non-negative number, got 'many'");
+        assert_eq!(parse_compare("--threshold").err().unwrap(), "--threshold needs a value");
+        assert_eq!(parse_compare("--threshold -5").err().unwrap(), "--threshold expects a non-negative percentage, got '-5'");
+        assert_eq!(parse_compare("--threshold lots").err().unwrap(), "--threshold expects a non-negative percentage, got 'lots'");
+    }
+}
@@ -0,0 +1,237 @@
+//Reproducing the published numbers in data.txt and checking for regressions against them
+//data.txt is free text, so parsing picks out the pieces the benchmark needs:
+//which scheme a section is about, the device, the run configuration, and the cumulative totals per operation
+//averages, communication costs and notes are skipped
+
+use super::{Times, Tests, operations};
+use super::crypto_pairing::PairCheck;
+
+//one published run from data.txt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Baseline {
+	pub device: String,
+	pub test_type: Tests,
+	pub num_iterations: u32,
+	pub num_punches: u32,
+	pub setup_rows: u32,
+	//operation label (as printed by the benchmark) and cumulative microseconds
+	pub totals: Vec<(String, u128)>,
+}
+
+//how one operation compares with its published number
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+	pub label: String,
+	pub published_mean: f64,
+	pub current_mean: f64,
+	//current / published, above 1 is slower
+	pub ratio: f64,
+	pub regression: bool,
+}
+
+//the number right after prefix in line, with an optional K or M suffix ("1M" is 1000000)
+fn number_after(line: &str, prefix: &str) -> Option<u32> {
+	let rest = &line[line.find(prefix)? + prefix.len()..];
+	let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+	let value: u32 = digits.parse().ok()?;
+	match rest[digits.len()..].chars().next() {
+		Some('K') => value.checked_mul(1_000),
+		Some('M') => value.checked_mul(1_000_000),
+		_ => Some(value),
+	}
+}
+
+//"Server punch: 1343669" -> ("Server punch", 1343669), None for anything else
+fn total_line(line: &str) -> Option<(String, u128)> {
+	let mut parts = line.splitn(2, ':');
+	let label = parts.next()?.trim();
+	let value = parts.next()?.trim().parse::<u128>().ok()?;
+	Some((label.to_owned(), value))
+}
+
+//pull every published run out of the contents of data.txt
+pub fn parse_baselines(text: &str) -> Vec<Baseline> {
+	let mut baselines: Vec<Baseline> = Vec::new();
+	let mut test_type = Tests::Group;
+	//the run whose totals are being read, if any
+	let mut current: Option<Baseline> = None;
+	let mut collecting = false;
+
+	for line in text.lines().map(str::trim) {
+		if line.starts_with("Punch card scheme with") {
+			test_type = if line.contains("pairing") { Tests::Pairing } else { Tests::Group };
+		} else if line.starts_with("Performance Results") {
+			baselines.extend(current.take());
+			let device = match (line.rfind('('), line.rfind(')')) {
+				(Some(open), Some(close)) if open < close => line[open + 1..close].to_owned(),
+				_ => String::new(),
+			};
+			current = Some(Baseline {
+				device,
+				test_type,
+				num_iterations: 0,
+				num_punches: 0,
+				setup_rows: 0,
+				totals: Vec::new(),
+			});
+		} else if let (Some(n), Some(run)) = (number_after(line, "database size to "), current.as_ref()) {
+			//a rerun of the same configuration with a bigger database
+			let mut rerun = run.clone();
+			rerun.setup_rows = n;
+			rerun.totals.clear();
+			baselines.extend(current.replace(rerun));
+			collecting = true;
+		} else if let Some(run) = current.as_mut() {
+			if let Some(n) = number_after(line, "repeated for ") {
+				run.num_iterations = n;
+			}
+			if let Some(n) = number_after(line, "(") {
+				if line.contains("punches per iteration") {
+					run.num_punches = n;
+				}
+			}
+			if let Some(n) = number_after(line, "starts with ") {
+				run.setup_rows = n;
+			}
+
+			if line.starts_with("Numbers are cumulative") {
+				collecting = true;
+			} else if collecting {
+				match total_line(line) {
+					Some(total) => run.totals.push(total),
+					None => collecting = false,
+				}
+			}
+		}
+	}
+	baselines.extend(current);
+	baselines.retain(|b| b.num_iterations > 0 && !b.totals.is_empty());
+	baselines
+}
+
+impl Baseline {
+	//a benchmark configuration that reproduces this run
+	//iterations can be lowered to save time, since the comparison is between means
+	//the published pairing runs only checked the first pairing, so they're rerun that way
+	pub fn times(&self, iterations: Option<u32>) -> Times {
+		let mut times = Times::new(self.test_type, iterations.unwrap_or(self.num_iterations), self.num_punches, self.setup_rows);
+		times.pair_check = PairCheck::Single;
+		times
+	}
+
+	//published mean for label, punches are timed num_punches times per iteration and everything else once
+	pub fn mean(&self, label: &str) -> Option<f64> {
+		let total = self.totals.iter().find(|(l, _)| l == label)?.1;
+		let count = if label.ends_with("punch") {
+			self.num_iterations * self.num_punches
+		} else {
+			self.num_iterations
+		};
+		if count == 0 {
+			return None;
+		}
+		Some(total as f64 / count as f64)
+	}
+}
+
+//compare the means in times with the published ones
+//an operation is a regression if it got slower by more than threshold (0.25 is 25% slower)
+pub fn compare(baseline: &Baseline, times: &Times, threshold: f64) -> Vec<Comparison> {
+	operations(times).iter()
+		.filter_map(|(_, label, _, samples)| {
+			let published_mean = baseline.mean(label)?;
+			let current_mean = samples.summary()?.mean;
+			let ratio = current_mean / published_mean;
+			Some(Comparison {
+				label: label.to_string(),
+				published_mean,
+				current_mean,
+				ratio,
+				regression: ratio > 1.0 + threshold,
+			})
+		})
+		.collect()
+}
+
+//side by side table of a comparison
+pub fn format_comparison(baseline: &Baseline, name: &str, times: &Times, rows: &[Comparison]) -> String {
+	let mut out = format!("{} ({}): {} iterations (published {}), {} punches, {} setup rows\n",
+		name, baseline.device, times.num_iterations, baseline.num_iterations, baseline.num_punches, baseline.setup_rows);
+	out += &format!("{:<14} {:>14} {:>14} {:>16}\n", "mean (us)", "published", "current", "change");
+	for row in rows {
+		let change = if row.ratio >= 1.0 {
+			format!("{:.2}x slower", row.ratio)
+		} else {
+			format!("{:.2}x faster", 1.0 / row.ratio)
+		};
+		out += &format!("{:<14} {:>14.3} {:>14.3} {:>16}{}\n",
+			row.label, row.published_mean, row.current_mean, change, if row.regression { "  REGRESSION" } else { "" });
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SAMPLE: &str = "Punch card scheme with curve25519-dalek
+
+Performance Results (computer)
+Each operation is repeated for 1000 iterations, except punches, which are done 10000 times (10 punches per iteration).
+The server database starts with 0 used punchcards in each iteration.
+Numbers are cumulative over all runs, in microseconds.
+Server setup: 18590
+Server punch: 1343669
+
+so the averages are (in microseconds):
+Server setup: 18.590
+
+When I increased the starting database size to 1M, I got these numbers
+Server setup: 27371
+The only one whose performance should be affected...
+
+Communication costs (for messages sent in protocol)
+Server setup: 32 bytes (g^secret)
+
+Punch card scheme with pairing-plus (BLS12-381)
+
+Performance Results for BLS12_381 group with merging (Google Pixel)
+Each operation is repeated for 100 iterations, except punches, which are done 1000 times (10 punches per iteration).
+The server database starts with 0 used punchcards in each iteration.
+Numbers are cumulative over all runs, in microseconds.
+Client punch: 137793866
+";
+
+	#[test]
+	fn parses_published_runs() {
+		let runs = parse_baselines(SAMPLE);
+		assert_eq!(runs.len(), 3);
+
+		assert_eq!(runs[0].device, "computer");
+		assert_eq!(runs[0].test_type, Tests::Group);
+		assert_eq!((runs[0].num_iterations, runs[0].num_punches, runs[0].setup_rows), (1000, 10, 0));
+		assert_eq!(runs[0].totals, vec![("Server setup".to_owned(), 18590), ("Server punch".to_owned(), 1343669)]);
+		assert!((runs[0].mean("Server punch").unwrap() - 134.3669).abs() < 1e-9);
+
+		assert_eq!(runs[1].setup_rows, 1_000_000);
+		assert_eq!(runs[1].totals, vec![("Server setup".to_owned(), 27371)]);
+
+		assert_eq!(runs[2].device, "Google Pixel");
+		assert_eq!(runs[2].test_type, Tests::Pairing);
+		assert_eq!(runs[2].num_iterations, 100);
+		//rerun with the paper's check, not the default
+		assert_eq!(runs[2].times(None).pair_check, PairCheck::Single);
+	}
+
+	#[test]
+	fn flags_slowdowns_past_threshold() {
+		let runs = parse_baselines(SAMPLE);
+		let mut times = runs[0].times(Some(1));
+		times.server_setup.record(20); //about 8% slower
+		times.server_punch.record(200); //about 49% slower
+		let rows = compare(&runs[0], &times, 0.25);
+		assert_eq!(rows.len(), 2);
+		assert!(!rows[0].regression);
+		assert!(rows[1].regression);
+	}
+}
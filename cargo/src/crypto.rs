@@ -1,36 +1,339 @@
-use sha2::Sha512;
 use rand_core::{RngCore, OsRng};
 use curve25519_dalek::constants;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::ristretto::RistrettoPoint;
 use curve25519_dalek::scalar::Scalar;
-use std::collections::HashSet;
+use curve25519_dalek::traits::{IsIdentity, VartimeMultiscalarMul};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use super::scheme::{PunchCardScheme, AuditedScheme, check_outcome};
+use super::error::{PunchCardError, AuditedRejection};
+use super::wire::{WireScheme, TAG_RISTRETTO, read_array32};
+use super::nullifier::{NullifierStore, MemoryNullifierStore};
+use super::keyfile::{encode_key, decode_key, write_dst, read_dsts};
+use super::wallet::{encode_card_state, decode_card_state};
+use super::expiry::{secret_until, check_expiry, ExpiryPolicy};
+use super::merchant::{card_message, DEFAULT_MERCHANT};
+use super::threshold::{ThresholdCombiner, ThresholdSigner, deal_secret};
+use super::transcript::Transcript;
+use super::epoch::DEFAULT_EPOCH;
+use super::hash_to_curve::{hash_to_ristretto255, RISTRETTO255_DST};
 
 #[derive(Debug)]
-pub struct ServerData {
+pub struct ServerData<N: NullifierStore = MemoryNullifierStore> {
 	secret: Scalar,
-	used_cards: HashSet<[u8; 32]>,
+	merchant: u32,
+	epoch: u32,
+	used_cards: N,
 	pub_secret: CompressedRistretto,
+	//secret^n and g^(secret^n) for the thresholds given to precompute_thresholds
+	powers: HashMap<u32, (Scalar, CompressedRistretto)>,
+	//which card secrets are taken, None takes any (see expiry.rs)
+	expiry: Option<ExpiryPolicy>,
+	//DST cards are hashed with (see hash_to_curve.rs)
+	dst: Vec<u8>,
 }
 
 #[derive(Debug)]
 pub struct PunchCard {
+	merchant: u32,
+	epoch: u32,
 	card_secret: [u8; 32], 
 	punch_card: RistrettoPoint,
 	last_mask: Scalar,
 	count: u32,
+	dst: Vec<u8>,
 }
 
 //notation from Figure 19.7 in Boneh-Shoup textbook v0.5
 #[derive(Debug)]
 pub struct Proof {
-	v_t: CompressedRistretto,
-	w_t: CompressedRistretto,
-	beta_z: [u8; 32],
+	pub(crate) v_t: CompressedRistretto,
+	pub(crate) w_t: CompressedRistretto,
+	pub(crate) beta_z: [u8; 32],
+}
+
+//g^(secret^k) for k = 1 to powers.len(), so a client can check a punch by k
+//proofs[i] shows powers[i+1] = powers[i]^secret, it's the same proof as a punch of the "card" powers[i]
+#[derive(Debug)]
+pub struct PowerKey {
+	merchant: u32,
+	epoch: u32,
+	powers: Vec<CompressedRistretto>,
+	proofs: Vec<Proof>,
+	dst: Vec<u8>,
+}
+
+//server-assisted merge: two unmasked cards, as they'd be redeemed, and how many punches each holds
+//the server checks and uses up both and punches a fresh card with the total (see ServerData::server_merge)
+//unlike the pairing scheme the server learns the count on each card, but the fresh card is unlinkable to them
+#[derive(Debug)]
+pub struct MergeRequest {
+	pub first: RedeemToken,
+	pub first_count: u32,
+	pub second: RedeemToken,
+	pub second_count: u32,
+}
+
+//the server's evidence for a redemption decision (see ServerData::server_verify_audited)
+//anyone with a checked PowerKey can tell from it whether outcome was honest
+//whether a card was already redeemed can't be proven this way, a DoubleRedemption only shows the card itself was valid
+#[derive(Debug)]
+pub struct RedemptionReceipt {
+	pub card_secret: [u8; 32],
+	pub card: CompressedRistretto,
+	pub num_punches: u32,
+	pub evidence: RedemptionEvidence,
+	pub outcome: Result<(), PunchCardError>,
+}
+
+//Valid proves card is H(card_secret)^(secret^num_punches), the same power as the published g^(secret^num_punches)
+//Invalid proves it isn't, without giving out the card that would have been valid, so a rejected card isn't
+//used up and can still be redeemed with its right count
+#[derive(Debug)]
+pub enum RedemptionEvidence {
+	Valid(Proof),
+	Invalid(MismatchProof),
+}
+
+//proof that card != u^x where pub_power = g^x, after Camenisch-Shoup
+//for a random r the prover sends diff = (u^x / card)^r and shows it knows a, r with diff = u^a / card^r and
+//1 = g^a / pub_power^r, the second forces a = x*r, so diff isn't the identity exactly when card != u^x
+#[derive(Debug)]
+pub struct MismatchProof {
+	pub(crate) diff: CompressedRistretto,
+	pub(crate) t_diff: CompressedRistretto,
+	pub(crate) t_one: CompressedRistretto,
+	pub(crate) z_a: [u8; 32],
+	pub(crate) z_r: [u8; 32],
+}
+
+//one Chaum-Pedersen statement and proof, decoded and with the challenge recomputed
+//u is the card before the punch and c the punched card, so c = u^secret
+struct Dleq {
+	u: RistrettoPoint,
+	c: RistrettoPoint,
+	v_t: RistrettoPoint,
+	w_t: RistrettoPoint,
+	beta_z: Scalar,
+	chal: Scalar,
+}
+
+//length of a saved PunchCard body
+const CARD_STATE_LEN: usize = 108;
+
+//which proof a punch transcript is for
+const PUNCH_PROOF: &[u8] = b"ristretto punch";
+
+//which proof a mismatch transcript is for
+const MISMATCH_PROOF: &[u8] = b"ristretto mismatch";
+
+//the transcript every punch proof for merchant in epoch starts from
+pub(crate) fn punch_transcript(merchant: u32, epoch: u32) -> Transcript {
+	Transcript::new(PUNCH_PROOF, merchant, epoch)
+}
+
+//Fiat-Shamir challenge for a punch, the server and the client must append the same things to base
+pub(crate) fn dleq_challenge(base: &Transcript, pub_secret: &CompressedRistretto, card: &CompressedRistretto, new_card: &CompressedRistretto,
+				  v_t: &CompressedRistretto, w_t: &CompressedRistretto) -> Scalar {
+	let mut transcript = base.clone();
+	transcript.append_message(b"pub_secret", pub_secret.as_bytes());
+	transcript.append_message(b"card", card.as_bytes());
+	transcript.append_message(b"new_card", new_card.as_bytes());
+	transcript.append_message(b"v_t", v_t.as_bytes());
+	transcript.append_message(b"w_t", w_t.as_bytes());
+	Scalar::from_bytes_mod_order_wide(&transcript.challenge_bytes(b"chal"))
+}
+
+//the unpunched card for card_secret at merchant, hashed with dst
+pub(crate) fn card_hash(merchant: u32, card_secret: &[u8; 32], dst: &[u8]) -> RistrettoPoint {
+	hash_to_ristretto255(&card_message(merchant, card_secret), dst)
+}
+
+impl Dleq {
+	
+	fn decode(base: &Transcript, pub_secret: &CompressedRistretto, u: RistrettoPoint, new_card: &CompressedRistretto, proof: &Proof) -> Result<Dleq, PunchCardError> {
+		let chal = dleq_challenge(base, pub_secret, &u.compress(), new_card, &proof.v_t, &proof.w_t);
+		Ok(Dleq {
+			u,
+			c: new_card.decompress().ok_or(PunchCardError::MalformedPoint)?,
+			v_t: proof.v_t.decompress().ok_or(PunchCardError::MalformedPoint)?,
+			w_t: proof.w_t.decompress().ok_or(PunchCardError::MalformedPoint)?,
+			beta_z: Scalar::from_canonical_bytes(proof.beta_z).ok_or(PunchCardError::MalformedScalar)?,
+			chal,
+		})
+	}
+	
+	//see Boneh Shoup textbook v0.5 Figure 19.7
+	fn check(&self, pub_secret: &RistrettoPoint) -> bool {
+		let gbz: RistrettoPoint = &self.beta_z * &constants::RISTRETTO_BASEPOINT_TABLE;
+		let vtvc = self.v_t + (pub_secret * self.chal);
+		let ubz = self.u * self.beta_z;
+		let wtwc = self.w_t + (self.c * self.chal);
+		gbz == vtvc && ubz == wtwc
+	}
+}
+
+//multiply card by exponent and prove it has the same discrete log as pub_power = g^exponent
+pub(crate) fn dleq_punch(base: &Transcript, card: CompressedRistretto, exponent: Scalar,
+						 pub_power: CompressedRistretto) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+
+	let card_dec = card.decompress().ok_or(PunchCardError::MalformedPoint)?;
+	let new_card_dec = card_dec * exponent;
+	let new_card = new_card_dec.compress();
+	
+	//generate Chaum-Pedersen proof
+	//see Boneh Shoup textbook v0.5 Figure 19.7
+	let beta_t = Scalar::random(&mut OsRng);
+	let v_t = &beta_t * &constants::RISTRETTO_BASEPOINT_TABLE;
+	let v_t_compress = v_t.compress();
+	let w_t = card_dec * beta_t;
+	let w_t_compress = w_t.compress();
+
+	let chal = dleq_challenge(base, &pub_power, &card, &new_card, &v_t_compress, &w_t_compress);
+	let beta_z = beta_t + exponent * chal;
+	
+	let proof = Proof {
+		v_t: v_t_compress,
+		w_t: w_t_compress,
+		beta_z: beta_z.to_bytes(),
+	};
+	
+	Ok((new_card, proof))
+}
+
+//check one proof made by dleq_punch, BadProof if it doesn't verify
+pub(crate) fn dleq_verify(base: &Transcript, pub_power: &CompressedRistretto, card: &CompressedRistretto,
+						  new_card: &CompressedRistretto, proof: &Proof) -> Result<(), PunchCardError> {
+	let u = card.decompress().ok_or(PunchCardError::MalformedPoint)?;
+	let dleq = Dleq::decode(base, pub_power, u, new_card, proof)?;
+	if !dleq.check(&pub_power.decompress().ok_or(PunchCardError::MalformedPoint)?) {
+		return Err(PunchCardError::BadProof);
+	}
+	Ok(())
+}
+
+//the transcript for a MismatchProof that card != u^x, where pub_power = g^x
+fn mismatch_transcript(merchant: u32, epoch: u32, pub_power: &CompressedRistretto, u: &CompressedRistretto, card: &CompressedRistretto) -> Transcript {
+	let mut transcript = Transcript::new(MISMATCH_PROOF, merchant, epoch);
+	transcript.append_message(b"pub_power", pub_power.as_bytes());
+	transcript.append_message(b"u", u.as_bytes());
+	transcript.append_message(b"card", card.as_bytes());
+	transcript
+}
+
+//Fiat-Shamir challenge for a MismatchProof, from mismatch_transcript
+fn mismatch_challenge(mut transcript: Transcript, diff: &CompressedRistretto, t_diff: &CompressedRistretto, t_one: &CompressedRistretto) -> Scalar {
+	transcript.append_message(b"diff", diff.as_bytes());
+	transcript.append_message(b"t_diff", t_diff.as_bytes());
+	transcript.append_message(b"t_one", t_one.as_bytes());
+	Scalar::from_bytes_mod_order_wide(&transcript.challenge_bytes(b"chal"))
+}
+
+//prove that card != u^exponent where pub_power = g^exponent, the caller checks that it isn't
+fn mismatch_prove(merchant: u32, epoch: u32, u: RistrettoPoint, card: RistrettoPoint, exponent: Scalar,
+				  pub_power: CompressedRistretto) -> Result<MismatchProof, PunchCardError> {
+	let pub_power_dec = pub_power.decompress().ok_or(PunchCardError::MalformedPoint)?;
+	let r = Scalar::random(&mut OsRng);
+	let a = exponent * r;
+	let diff = u * a - card * r;
+	
+	let t_a = Scalar::random(&mut OsRng);
+	let t_r = Scalar::random(&mut OsRng);
+	let t_diff = (u * t_a - card * t_r).compress();
+	let t_one = (&t_a * &constants::RISTRETTO_BASEPOINT_TABLE - pub_power_dec * t_r).compress();
+	
+	let diff = diff.compress();
+	let transcript = mismatch_transcript(merchant, epoch, &pub_power, &u.compress(), &card.compress());
+	let chal = mismatch_challenge(transcript, &diff, &t_diff, &t_one);
+	Ok(MismatchProof {
+		diff,
+		t_diff,
+		t_one,
+		z_a: (t_a + chal * a).to_bytes(),
+		z_r: (t_r + chal * r).to_bytes(),
+	})
+}
+
+//check a MismatchProof that card != u^x where pub_power = g^x, BadProof if it doesn't verify
+fn mismatch_verify(merchant: u32, epoch: u32, pub_power: &CompressedRistretto, u: &CompressedRistretto, card: &CompressedRistretto,
+				   proof: &MismatchProof) -> Result<(), PunchCardError> {
+	let decompress = |point: &CompressedRistretto| point.decompress().ok_or(PunchCardError::MalformedPoint);
+	let (pub_power_dec, u_dec, card_dec) = (decompress(pub_power)?, decompress(u)?, decompress(card)?);
+	let (diff, t_diff, t_one) = (decompress(&proof.diff)?, decompress(&proof.t_diff)?, decompress(&proof.t_one)?);
+	let z_a = Scalar::from_canonical_bytes(proof.z_a).ok_or(PunchCardError::MalformedScalar)?;
+	let z_r = Scalar::from_canonical_bytes(proof.z_r).ok_or(PunchCardError::MalformedScalar)?;
+	let chal = mismatch_challenge(mismatch_transcript(merchant, epoch, pub_power, u, card), &proof.diff, &proof.t_diff, &proof.t_one);
+	
+	//u^z_a / card^z_r = t_diff * diff^chal and g^z_a / pub_power^z_r = t_one
+	let diff_holds = u_dec * z_a - card_dec * z_r == t_diff + diff * chal;
+	let one_holds = &z_a * &constants::RISTRETTO_BASEPOINT_TABLE - pub_power_dec * z_r == t_one;
+	if diff.is_identity() || !diff_holds || !one_holds {
+		return Err(PunchCardError::BadProof);
+	}
+	Ok(())
+}
+
+//check many punch proofs made by merchant in epoch against pub_secret at once
+//each proof's two equations are scaled by fresh random scalars and summed, so the whole batch is
+//one multiscalar multiplication that is the identity only if (except with negligible probability) every proof verifies
+//if the batch fails, the proofs are checked one by one to find the bad one
+pub fn batch_verify(pub_secret: CompressedRistretto, merchant: u32, epoch: u32,
+					punches: &[(&CompressedRistretto, &CompressedRistretto, &Proof)]) -> Result<(), PunchCardError> {
+	
+	let base = punch_transcript(merchant, epoch);
+	let pub_secret_dec = pub_secret.decompress().ok_or(PunchCardError::MalformedPoint)?;
+	let mut proofs = Vec::with_capacity(punches.len());
+	let mut undecodable = None;
+	for (i, (card, new_card, proof)) in punches.iter().enumerate() {
+		let decoded = card.decompress().ok_or(PunchCardError::MalformedPoint)
+			.and_then(|u| Dleq::decode(&base, &pub_secret, u, new_card, proof));
+		match decoded {
+			Ok(decoded) => proofs.push(decoded),
+			Err(_) => {
+				undecodable = Some(i);
+				break;
+			},
+		}
+	}
+	if undecodable.is_none() && batch_identity(&proofs, pub_secret_dec) {
+		return Ok(());
+	}
+	
+	//find the culprit, if all the decoded ones pass then it's the one that didn't decode
+	match proofs.iter().position(|proof| !proof.check(&pub_secret_dec)).or(undecodable) {
+		Some(i) => Err(PunchCardError::BadProofAt(i)),
+		None => Err(PunchCardError::BadProof),
+	}
+}
+
+//the random linear combination of all the proofs' equations, which is the identity if they all hold
+fn batch_identity(proofs: &[Dleq], pub_secret: RistrettoPoint) -> bool {
+	//g^(sum rho*z) - pub_secret^(sum rho*chal) + sum (-rho*v_t + sigma*z*u - sigma*w_t - sigma*chal*c)
+	let mut scalars = Vec::with_capacity(2 + 4*proofs.len());
+	let mut points = Vec::with_capacity(2 + 4*proofs.len());
+	let mut g_scalar = Scalar::zero();
+	let mut pub_scalar = Scalar::zero();
+	for proof in proofs {
+		let rho = Scalar::random(&mut OsRng);
+		let sigma = Scalar::random(&mut OsRng);
+		g_scalar += rho * proof.beta_z;
+		pub_scalar -= rho * proof.chal;
+		scalars.extend_from_slice(&[-rho, sigma * proof.beta_z, -sigma, -(sigma * proof.chal)]);
+		points.extend_from_slice(&[proof.v_t, proof.u, proof.w_t, proof.c]);
+	}
+	scalars.push(g_scalar);
+	points.push(constants::RISTRETTO_BASEPOINT_POINT);
+	scalars.push(pub_scalar);
+	points.push(pub_secret);
+	
+	RistrettoPoint::vartime_multiscalar_mul(&scalars, &points).is_identity()
 }
 
 fn scalar_exponentiate(base: Scalar, exp: u32) -> Scalar{
-	if exp == 1 {
+	if exp == 0 {
+		Scalar::one()
+	} else if exp == 1 {
 		base
 	} else if exp % 2 == 1{
 		base * scalar_exponentiate(base, exp - 1)
@@ -43,83 +346,281 @@ fn scalar_exponentiate(base: Scalar, exp: u32) -> Scalar{
 
 impl ServerData {
 
-	//set up the server secret and redeemed card db
+	//set up the server secret with an in-memory redeemed card db
 	pub fn server_setup() -> (CompressedRistretto, ServerData) {
+		ServerData::server_setup_with_store(MemoryNullifierStore::new())
+	}
+}
+
+impl<N: NullifierStore> ServerData<N> {
+
+	//set up the server secret, recording redeemed cards in used_cards
+	pub fn server_setup_with_store(used_cards: N) -> (CompressedRistretto, ServerData<N>) {
+		Self::server_setup_for(DEFAULT_MERCHANT, used_cards)
+	}
+	
+	//set up the secret for merchant (see merchant.rs)
+	pub fn server_setup_for(merchant: u32, used_cards: N) -> (CompressedRistretto, ServerData<N>) {
+		Self::server_setup_with_dst(merchant, used_cards, RISTRETTO255_DST)
+	}
+	
+	//set up the secret for merchant, hashing cards with dst instead of RISTRETTO255_DST
+	//only cards set up with the same dst (PunchCard::card_setup_with_dst) redeem here
+	pub fn server_setup_with_dst(merchant: u32, used_cards: N, dst: &[u8]) -> (CompressedRistretto, ServerData<N>) {
+		Self::from_secret(Scalar::random(&mut OsRng), merchant, used_cards, dst)
+	}
+	
+	fn from_secret(secret: Scalar, merchant: u32, used_cards: N, dst: &[u8]) -> (CompressedRistretto, ServerData<N>) {
 	
-		let secret = Scalar::random(&mut OsRng);
-		let used_cards = HashSet::new();
 		let pub_secret = &secret * &constants::RISTRETTO_BASEPOINT_TABLE;
 		let pub_secret = pub_secret.compress();
 		let new_server = ServerData {
 			secret,
+			merchant,
+			epoch: DEFAULT_EPOCH,
 			used_cards,
 			pub_secret,
+			powers: HashMap::new(),
+			expiry: None,
+			dst: dst.to_vec(),
 		};
 		
 		(pub_secret, new_server)
 	}
 	
+	pub fn merchant(&self) -> u32 {
+		self.merchant
+	}
+	
+	pub fn epoch(&self) -> u32 {
+		self.epoch
+	}
+	
+	pub fn dst(&self) -> &[u8] {
+		&self.dst
+	}
+	
+	//bind the proofs from now on to epoch (see epoch.rs), clients have to check them in the same epoch
+	pub fn set_epoch(&mut self, epoch: u32) {
+		self.epoch = epoch;
+	}
+	
+	//check every card secret taken from now on against policy (see expiry.rs), None takes any
+	pub fn set_expiry_policy(&mut self, policy: Option<ExpiryPolicy>) {
+		self.expiry = policy;
+	}
+	
+	pub fn expiry_policy(&self) -> Option<ExpiryPolicy> {
+		self.expiry
+	}
+	
+	fn transcript(&self) -> Transcript {
+		punch_transcript(self.merchant, self.epoch)
+	}
+	
+	//key body is secret || merchant (u32 little endian) || epoch (u32 little endian) || pub_secret || dst
+	//see keyfile.rs for the header and how the dst is written
+	pub fn export_key(&self) -> Vec<u8> {
+		let mut body = Vec::with_capacity(72 + 4 + self.dst.len());
+		body.extend_from_slice(self.secret.as_bytes());
+		body.extend_from_slice(&self.merchant.to_le_bytes());
+		body.extend_from_slice(&self.epoch.to_le_bytes());
+		body.extend_from_slice(self.pub_secret.as_bytes());
+		write_dst(&self.dst, &mut body);
+		encode_key(TAG_RISTRETTO, &body)
+	}
+	
+	//reload a server exported with export_key
+	//fails with CorruptStore if the public key doesn't belong to the secret
+	pub fn import_key(key: &[u8], used_cards: N) -> Result<(CompressedRistretto, ServerData<N>), PunchCardError> {
+		let (body, rest) = decode_key(TAG_RISTRETTO, key, 72)?;
+		let dsts = read_dsts(rest, 1)?;
+		let secret = Scalar::from_canonical_bytes(read_array32(&body[0..32])?).ok_or(PunchCardError::MalformedScalar)?;
+		let merchant = u32::from_le_bytes([body[32], body[33], body[34], body[35]]);
+		let epoch = u32::from_le_bytes([body[36], body[37], body[38], body[39]]);
+		let stored_pub_secret = CompressedRistretto(read_array32(&body[40..72])?);
+		
+		let (pub_secret, mut server) = Self::from_secret(secret, merchant, used_cards, &dsts[0]);
+		if pub_secret != stored_pub_secret {
+			return Err(PunchCardError::CorruptStore);
+		}
+		server.set_epoch(epoch);
+		Ok((pub_secret, server))
+	}
+	
 	//punch card by multiplying by secret
 	//prove that this was done honestly
-	pub fn server_punch(&self, card: CompressedRistretto) -> (CompressedRistretto, Proof) {
-	
-		let card_dec = card.decompress().expect("couldn't decompress point in server_punch");
-		let new_card_dec = card_dec * self.secret;
-		let new_card = new_card_dec.compress();
-		
-		//generate Chaum-Pedersen proof
-		//see Boneh Shoup textbook v0.5 Figure 19.7
-		let beta_t = Scalar::random(&mut OsRng);
-		let v_t = &beta_t * &constants::RISTRETTO_BASEPOINT_TABLE;
-		let v_t_compress = v_t.compress();
-		let w_t = card_dec * beta_t;
-		let w_t_compress = w_t.compress();
-
-		let mut hashinput: Vec<u8> = Vec::new();
-		hashinput.extend_from_slice(&self.pub_secret.to_bytes());
-		hashinput.extend_from_slice(&card.to_bytes());
-		hashinput.extend_from_slice(&new_card.to_bytes());
-		hashinput.extend_from_slice(&v_t_compress.to_bytes());
-		hashinput.extend_from_slice(&w_t_compress.to_bytes());
-		let hashinput_bytes: &[u8] = &hashinput;
-		let chal = Scalar::hash_from_bytes::<Sha512>(hashinput_bytes);
-		let beta_z = beta_t + self.secret * chal;
-		
-		let proof = Proof {
-			v_t: v_t_compress,
-			w_t: w_t_compress,
-			beta_z: beta_z.to_bytes(),
-		};
-		
-		(new_card, proof)
+	pub fn server_punch(&self, card: CompressedRistretto) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		self.punch_with(card, self.secret, self.pub_secret)
+	}
+	
+	//redemptions usually happen at a few fixed punch counts, so secret^n (and g^(secret^n) for punches by n)
+	//can be computed once for each of them instead of on every redemption
+	pub fn precompute_thresholds(&mut self, thresholds: &[u32]) {
+		for n in thresholds {
+			let exponent = scalar_exponentiate(self.secret, *n);
+			let pub_power = (&exponent * &constants::RISTRETTO_BASEPOINT_TABLE).compress();
+			self.powers.insert(*n, (exponent, pub_power));
+		}
+	}
+	
+	//secret^n, from the precomputed thresholds if n is one of them
+	fn secret_power(&self, n: u32) -> Scalar {
+		match self.powers.get(&n) {
+			Some((exponent, _)) => *exponent,
+			None => scalar_exponentiate(self.secret, n),
+		}
+	}
+	
+	//punch card k times at once by multiplying by secret^k
+	//the proof is against g^(secret^k), which clients get from a PowerKey
+	pub fn server_punch_by(&self, card: CompressedRistretto, k: u32) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		if k == 0 {
+			return Err(PunchCardError::InvalidPunchCount(k));
+		}
+		let (exponent, pub_power) = self.power(k);
+		self.punch_with(card, exponent, pub_power)
+	}
+	
+	//secret^k and g^(secret^k), from the precomputed thresholds if k is one of them
+	fn power(&self, k: u32) -> (Scalar, CompressedRistretto) {
+		match self.powers.get(&k) {
+			Some(power) => *power,
+			None => {
+				let exponent = scalar_exponentiate(self.secret, k);
+				(exponent, (&exponent * &constants::RISTRETTO_BASEPOINT_TABLE).compress())
+			},
+		}
+	}
+	
+	//publish the powers needed to check punches of up to max_k at a time
+	pub fn power_key(&self, max_k: u32) -> Result<PowerKey, PunchCardError> {
+		if max_k == 0 {
+			return Err(PunchCardError::InvalidPunchCount(max_k));
+		}
+		let mut powers = vec![self.pub_secret];
+		let mut proofs = Vec::new();
+		for i in 1..max_k as usize {
+			let (next, proof) = self.server_punch(powers[i - 1])?;
+			powers.push(next);
+			proofs.push(proof);
+		}
+		Ok(PowerKey {
+			merchant: self.merchant,
+			epoch: self.epoch,
+			powers,
+			proofs,
+			dst: self.dst.clone(),
+		})
+	}
+	
+	fn punch_with(&self, card: CompressedRistretto, exponent: Scalar, pub_power: CompressedRistretto) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		dleq_punch(&self.transcript(), card, exponent, pub_power)
 	}
 	
 	//check that the punch card is valid with num_punches
-	//check that the punch card secret is new
-	pub fn server_verify(&mut self, card: CompressedRistretto, card_secret: [u8; 32], num_punches: u32) -> bool {
-		
-		let num_punches = scalar_exponentiate(self.secret, num_punches);
-		let expected_card = RistrettoPoint::hash_from_bytes::<Sha512>(&card_secret) * num_punches;
+	//check that the punch card secret is new and taken by the expiry policy
+	pub fn server_verify(&mut self, card: CompressedRistretto, card_secret: [u8; 32], num_punches: u32) -> Result<(), PunchCardError> {
 		
+		check_expiry(self.expiry, &[card_secret])?;
+		self.check_card(card, card_secret, num_punches)?;
+		//insert returns false if this was already in the store
+		if !self.used_cards.insert(card_secret)? {
+			return Err(PunchCardError::DoubleRedemption);
+		}
+		Ok(())
+	}
+	
+	//server_verify that also proves how it decided, for the client or an auditor
+	//Ok only if the card was redeemed, a rejected or double redeemed card is an AuditedRejection carrying its receipt,
+	//without one for failures that leave nothing to audit
+	//like server_verify only a valid card uses up card_secret, a rejected one (e.g. sent with the wrong count)
+	//gets a proof that it doesn't match (see RedemptionEvidence) and can be sent again
+	pub fn server_verify_audited(&mut self, card: CompressedRistretto, card_secret: [u8; 32],
+								 num_punches: u32) -> Result<RedemptionReceipt, AuditedRejection<RedemptionReceipt>> {
+		//anyone can check this from the card secret, so it's refused before there's anything to audit
+		check_expiry(self.expiry, &[card_secret])?;
+		let card_dec = card.decompress().ok_or(PunchCardError::MalformedPoint)?;
+		let (exponent, pub_power) = self.power(num_punches);
+		let unpunched = card_hash(self.merchant, &card_secret, &self.dst);
 		
-		if card == expected_card.compress() {
-			//returns true if this was not in the set
-			self.used_cards.insert(card_secret)
+		let (evidence, outcome) = if card_dec == unpunched * exponent {
+			let (_, proof) = self.punch_with(unpunched.compress(), exponent, pub_power)?;
+			//insert returns false if this was already in the store
+			let outcome = match self.used_cards.insert(card_secret)? {
+				true => Ok(()),
+				false => Err(PunchCardError::DoubleRedemption),
+			};
+			(RedemptionEvidence::Valid(proof), outcome)
 		} else {
-			false
+			let proof = mismatch_prove(self.merchant, self.epoch, unpunched, card_dec, exponent, pub_power)?;
+			(RedemptionEvidence::Invalid(proof), Err(PunchCardError::InvalidCard))
+		};
+		let receipt = RedemptionReceipt {
+			card_secret,
+			card,
+			num_punches,
+			evidence,
+			outcome,
+		};
+		match receipt.outcome.clone() {
+			Ok(()) => Ok(receipt),
+			Err(error) => Err(AuditedRejection { error, receipt: Some(Box::new(receipt)) }),
+		}
+	}
+	
+	//check that card is H(card_secret)^(secret^num_punches)
+	fn check_card(&self, card: CompressedRistretto, card_secret: [u8; 32], num_punches: u32) -> Result<(), PunchCardError> {
+		let num_punches = self.secret_power(num_punches);
+		let expected_card = card_hash(self.merchant, &card_secret, &self.dst) * num_punches;
+		
+		
+		if card != expected_card.compress() {
+			return Err(PunchCardError::InvalidCard);
+		}
+		Ok(())
+	}
+	
+	//merge the two cards in request into fresh_card
+	//both cards are checked against their counts like a redemption and used up, and fresh_card is punched
+	//with the total in one go, so the client checks it with verify_remask_by against a PowerKey that reaches the total
+	//fresh_card is punched before the old cards are marked used, so a bad fresh card doesn't cost the client them
+	pub fn server_merge(&mut self, request: MergeRequest, fresh_card: CompressedRistretto) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		let total = request.total()?;
+		check_expiry(self.expiry, &[request.first.card_secret, request.second.card_secret])?;
+		self.check_card(request.first.card, request.first.card_secret, request.first_count)?;
+		self.check_card(request.second.card, request.second.card_secret, request.second_count)?;
+		let punched = self.server_punch_by(fresh_card, total)?;
+		
+		//a card merged with itself would carry its punches twice
+		//insert_all records both or neither
+		let secrets = [request.first.card_secret, request.second.card_secret];
+		if secrets[0] == secrets[1] || !self.used_cards.insert_all(&secrets)? {
+			return Err(PunchCardError::DoubleRedemption);
 		}
+		Ok(punched)
 	}
 	
 	pub fn count_cards(&self) -> usize {
 		self.used_cards.len()
 	}
 	
+	//split the secret into shares for signers signer processes, any threshold of which can punch (see threshold.rs)
+	//the server is used up so the whole secret doesn't outlive the split, cards it punched keep working
+	//and the combiner keeps its expiry policy
+	pub fn into_threshold(self, threshold: u32, signers: u32) -> Result<(ThresholdCombiner<N>, Vec<ThresholdSigner>), PunchCardError> {
+		let (mut combiner, shares) = deal_secret(self.secret, self.merchant, self.epoch, self.used_cards, threshold, signers, self.dst)?;
+		combiner.set_expiry_policy(self.expiry);
+		Ok((combiner, shares))
+	}
+	
 	//preload the database of used cards with num entries
-	pub fn cheat_setup_db(&mut self, num:u32) {
+	pub fn cheat_setup_db(&mut self, num:u32) -> Result<(), PunchCardError> {
 		for i in 0..num {
 			let temp = Scalar::from(i).to_bytes();
-			self.used_cards.insert(temp);
+			self.used_cards.insert(temp)?;
 		}
+		Ok(())
 	}
 	
 	pub fn lookup_test(&self, input: [u8; 32]) -> bool {
@@ -133,20 +634,52 @@ impl PunchCard {
 	//create a new punchcard
 	//punch card is already masked after this function
 	pub fn card_setup() -> (CompressedRistretto, PunchCard) {
+		Self::card_setup_for(DEFAULT_MERCHANT)
+	}
+	
+	//create a new punchcard for merchant (see merchant.rs)
+	pub fn card_setup_for(merchant: u32) -> (CompressedRistretto, PunchCard) {
 		
 		let mut card_secret = [0u8; 32];
 		OsRng.fill_bytes(&mut card_secret);
 		
+		Self::card_setup_with_secret(merchant, card_secret, RISTRETTO255_DST)
+	}
+	
+	//create a new punchcard for merchant that expires after epoch expiry (see expiry.rs)
+	pub fn card_setup_until(merchant: u32, expiry: u32) -> (CompressedRistretto, PunchCard) {
+		Self::card_setup_with_secret(merchant, secret_until(expiry), RISTRETTO255_DST)
+	}
+	
+	//create a new punchcard for merchant hashed with dst instead of RISTRETTO255_DST,
+	//for a server set up with ServerData::server_setup_with_dst, expiring after epoch expiry if there is one
+	pub fn card_setup_with_dst(merchant: u32, expiry: Option<u32>, dst: &[u8]) -> (CompressedRistretto, PunchCard) {
+		let card_secret = match expiry {
+			Some(expiry) => secret_until(expiry),
+			None => {
+				let mut card_secret = [0u8; 32];
+				OsRng.fill_bytes(&mut card_secret);
+				card_secret
+			},
+		};
+		Self::card_setup_with_secret(merchant, card_secret, dst)
+	}
+	
+	fn card_setup_with_secret(merchant: u32, card_secret: [u8; 32], dst: &[u8]) -> (CompressedRistretto, PunchCard) {
+		
 		let last_mask = Scalar::random(&mut OsRng);
 		
 		//the punch card is already masked at this point
-		let punch_card = RistrettoPoint::hash_from_bytes::<Sha512>(&card_secret) * last_mask;
+		let punch_card = card_hash(merchant, &card_secret, dst) * last_mask;
 		
 		let new_punch_card = PunchCard {
+			merchant,
+			epoch: DEFAULT_EPOCH,
 			card_secret,
 			punch_card,
 			last_mask,
 			count: 0,
+			dst: dst.to_vec(),
 		};
 		
 		(new_punch_card.punch_card.compress(), new_punch_card)
@@ -154,59 +687,593 @@ impl PunchCard {
 	
 	//verify proof from the server
 	//if accepted, unmask punchcard, remask with new mask, increment count
-	//otherwise reuse old punchcard, same count
+	//otherwise return BadProof and keep the old punchcard, same count
 	pub fn verify_remask(&mut self, card: CompressedRistretto, pub_secret: CompressedRistretto,
-						 proof: Proof) -> (CompressedRistretto, bool) {
+						 proof: Proof) -> Result<CompressedRistretto, PunchCardError> {
+		self.verify_remask_with(card, pub_secret, 1, proof)
+	}
+	
+	//verify_remask for a punch by k, checked against g^(secret^k) from powers
+	//powers should have been checked with PowerKey::verify
+	pub fn verify_remask_by(&mut self, card: CompressedRistretto, powers: &PowerKey, k: u32,
+							proof: Proof) -> Result<CompressedRistretto, PunchCardError> {
+		let pub_power = powers.power(k).ok_or(PunchCardError::InvalidPunchCount(k))?;
+		self.verify_remask_with(card, pub_power, k, proof)
+	}
+	
+	fn verify_remask_with(&mut self, card: CompressedRistretto, pub_power: CompressedRistretto, k: u32,
+						  proof: Proof) -> Result<CompressedRistretto, PunchCardError> {
+		let count = self.count.checked_add(k).ok_or(PunchCardError::InvalidPunchCount(k))?;
 		
 		//verify Chaum-Pedersen proof
-		//see Boneh Shoup textbook v0.5 Figure 19.7
-		let mut hashinput: Vec<u8> = Vec::new();
-		hashinput.extend_from_slice(&pub_secret.to_bytes());
-		hashinput.extend_from_slice(&self.punch_card.compress().to_bytes());
-		hashinput.extend_from_slice(&card.to_bytes());
-		hashinput.extend_from_slice(&proof.v_t.to_bytes());
-		hashinput.extend_from_slice(&proof.w_t.to_bytes());
-		let hashinput_bytes: &[u8] = &hashinput;
-		let chal = Scalar::hash_from_bytes::<Sha512>(hashinput_bytes);
-		
-		let gbz: RistrettoPoint = &Scalar::from_bytes_mod_order(proof.beta_z) * &constants::RISTRETTO_BASEPOINT_TABLE;
-		let vtvc = proof.v_t.decompress().expect("couldn't decompress in verify_remask") 
-					+ (pub_secret.decompress().expect("couldn't decompress pub_secret in verify_remask")
-							 * chal);
-		let ubz = self.punch_card * Scalar::from_bytes_mod_order(proof.beta_z);
-		let wtwc = proof.w_t.decompress().expect("couldn't decompress in verify_remask") 
-					+ (card.decompress().expect("couldn't decompress in verify_remask") * chal);
-					
-
-		let mut success = true;
-		if gbz == vtvc && ubz == wtwc {
-		//if true { //for debugging
-			let unmasked_card = card.decompress()
-				.expect("couldn't decompress point in verify_remask") 
-				* self.last_mask.invert();
-			self.last_mask = Scalar::random(&mut OsRng);
-			self.punch_card = unmasked_card * self.last_mask;
-			self.count += 1;
-		} else {
-			success = false;
+		let dleq = Dleq::decode(&punch_transcript(self.merchant, self.epoch), &pub_power, self.punch_card, &card, &proof)?;
+		if !dleq.check(&pub_power.decompress().ok_or(PunchCardError::MalformedPoint)?) {
+			return Err(PunchCardError::BadProof);
 		}
 
-		(self.punch_card.compress(), success)
+		let unmasked_card = dleq.c * self.last_mask.invert();
+		self.last_mask = Scalar::random(&mut OsRng);
+		self.punch_card = unmasked_card * self.last_mask;
+		self.count = count;
+
+		Ok(self.punch_card.compress())
 	}
 	
 	//unmask the punch card and return its relevant contents
 	pub fn unmask_redeem(&mut self) -> ([u8; 32], CompressedRistretto) {
 	
-		self.punch_card = self.punch_card * self.last_mask.invert();
+		self.punch_card *= self.last_mask.invert();
 		
 		(self.card_secret, self.punch_card.compress())
 	}
 	
+	//unmask this card and other to merge them with ServerData::server_merge
+	//the merged punches go on a fresh card, so neither of these can be used again
+	pub fn unmask_merge(mut self, mut other: PunchCard) -> MergeRequest {
+		let (first_count, second_count) = (self.count, other.count);
+		let (card_secret, card) = self.unmask_redeem();
+		let first = RedeemToken { card_secret, card };
+		let (card_secret, card) = other.unmask_redeem();
+		let second = RedeemToken { card_secret, card };
+		MergeRequest {
+			first,
+			first_count,
+			second,
+			second_count,
+		}
+	}
+	
 	pub fn get_count(&self) -> u32 {
 		self.count
 	}
 	
+	//the epoch whose punches this card checks (see epoch.rs), it has to match the server's
+	pub fn set_epoch(&mut self, epoch: u32) {
+		self.epoch = epoch;
+	}
+	
+	//the current masked card, to send for the next punch
+	pub fn get_card(&self) -> CompressedRistretto {
+		self.punch_card.compress()
+	}
+	
+	//state body is merchant (u32 little endian) || epoch (u32 little endian) || card_secret || count (u32 little endian)
+	//|| punch_card || last_mask || dst, see wallet.rs for the header and keyfile.rs for how the dst is written
+	pub fn save_state(&self) -> Vec<u8> {
+		let mut body = Vec::with_capacity(CARD_STATE_LEN + 4 + self.dst.len());
+		body.extend_from_slice(&self.merchant.to_le_bytes());
+		body.extend_from_slice(&self.epoch.to_le_bytes());
+		body.extend_from_slice(&self.card_secret);
+		body.extend_from_slice(&self.count.to_le_bytes());
+		body.extend_from_slice(self.punch_card.compress().as_bytes());
+		body.extend_from_slice(self.last_mask.as_bytes());
+		write_dst(&self.dst, &mut body);
+		encode_card_state(TAG_RISTRETTO, &body)
+	}
+	
+	//restore a card saved with save_state
+	//without the server secret we can't check the punches themselves,
+	//but the mask must be usable and a card with no punches must be the hash of its secret
+	pub fn restore_state(state: &[u8]) -> Result<PunchCard, PunchCardError> {
+		let (body, rest) = decode_card_state(TAG_RISTRETTO, state, CARD_STATE_LEN)?;
+		let dst = read_dsts(rest, 1)?.remove(0);
+		let merchant = u32::from_le_bytes([body[0], body[1], body[2], body[3]]);
+		let epoch = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+		let body = &body[8..];
+		let card_secret = read_array32(&body[0..32])?;
+		let count = u32::from_le_bytes([body[32], body[33], body[34], body[35]]);
+		let punch_card = CompressedRistretto(read_array32(&body[36..68])?)
+			.decompress().ok_or(PunchCardError::MalformedPoint)?;
+		let last_mask = Scalar::from_canonical_bytes(read_array32(&body[68..100])?)
+			.ok_or(PunchCardError::MalformedScalar)?;
+		
+		if last_mask == Scalar::zero() {
+			return Err(PunchCardError::NotInvertible);
+		}
+		if count == 0 && punch_card != card_hash(merchant, &card_secret, &dst) * last_mask {
+			return Err(PunchCardError::InvalidCard);
+		}
+		
+		Ok(PunchCard {
+			merchant,
+			epoch,
+			card_secret,
+			punch_card,
+			last_mask,
+			count,
+			dst,
+		})
+	}
+	
 	pub fn exp_test(&self) -> RistrettoPoint{
         self.punch_card * self.last_mask
 	}
 }
+
+impl MergeRequest {
+
+	//punches the merged card gets
+	pub fn total(&self) -> Result<u32, PunchCardError> {
+		self.first_count.checked_add(self.second_count).ok_or(PunchCardError::InvalidPunchCount(u32::MAX))
+	}
+}
+
+impl RedemptionReceipt {
+	
+	//check the server's evidence and that outcome follows from it
+	//powers must reach num_punches and should have been checked with PowerKey::verify
+	//BadProof if the evidence doesn't verify, DishonestRedemption if the outcome doesn't match it
+	pub fn audit(&self, powers: &PowerKey) -> Result<(), PunchCardError> {
+		let pub_power = match self.num_punches {
+			0 => constants::RISTRETTO_BASEPOINT_COMPRESSED,
+			n => powers.power(n).ok_or(PunchCardError::InvalidPunchCount(n))?,
+		};
+		let unpunched = card_hash(powers.merchant, &self.card_secret, &powers.dst).compress();
+		let valid = match &self.evidence {
+			RedemptionEvidence::Valid(proof) => {
+				dleq_verify(&punch_transcript(powers.merchant, powers.epoch), &pub_power, &unpunched, &self.card, proof)?;
+				true
+			},
+			RedemptionEvidence::Invalid(proof) => {
+				mismatch_verify(powers.merchant, powers.epoch, &pub_power, &unpunched, &self.card, proof)?;
+				false
+			},
+		};
+		
+		check_outcome(valid, &self.outcome)
+	}
+}
+
+impl PowerKey {
+	
+	//g^(secret^k), if k is covered
+	pub fn power(&self, k: u32) -> Option<CompressedRistretto> {
+		if k == 0 {
+			return None;
+		}
+		self.powers.get(k as usize - 1).copied()
+	}
+	
+	//largest k this key can check
+	pub fn max_k(&self) -> u32 {
+		self.powers.len() as u32
+	}
+	
+	//check that the powers start at pub_secret and each is the previous one raised to the secret
+	pub fn verify(&self, pub_secret: CompressedRistretto) -> Result<(), PunchCardError> {
+		if self.powers.first() != Some(&pub_secret) || self.proofs.len() + 1 != self.powers.len() {
+			return Err(PunchCardError::BadProof);
+		}
+		let chain: Vec<_> = self.proofs.iter().enumerate()
+			.map(|(i, proof)| (&self.powers[i], &self.powers[i + 1], proof))
+			.collect();
+		batch_verify(pub_secret, self.merchant, self.epoch, &chain)
+	}
+}
+
+//the standard (non-mergeable) scheme over the Ristretto group
+//N is where the server keeps redeemed card secrets
+#[derive(Debug)]
+pub struct GroupScheme<N = MemoryNullifierStore>(PhantomData<N>);
+
+//redemption message: the card secret and the unmasked card
+#[derive(Debug)]
+pub struct RedeemToken {
+	pub card_secret: [u8; 32],
+	pub card: CompressedRistretto,
+}
+
+impl<N: NullifierStore> PunchCardScheme for GroupScheme<N> {
+	type Store = N;
+	type Server = ServerData<N>;
+	type PublicKey = CompressedRistretto;
+	type Client = PunchCard;
+	type Card = CompressedRistretto;
+	type Proof = Proof;
+	type RedeemToken = RedeemToken;
+	type PowerKey = PowerKey;
+	type Dst = Vec<u8>;
+
+	const NAME: &'static str = "25519 group";
+	const CARDS_PER_REDEMPTION: u32 = 1;
+
+	fn default_dst() -> Vec<u8> {
+		RISTRETTO255_DST.to_vec()
+	}
+
+	fn server_setup_with_dst(merchant: u32, store: N, dst: &Vec<u8>) -> Result<(CompressedRistretto, ServerData<N>), PunchCardError> {
+		Ok(ServerData::server_setup_with_dst(merchant, store, dst))
+	}
+
+	fn export_key(server: &ServerData<N>) -> Result<Vec<u8>, PunchCardError> {
+		Ok(server.export_key())
+	}
+
+	fn import_key(key: &[u8], store: N) -> Result<(CompressedRistretto, ServerData<N>), PunchCardError> {
+		ServerData::import_key(key, store)
+	}
+
+	fn merchant(server: &ServerData<N>) -> u32 {
+		server.merchant()
+	}
+
+	fn set_epoch(server: &mut ServerData<N>, epoch: u32) {
+		server.set_epoch(epoch);
+	}
+
+	fn set_expiry_policy(server: &mut ServerData<N>, policy: Option<ExpiryPolicy>) {
+		server.set_expiry_policy(policy);
+	}
+
+	fn expiry_policy(server: &ServerData<N>) -> Option<ExpiryPolicy> {
+		server.expiry_policy()
+	}
+
+	fn set_card_epoch(client: &mut PunchCard, epoch: u32) {
+		client.set_epoch(epoch);
+	}
+
+	fn card_setup_with_dst(merchant: u32, expiry: Option<u32>, dst: &Vec<u8>) -> Result<(CompressedRistretto, PunchCard), PunchCardError> {
+		Ok(PunchCard::card_setup_with_dst(merchant, expiry, dst))
+	}
+
+	fn server_punch(server: &ServerData<N>, card: &CompressedRistretto) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		server.server_punch(*card)
+	}
+
+	fn verify_remask(client: &mut PunchCard, card: CompressedRistretto, pub_key: &CompressedRistretto,
+					 proof: Proof) -> Result<CompressedRistretto, PunchCardError> {
+		client.verify_remask(card, *pub_key, proof)
+	}
+
+	fn precompute_thresholds(server: &mut ServerData<N>, thresholds: &[u32]) -> Result<(), PunchCardError> {
+		server.precompute_thresholds(thresholds);
+		Ok(())
+	}
+
+	fn publish_powers(server: &ServerData<N>, max_k: u32) -> Result<PowerKey, PunchCardError> {
+		server.power_key(max_k)
+	}
+
+	fn verify_powers(pub_key: &CompressedRistretto, powers: &PowerKey) -> Result<(), PunchCardError> {
+		powers.verify(*pub_key)
+	}
+
+	fn server_punch_by(server: &ServerData<N>, card: &CompressedRistretto, k: u32) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		server.server_punch_by(*card, k)
+	}
+
+	fn verify_remask_by(client: &mut PunchCard, card: CompressedRistretto, powers: &PowerKey, k: u32,
+						proof: Proof) -> Result<CompressedRistretto, PunchCardError> {
+		client.verify_remask_by(card, powers, k, proof)
+	}
+
+	fn batch_verify(pub_key: &CompressedRistretto, merchant: u32, epoch: u32,
+					punches: &[(&CompressedRistretto, &CompressedRistretto, &Proof)]) -> Result<(), PunchCardError> {
+		batch_verify(*pub_key, merchant, epoch, punches)
+	}
+
+	fn get_count(client: &PunchCard) -> Result<u32, PunchCardError> {
+		Ok(client.get_count())
+	}
+
+	fn get_card(client: &PunchCard) -> Result<CompressedRistretto, PunchCardError> {
+		Ok(client.get_card())
+	}
+
+	fn save_client(client: &PunchCard) -> Result<Vec<u8>, PunchCardError> {
+		Ok(client.save_state())
+	}
+
+	fn restore_client(state: &[u8]) -> Result<PunchCard, PunchCardError> {
+		PunchCard::restore_state(state)
+	}
+
+	fn unmask_redeem(mut clients: Vec<PunchCard>) -> Result<RedeemToken, PunchCardError> {
+		let got = clients.len();
+		match (clients.pop(), got) {
+			(Some(mut client), 1) => {
+				let (card_secret, card) = client.unmask_redeem();
+				Ok(RedeemToken {
+					card_secret,
+					card,
+				})
+			},
+			_ => Err(PunchCardError::WrongCardCount { expected: 1, got }),
+		}
+	}
+
+	fn server_verify(server: &mut ServerData<N>, token: RedeemToken, num_punches: u32) -> Result<(), PunchCardError> {
+		server.server_verify(token.card, token.card_secret, num_punches)
+	}
+
+	fn nullifiers(token: &RedeemToken) -> Vec<[u8; 32]> {
+		vec![token.card_secret]
+	}
+
+	fn store_mut(server: &mut ServerData<N>) -> &mut N {
+		&mut server.used_cards
+	}
+
+	fn count_cards(server: &ServerData<N>) -> usize {
+		server.count_cards()
+	}
+
+	fn cheat_setup_db(server: &mut ServerData<N>, num: u32) -> Result<(), PunchCardError> {
+		server.cheat_setup_db(num)
+	}
+}
+
+impl<N: NullifierStore> AuditedScheme for GroupScheme<N> {
+	type Receipt = RedemptionReceipt;
+
+	fn server_verify_audited(server: &mut ServerData<N>, token: RedeemToken, num_punches: u32) -> Result<RedemptionReceipt, AuditedRejection<RedemptionReceipt>> {
+		server.server_verify_audited(token.card, token.card_secret, num_punches)
+	}
+
+	fn audit(receipt: &RedemptionReceipt, powers: &PowerKey) -> Result<(), PunchCardError> {
+		receipt.audit(powers)
+	}
+}
+
+//points and scalars are 32 bytes each
+impl<N: NullifierStore> WireScheme for GroupScheme<N> {
+	const SCHEME_TAG: u8 = TAG_RISTRETTO;
+	const PUBLIC_KEY_LEN: usize = 32;
+	const CARD_LEN: usize = 32;
+	const PROOF_LEN: usize = 96;
+	const REDEEM_TOKEN_LEN: usize = 64;
+
+	fn write_public_key(pub_key: &CompressedRistretto, out: &mut Vec<u8>) {
+		out.extend_from_slice(pub_key.as_bytes());
+	}
+
+	fn read_public_key(bytes: &[u8]) -> Result<CompressedRistretto, PunchCardError> {
+		Ok(CompressedRistretto(read_array32(bytes)?))
+	}
+
+	fn write_card(card: &CompressedRistretto, out: &mut Vec<u8>) {
+		out.extend_from_slice(card.as_bytes());
+	}
+
+	fn read_card(bytes: &[u8]) -> Result<CompressedRistretto, PunchCardError> {
+		Ok(CompressedRistretto(read_array32(bytes)?))
+	}
+
+	//v_t || w_t || beta_z
+	fn write_proof(proof: &Proof, out: &mut Vec<u8>) {
+		out.extend_from_slice(proof.v_t.as_bytes());
+		out.extend_from_slice(proof.w_t.as_bytes());
+		out.extend_from_slice(&proof.beta_z);
+	}
+
+	fn read_proof(bytes: &[u8]) -> Result<Proof, PunchCardError> {
+		if bytes.len() != 96 {
+			return Err(PunchCardError::BadLength { expected: 96, got: bytes.len() });
+		}
+		Ok(Proof {
+			v_t: CompressedRistretto(read_array32(&bytes[0..32])?),
+			w_t: CompressedRistretto(read_array32(&bytes[32..64])?),
+			beta_z: read_array32(&bytes[64..96])?,
+		})
+	}
+
+	//card_secret || card
+	fn write_redeem_token(token: &RedeemToken, out: &mut Vec<u8>) {
+		out.extend_from_slice(&token.card_secret);
+		out.extend_from_slice(token.card.as_bytes());
+	}
+
+	fn read_redeem_token(bytes: &[u8]) -> Result<RedeemToken, PunchCardError> {
+		if bytes.len() != 64 {
+			return Err(PunchCardError::BadLength { expected: 64, got: bytes.len() });
+		}
+		Ok(RedeemToken {
+			card_secret: read_array32(&bytes[0..32])?,
+			card: CompressedRistretto(read_array32(&bytes[32..64])?),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	//a card punched punches times
+	fn punched(server: &ServerData, pub_secret: CompressedRistretto, punches: u32) -> PunchCard {
+		let (mut card, mut client) = PunchCard::card_setup();
+		for _ in 0..punches {
+			let (new_card, proof) = server.server_punch(card).unwrap();
+			card = client.verify_remask(new_card, pub_secret, proof).unwrap();
+		}
+		client
+	}
+
+	#[test]
+	fn server_assisted_merge() {
+		let (pub_secret, mut server) = ServerData::server_setup();
+		let powers = server.power_key(10).unwrap();
+		powers.verify(pub_secret).unwrap();
+
+		let first = punched(&server, pub_secret, 3);
+		let saved = first.save_state();
+		let request = first.unmask_merge(punched(&server, pub_secret, 4));
+		assert_eq!(request.total(), Ok(7));
+		let (fresh_card, mut fresh) = PunchCard::card_setup();
+		let (card, proof) = server.server_merge(request, fresh_card).unwrap();
+		let card = fresh.verify_remask_by(card, &powers, 7, proof).unwrap();
+		assert_eq!(fresh.get_count(), 7);
+		assert_eq!(server.count_cards(), 2);
+
+		//the merged card keeps going and redeems like any other
+		let (new_card, proof) = server.server_punch(card).unwrap();
+		fresh.verify_remask(new_card, pub_secret, proof).unwrap();
+		let (card_secret, card) = fresh.unmask_redeem();
+		server.server_verify(card, card_secret, 8).unwrap();
+
+		//a merged card can't be merged again
+		let request = PunchCard::restore_state(&saved).unwrap().unmask_merge(punched(&server, pub_secret, 1));
+		assert_eq!(server.server_merge(request, PunchCard::card_setup().0).err(), Some(PunchCardError::DoubleRedemption));
+		assert_eq!(server.count_cards(), 3);
+	}
+
+	//two merchants that somehow share a secret still can't take each other's cards
+	#[test]
+	fn merchant_bound() {
+		let secret = Scalar::random(&mut OsRng);
+		let (pub_secret, shop1) = ServerData::from_secret(secret, 1, MemoryNullifierStore::new(), RISTRETTO255_DST);
+		let (_, mut shop2) = ServerData::from_secret(secret, 2, MemoryNullifierStore::new(), RISTRETTO255_DST);
+
+		let (card, mut client) = PunchCard::card_setup_for(1);
+		let (new_card, proof) = shop2.server_punch(card).unwrap();
+		assert_eq!(client.verify_remask(new_card, pub_secret, proof), Err(PunchCardError::BadProof));
+		let (new_card, proof) = shop1.server_punch(card).unwrap();
+		client.verify_remask(new_card, pub_secret, proof).unwrap();
+
+		let (card_secret, card) = client.unmask_redeem();
+		assert_eq!(shop2.server_verify(card, card_secret, 1), Err(PunchCardError::InvalidCard));
+	}
+
+	#[test]
+	fn audited_redemption() {
+		let (pub_secret, mut server) = ServerData::server_setup();
+		let powers = server.power_key(3).unwrap();
+		powers.verify(pub_secret).unwrap();
+		
+		//a card sent with the wrong count is rejected but not used up
+		let (card_secret, card) = punched(&server, pub_secret, 3).unmask_redeem();
+		let mut rejected = rejection(server.server_verify_audited(card, card_secret, 2));
+		assert_eq!(rejected.outcome, Err(PunchCardError::InvalidCard));
+		rejected.audit(&powers).unwrap();
+		assert_eq!(server.count_cards(), 0);
+		
+		let receipt = server.server_verify_audited(card, card_secret, 3).unwrap();
+		assert_eq!(receipt.outcome, Ok(()));
+		receipt.audit(&powers).unwrap();
+		let mut receipt = rejection(server.server_verify_audited(card, card_secret, 3));
+		assert_eq!(receipt.outcome, Err(PunchCardError::DoubleRedemption));
+		receipt.audit(&powers).unwrap();
+		
+		//rejecting a valid card, taking an invalid one, or proving the wrong thing
+		receipt.outcome = Err(PunchCardError::InvalidCard);
+		assert_eq!(receipt.audit(&powers), Err(PunchCardError::DishonestRedemption));
+		rejected.outcome = Ok(());
+		assert_eq!(rejected.audit(&powers), Err(PunchCardError::DishonestRedemption));
+		receipt.evidence = rejected.evidence;
+		assert_eq!(receipt.audit(&powers), Err(PunchCardError::BadProof));
+		
+		let (card_secret, card) = PunchCard::card_setup().1.unmask_redeem();
+		server.server_verify_audited(card, card_secret, 0).unwrap().audit(&powers).unwrap();
+		let (card_secret, card) = PunchCard::card_setup().1.unmask_redeem();
+		let receipt = rejection(server.server_verify_audited(card, card_secret, 4));
+		assert_eq!(receipt.audit(&powers), Err(PunchCardError::InvalidPunchCount(4)));
+	}
+
+	//the receipt of a rejected audited redemption
+	fn rejection(result: Result<RedemptionReceipt, AuditedRejection<RedemptionReceipt>>) -> RedemptionReceipt {
+		let rejection = result.unwrap_err();
+		let receipt = *rejection.receipt.unwrap();
+		assert_eq!(receipt.outcome, Err(rejection.error));
+		receipt
+	}
+
+	//a caller moving from server_verify to server_verify_audited and using ? must not take a rejection for a redemption
+	#[test]
+	fn audited_rejection_is_an_error() {
+		fn redeem(server: &mut ServerData, card: CompressedRistretto, card_secret: [u8; 32], num_punches: u32) -> Result<(), PunchCardError> {
+			server.server_verify_audited(card, card_secret, num_punches)?;
+			Ok(())
+		}
+		let (pub_secret, mut server) = ServerData::server_setup();
+		let (card_secret, card) = punched(&server, pub_secret, 2).unmask_redeem();
+		assert_eq!(redeem(&mut server, card, card_secret, 1), Err(PunchCardError::InvalidCard));
+		assert_eq!(redeem(&mut server, card, card_secret, 2), Ok(()));
+		assert_eq!(redeem(&mut server, card, card_secret, 2), Err(PunchCardError::DoubleRedemption));
+	}
+
+	//asking for an audit of an unpunched card at a high count must not hand back a card valid for that count,
+	//and a forged mismatch proof for a valid card must not verify
+	#[test]
+	fn rejected_receipt_not_redeemable() {
+		let (pub_secret, mut server) = ServerData::server_setup();
+		let (card_secret, card) = PunchCard::card_setup().1.unmask_redeem();
+		let receipt = rejection(server.server_verify_audited(card, card_secret, 10));
+		assert_eq!(receipt.outcome, Err(PunchCardError::InvalidCard));
+		assert!(matches!(receipt.evidence, RedemptionEvidence::Invalid(_)));
+		assert_eq!(server.count_cards(), 0);
+		server.server_verify(card, card_secret, 0).unwrap();
+		
+		let (card_secret, card) = punched(&server, pub_secret, 10).unmask_redeem();
+		let (exponent, pub_power) = server.power(10);
+		let unpunched = card_hash(DEFAULT_MERCHANT, &card_secret, RISTRETTO255_DST);
+		let forged = mismatch_prove(DEFAULT_MERCHANT, DEFAULT_EPOCH, unpunched, card.decompress().unwrap(), exponent, pub_power).unwrap();
+		assert_eq!(mismatch_verify(DEFAULT_MERCHANT, DEFAULT_EPOCH, &pub_power, &unpunched.compress(), &card, &forged),
+				   Err(PunchCardError::BadProof));
+	}
+
+	#[test]
+	fn merge_rejects_bad_requests() {
+		let (pub_secret, mut server) = ServerData::server_setup();
+
+		//claiming more punches than a card holds
+		let mut request = punched(&server, pub_secret, 2).unmask_merge(punched(&server, pub_secret, 2));
+		request.second_count = 3;
+		assert_eq!(server.server_merge(request, PunchCard::card_setup().0).err(), Some(PunchCardError::InvalidCard));
+
+		//one card twice
+		let state = punched(&server, pub_secret, 2).save_state();
+		let request = PunchCard::restore_state(&state).unwrap().unmask_merge(PunchCard::restore_state(&state).unwrap());
+		assert_eq!(server.server_merge(request, PunchCard::card_setup().0).err(), Some(PunchCardError::DoubleRedemption));
+
+		//a bad fresh card doesn't use up the old ones
+		let first = punched(&server, pub_secret, 1);
+		let saved = first.save_state();
+		let request = first.unmask_merge(punched(&server, pub_secret, 1));
+		assert_eq!(server.server_merge(request, CompressedRistretto([0xff; 32])).err(), Some(PunchCardError::MalformedPoint));
+		assert_eq!(server.count_cards(), 0);
+		let (card_secret, card) = PunchCard::restore_state(&saved).unwrap().unmask_redeem();
+		server.server_verify(card, card_secret, 1).unwrap();
+	}
+
+	//audited redemptions, merges and threshold redemptions check the expiry policy like server_verify
+	#[test]
+	fn expiry_checked_everywhere() {
+		let (_, mut server) = ServerData::server_setup();
+		server.set_expiry_policy(Some(ExpiryPolicy::new(5, 2)));
+		let expired = || PunchCard::card_setup_until(DEFAULT_MERCHANT, 4).1;
+
+		let (card_secret, card) = expired().unmask_redeem();
+		assert_eq!(server.server_verify_audited(card, card_secret, 0).map_err(PunchCardError::from).err(), Some(PunchCardError::Expired(4)));
+		let (card_secret, card) = PunchCard::card_setup().1.unmask_redeem();
+		assert_eq!(server.server_verify_audited(card, card_secret, 0).map_err(PunchCardError::from).err(), Some(PunchCardError::NoExpiry));
+		let request = PunchCard::card_setup_until(DEFAULT_MERCHANT, 5).1.unmask_merge(expired());
+		assert_eq!(server.server_merge(request, PunchCard::card_setup().0).err(), Some(PunchCardError::Expired(4)));
+		assert_eq!(server.count_cards(), 0);
+
+		let (mut combiner, signers) = server.into_threshold(1, 1).unwrap();
+		let (card_secret, card) = expired().unmask_redeem();
+		assert_eq!(combiner.server_verify(card, card_secret, 0, |c| Ok(vec![signers[0].partial_card(c)?])),
+				   Err(PunchCardError::Expired(4)));
+		assert_eq!(combiner.count_cards(), 0);
+	}
+}
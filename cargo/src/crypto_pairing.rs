@@ -1,40 +1,85 @@
 //use sha2::Sha512;
-use sha2::Sha256;
 use rand_core::{RngCore, OsRng};
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::marker::PhantomData;
 use curve25519_dalek::scalar::Scalar;
 use ff_zeroize::Field;
 use ff_zeroize::PrimeField;
 use pairing_plus::Engine;
-use pairing_plus::hash_to_field::ExpandMsgXmd;
 use pairing_plus::CurveProjective;
+use pairing_plus::CurveAffine;
 use pairing_plus::serdes::SerDes;
-use pairing_plus::hash_to_field::hash_to_field;
-use pairing_plus::hash_to_curve::HashToCurve;
 use pairing_plus::bls12_381::Bls12;
 use pairing_plus::bls12_381::Fr;
 use pairing_plus::bls12_381::G1;
 use pairing_plus::bls12_381::G2;
 use pairing_plus::bls12_381::Fq12;
+use super::scheme::{PunchCardScheme, AuditedScheme, check_outcome};
+use super::error::{PunchCardError, AuditedRejection};
+use super::wire::{WireScheme, TAG_BLS12_381, read_array32};
+use super::nullifier::{NullifierStore, MemoryNullifierStore};
+use super::keyfile::{encode_key, decode_key, write_dst, read_dsts};
+use super::wallet::{encode_card_state, decode_card_state};
+use super::expiry::{secret_until, check_expiry, ExpiryPolicy};
+use super::merchant::{card_message, DEFAULT_MERCHANT};
+use super::transcript::Transcript;
+use super::epoch::DEFAULT_EPOCH;
+use super::hash_to_curve::{hash_to_bls12_381, hash_to_fr, HashToBls12381, BLS12381G1_DST, BLS12381G2_DST};
 
 
-//Making a second module with all the same functions used in crypto.rs 
-//but for the version that uses pairings to merge 2 cards
-//Not the right way to do this, but it will do for now
+//Same scheme as crypto.rs but for the version that uses pairings to merge 2 cards
+//PairingScheme at the bottom exposes it through the common PunchCardScheme trait
+
+//how the server checks a redeemed card
+//the client sends both pairings, card 1's G1 part with card 2's G2 part and card 2's G1 part with card 1's G2 part
+//Both (the default) checks each pairing on its own, so neither card can have G1 and G2 halves that disagree
+//Product checks the product of the two pairings, which costs one final exponentiation instead of two
+//but only binds the halves together as a product
+//Single only checks the first, as in the paper, so the other half of each card is never looked at
+//and a client who gets card 1's G1 half punched together with card 2's G2 half has every punch counted twice;
+//it's only there to reproduce the paper's benchmarks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairCheck {
+    Single,
+    Both,
+    Product,
+}
+
+//written out rather than derived with #[default], which needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for PairCheck {
+    fn default() -> PairCheck {
+        PairCheck::Both
+    }
+}
 
 #[derive(Debug)]
-pub struct PairServerData {
+pub struct PairServerData<N: NullifierStore = MemoryNullifierStore> {
 	secret: Fr,
-	used_cards: HashSet<[u8; 32]>,
+	merchant: u32,
+	epoch: u32,
+	used_cards: N,
+	check: PairCheck,
+	//which card secrets are taken, None takes any (see expiry.rs)
+	expiry: Option<ExpiryPolicy>,
 	pub pub_secret_g1: Vec<u8>, //compressed form of g1^secret
 	pub pub_secret_g2: Vec<u8>, //compressed form of g2^secret
+	//secret^n and compressed g1^(secret^n), g2^(secret^n) for the thresholds given to pair_precompute_thresholds
+	powers: HashMap<u32, (Fr, Vec<u8>, Vec<u8>)>,
+	//DSTs the G1 and G2 card parts are hashed with (see hash_to_curve.rs)
+	dst_g1: Vec<u8>,
+	dst_g2: Vec<u8>,
 }
 
 //this holds the two parts of one punch card
 #[derive(Debug)]
 pub struct PairPunchCard {
+    merchant: u32,
+    epoch: u32,
     g1card: PairPunchCardPart<G1>,
     g2card: PairPunchCardPart<G2>,
+    dst_g1: Vec<u8>,
+    dst_g2: Vec<u8>,
 }
 
 //This is one punch card part
@@ -46,6 +91,32 @@ pub struct PairPunchCardPart<T> {
 	count: u32,
 }
 
+//redemption message: both card secrets, the merged card (both pairings), and how many punches it holds
+//the two cards don't need the same number of punches, the merged card carries x^(count1 + count2) either way
+#[derive(Debug)]
+pub struct PairRedeemToken {
+    pub card_secret1: [u8; 32],
+    pub card_secret2: [u8; 32],
+    pub card: Vec<u8>,
+    pub card2: Vec<u8>,
+    pub num_punches: u32,
+}
+
+//redemption message for merging any number of cards
+//the cards are merged two at a time in the order given: the G1 part of card 2j is paired with the G2 part of card 2j+1,
+//and with an odd number of cards the last one is paired with itself
+//cards2 holds the other pairing of each pair, the G1 part of card 2j+1 with the G2 part of card 2j, for the
+//Both and Product checks (a card paired with itself has both of its halves in its one pairing already)
+//counts holds how many punches each pair carries, so the server learns how the punches are split between
+//pairs, but not between the two cards of a pair
+#[derive(Debug)]
+pub struct PairMergeToken {
+    pub card_secrets: Vec<[u8; 32]>,
+    pub cards: Vec<Vec<u8>>,
+    pub cards2: Vec<Vec<u8>>,
+    pub counts: Vec<u32>,
+}
+
 //we'll use two proofs, one for the exponentiation in each group
 //notation from Figure 19.7 in Boneh-Shoup textbook v0.5
 #[derive(Debug)]
@@ -55,93 +126,553 @@ pub struct PairProof {
 	beta_z: Vec<u8>,//compressed point in Fr
 }
 
+//the server's evidence for an audited redemption decision (see PairServerData::pair_server_verify_audited)
+//anyone with a checked PairPowerKey can tell from it whether outcome was honest
+#[derive(Debug)]
+pub struct PairRedemptionReceipt {
+    pub token: PairRedeemToken,
+    pub num_punches: u32,
+    pub evidence: PairRedemptionEvidence,
+    pub outcome: Result<(), PunchCardError>,
+}
+
+//Valid holds H1(card_secret1)^(x^num_punches) and H1(card_secret2)^(x^num_punches), each with a proof against the
+//published g1^(x^num_punches), whose pairings with H2(card_secret2) and H2(card_secret1) are the token's card and card2
+//Invalid proves that one of the two pairings (0 for card, 1 for card2) isn't what it should be, without giving
+//out the one that would have been valid, so the token's cards aren't used up
+#[derive(Debug)]
+pub enum PairRedemptionEvidence {
+    Valid(Vec<(Vec<u8>, PairProof)>),
+    Invalid(usize, PairMismatchProof),
+}
+
+//proof that card != base^x where base = e(H1(secret_a), H2(secret_b)) and pub_power = g1^x
+//the MismatchProof of crypto.rs with diff and t_diff in the target group and t_one in G1
+#[derive(Debug)]
+pub struct PairMismatchProof {
+    diff: Vec<u8>,
+    t_diff: Vec<u8>,
+    t_one: Vec<u8>,
+    z_a: Vec<u8>,
+    z_r: Vec<u8>,
+}
+
+//g^(secret^k) in G1 and G2 for k = 1 to powers_g1.len(), so a client can check a punch by k
+//proofs_g1[i] shows powers_g1[i+1] = powers_g1[i]^secret, it's the same proof as a punch of that "card" (same for G2)
+#[derive(Debug)]
+pub struct PairPowerKey {
+    merchant: u32,
+    epoch: u32,
+    powers_g1: Vec<Vec<u8>>,
+    powers_g2: Vec<Vec<u8>>,
+    proofs_g1: Vec<PairProof>,
+    proofs_g2: Vec<PairProof>,
+    dst_g1: Vec<u8>,
+    dst_g2: Vec<u8>,
+}
+
+//one Chaum-Pedersen statement and proof in G1 or G2, decoded and with the challenge recomputed
+//u is the card part before the punch and c the punched part, so c = u^secret
+struct PairDleq<T> {
+    u: T,
+    c: T,
+    v_t: T,
+    w_t: T,
+    beta_z: Fr,
+    chal: Fr,
+}
+
+//punches to check in one batch, as (card sent, punched card returned, proof) in both groups
+pub type PairPunchRecord<'a> = (&'a (Vec<u8>, Vec<u8>), &'a (Vec<u8>, Vec<u8>), &'a (PairProof, PairProof));
+
+//compressed sizes of the elements that go over the wire
+const G1_LEN: usize = 48;
+const G2_LEN: usize = 96;
+const FR_LEN: usize = 32;
+const FQ12_LEN: usize = 576;
+//length of a saved PairPunchCard body
+const CARD_STATE_LEN: usize = 4 + 4 + 32 + 4 + (G1_LEN + FR_LEN) + (G2_LEN + FR_LEN);
+
+//which proof a punch transcript is for, one for each group
+const PUNCH_PROOF_G1: &[u8] = b"bls12-381 g1 punch";
+const PUNCH_PROOF_G2: &[u8] = b"bls12-381 g2 punch";
+//which proof a mismatch transcript is for
+const MISMATCH_PROOF: &[u8] = b"bls12-381 mismatch";
+//hash_to_field dst for turning a transcript challenge into a scalar
+const CHALLENGE_DST: &[u8] = b"PunchCard-BLS12381-challenge";
+
+//compress a group element (or Fr/Fq12 element)
+fn serialize_elem<T: SerDes>(elem: &T) -> Result<Vec<u8>, PunchCardError> {
+    let mut compressed = Vec::<u8>::new();
+    elem.serialize(&mut compressed, true).map_err(|_| PunchCardError::Serialization)?;
+    Ok(compressed)
+}
+
+//decompress a group element received from the other party
+fn deserialize_point<T: SerDes>(compressed: &[u8]) -> Result<T, PunchCardError> {
+    T::deserialize(&mut &compressed[..], true).map_err(|_| PunchCardError::MalformedPoint)
+}
+
+//the unpunched card part for card_secret at merchant, dst defaults to BLS12381G1_DST for G1 and BLS12381G2_DST for G2
+fn card_hash<T: HashToBls12381>(merchant: u32, card_secret: &[u8; 32], dst: &[u8]) -> T {
+    hash_to_bls12_381::<T>(&card_message(merchant, card_secret), dst)
+}
+
+//the transcripts every punch proof for merchant in epoch starts from, one for each group
+fn punch_transcripts(merchant: u32, epoch: u32) -> (Transcript, Transcript) {
+    (Transcript::new(PUNCH_PROOF_G1, merchant, epoch), Transcript::new(PUNCH_PROOF_G2, merchant, epoch))
+}
+
+//Fiat-Shamir challenge for a punch, base is the punch transcript for the card part's group
+fn pair_dleq_challenge(base: &Transcript, pub_secret: &[u8], compressed_card: &[u8], new_compressed_card: &[u8], v_t: &[u8], w_t: &[u8]) -> Fr {
+		let mut transcript = base.clone();
+		transcript.append_message(b"pub_secret", pub_secret);
+		transcript.append_message(b"card", compressed_card);
+		transcript.append_message(b"new_card", new_compressed_card);
+		transcript.append_message(b"v_t", v_t);
+		transcript.append_message(b"w_t", w_t);
+		hash_to_fr(&transcript.challenge_bytes(b"chal")[..], CHALLENGE_DST)
+}
+
+impl<T> PairDleq<T>
+    where T: CurveProjective + SerDes,
+    <<T as pairing_plus::CurveProjective>::Scalar as ff_zeroize::PrimeField>::Repr: std::convert::From<pairing_plus::bls12_381::Fr>
+{
+
+    fn decode(base: &Transcript, pub_secret: &[u8], u: T, new_compressed_card: &[u8], proof: &PairProof) -> Result<PairDleq<T>, PunchCardError> {
+        //serialize the punch card so it can be hashed
+        let compressed_card = serialize_elem(&u)?;
+        let chal = pair_dleq_challenge(base, pub_secret, &compressed_card, new_compressed_card, &proof.v_t, &proof.w_t);
+        Ok(PairDleq {
+            u,
+            c: deserialize_point::<T>(new_compressed_card)?,
+            v_t: deserialize_point::<T>(&proof.v_t)?,
+            w_t: deserialize_point::<T>(&proof.w_t)?,
+            beta_z: Fr::deserialize(&mut &proof.beta_z[..], true).map_err(|_| PunchCardError::MalformedScalar)?,
+            chal,
+        })
+    }
+
+    //see Boneh Shoup textbook v0.5 Figure 19.7
+    fn check(&self, pub_secret: &T) -> bool {
+		let mut gbz = T::one();
+		gbz.mul_assign(self.beta_z);
+        
+        let mut vtvc = self.v_t;
+        let mut part = *pub_secret;
+        part.mul_assign(self.chal);
+        vtvc.add_assign(&part);
+        
+        let mut ubz = self.u;
+        ubz.mul_assign(self.beta_z);
+        
+        let mut wtwc = self.w_t;
+        let mut part = self.c;
+        part.mul_assign(self.chal);
+        wtwc.add_assign(&part);
+        
+        gbz == vtvc && ubz == wtwc
+    }
+}
+
+//whether elem is in the order r subgroup of Fq12 that pairings land in
+//the mismatch proof only holds up in a group of prime order, so anything else sent as a card is refused
+fn in_target_group(elem: &Fq12) -> bool {
+    elem.pow(Fr::char()) == Fq12::one()
+}
+
+//base^a / card^r in the target group
+fn gt_combine(base: &Fq12, a: Fr, card: &Fq12, r: Fr) -> Fq12 {
+    let mut neg_r = r;
+    neg_r.negate();
+    let mut out = base.pow(a.into_repr());
+    out.mul_assign(&card.pow(neg_r.into_repr()));
+    out
+}
+
+//g1^a / pub_power^r
+fn g1_combine(a: Fr, pub_power: &G1, r: Fr) -> G1 {
+    let mut out = G1::one();
+    out.mul_assign(a);
+    let mut part = *pub_power;
+    part.mul_assign(r);
+    out.sub_assign(&part);
+    out
+}
+
+//the transcript for a PairMismatchProof that card != base^x, where pub_power = g1^x
+fn pair_mismatch_transcript(merchant: u32, epoch: u32, pub_power: &[u8], base: &Fq12, card: &Fq12) -> Result<Transcript, PunchCardError> {
+    let mut transcript = Transcript::new(MISMATCH_PROOF, merchant, epoch);
+    transcript.append_message(b"pub_power", pub_power);
+    transcript.append_message(b"base", &serialize_elem(base)?);
+    transcript.append_message(b"card", &serialize_elem(card)?);
+    Ok(transcript)
+}
+
+//Fiat-Shamir challenge for a PairMismatchProof, from pair_mismatch_transcript
+fn pair_mismatch_challenge(mut transcript: Transcript, diff: &[u8], t_diff: &[u8], t_one: &[u8]) -> Fr {
+    transcript.append_message(b"diff", diff);
+    transcript.append_message(b"t_diff", t_diff);
+    transcript.append_message(b"t_one", t_one);
+    hash_to_fr(&transcript.challenge_bytes(b"chal")[..], CHALLENGE_DST)
+}
+
+//prove that card != base^exponent where pub_power = g1^exponent, the caller checks that it isn't
+//and that card is in the target group
+fn pair_mismatch_prove(merchant: u32, epoch: u32, base: &Fq12, card: &Fq12, exponent: Fr, pub_power: &[u8]) -> Result<PairMismatchProof, PunchCardError> {
+    let pub_power_dec = deserialize_point::<G1>(pub_power)?;
+    let r = Fr::random(&mut OsRng);
+    let mut a = exponent;
+    a.mul_assign(&r);
+    let diff = serialize_elem(&gt_combine(base, a, card, r))?;
+    
+    let t_a = Fr::random(&mut OsRng);
+    let t_r = Fr::random(&mut OsRng);
+    let t_diff = serialize_elem(&gt_combine(base, t_a, card, t_r))?;
+    let t_one = serialize_elem(&g1_combine(t_a, &pub_power_dec, t_r))?;
+    
+    let transcript = pair_mismatch_transcript(merchant, epoch, pub_power, base, card)?;
+    let chal = pair_mismatch_challenge(transcript, &diff, &t_diff, &t_one);
+    //z = t + chal * witness
+    let z = |t: Fr, witness: Fr| {
+        let mut z = chal;
+        z.mul_assign(&witness);
+        z.add_assign(&t);
+        serialize_elem(&z)
+    };
+    Ok(PairMismatchProof {
+        z_a: z(t_a, a)?,
+        z_r: z(t_r, r)?,
+        diff,
+        t_diff,
+        t_one,
+    })
+}
+
+//check a PairMismatchProof that card != base^x where pub_power = g1^x, BadProof if it doesn't verify
+fn pair_mismatch_verify(merchant: u32, epoch: u32, pub_power: &[u8], base: &Fq12, card: &Fq12, proof: &PairMismatchProof) -> Result<(), PunchCardError> {
+    let pub_power_dec = deserialize_point::<G1>(pub_power)?;
+    let diff = deserialize_point::<Fq12>(&proof.diff)?;
+    let t_diff = deserialize_point::<Fq12>(&proof.t_diff)?;
+    let t_one = deserialize_point::<G1>(&proof.t_one)?;
+    let z_a = Fr::deserialize(&mut &proof.z_a[..], true).map_err(|_| PunchCardError::MalformedScalar)?;
+    let z_r = Fr::deserialize(&mut &proof.z_r[..], true).map_err(|_| PunchCardError::MalformedScalar)?;
+    if ![card, &diff, &t_diff].iter().all(|elem| in_target_group(elem)) || diff == Fq12::one() {
+        return Err(PunchCardError::BadProof);
+    }
+    let transcript = pair_mismatch_transcript(merchant, epoch, pub_power, base, card)?;
+    let chal = pair_mismatch_challenge(transcript, &proof.diff, &proof.t_diff, &proof.t_one);
+    
+    //base^z_a / card^z_r = t_diff * diff^chal and g1^z_a / pub_power^z_r = t_one
+    let mut rhs = diff.pow(chal.into_repr());
+    rhs.mul_assign(&t_diff);
+    if gt_combine(base, z_a, card, z_r) != rhs || g1_combine(z_a, &pub_power_dec, z_r) != t_one {
+        return Err(PunchCardError::BadProof);
+    }
+    Ok(())
+}
+
+//the unpunched halves of the two pairings a redeemed token holds, (H1(secret1), H2(secret2)) for card and
+//(H1(secret2), H2(secret1)) for card2
+fn redemption_halves(merchant: u32, secret1: &[u8; 32], secret2: &[u8; 32], dst_g1: &[u8], dst_g2: &[u8]) -> [(G1, G2); 2] {
+    let halves = |secret_a, secret_b| (card_hash::<G1>(merchant, secret_a, dst_g1), card_hash::<G2>(merchant, secret_b, dst_g2));
+    [halves(secret1, secret2), halves(secret2, secret1)]
+}
+
+//batch check the proofs for one group, see batch_verify in crypto.rs for how the batch is combined
+//returns the position of the first proof that doesn't decode or verify, None if they all do
+fn pair_batch_verify_part<T>(pub_secret: &[u8], base: &Transcript, punches: &[(&[u8], &[u8], &PairProof)]) -> Result<Option<usize>, PunchCardError>
+    where T: CurveProjective<Scalar = Fr> + SerDes,
+    <<T as pairing_plus::CurveProjective>::Scalar as ff_zeroize::PrimeField>::Repr: std::convert::From<pairing_plus::bls12_381::Fr>
+{
+    let pub_secret_dec = deserialize_point::<T>(pub_secret)?;
+    let mut proofs = Vec::with_capacity(punches.len());
+    let mut undecodable = None;
+    for (i, (card, new_card, proof)) in punches.iter().enumerate() {
+        match deserialize_point::<T>(card).and_then(|u| PairDleq::decode(base, pub_secret, u, new_card, proof)) {
+            Ok(decoded) => proofs.push(decoded),
+            Err(_) => {
+                undecodable = Some(i);
+                break;
+            },
+        }
+    }
+    
+    if undecodable.is_none() {
+        //g^(sum rho*z) - pub_secret^(sum rho*chal) + sum (-rho*v_t + sigma*z*u - sigma*w_t - sigma*chal*c)
+        let mut scalars = Vec::with_capacity(2 + 4*proofs.len());
+        let mut points = Vec::with_capacity(2 + 4*proofs.len());
+        let mut g_scalar = Fr::zero();
+        let mut pub_scalar = Fr::zero();
+        for proof in &proofs {
+            let rho = Fr::random(&mut OsRng);
+            let sigma = Fr::random(&mut OsRng);
+            
+            let mut term = rho;
+            term.mul_assign(&proof.beta_z);
+            g_scalar.add_assign(&term);
+            let mut term = rho;
+            term.mul_assign(&proof.chal);
+            pub_scalar.sub_assign(&term);
+            
+            let mut neg_rho = rho;
+            neg_rho.negate();
+            let mut sigma_z = sigma;
+            sigma_z.mul_assign(&proof.beta_z);
+            let mut neg_sigma = sigma;
+            neg_sigma.negate();
+            let mut neg_sigma_chal = neg_sigma;
+            neg_sigma_chal.mul_assign(&proof.chal);
+            
+            scalars.extend_from_slice(&[neg_rho.into_repr().0, sigma_z.into_repr().0, neg_sigma.into_repr().0, neg_sigma_chal.into_repr().0]);
+            points.extend_from_slice(&[proof.v_t.into_affine(), proof.u.into_affine(), proof.w_t.into_affine(), proof.c.into_affine()]);
+        }
+        scalars.push(g_scalar.into_repr().0);
+        points.push(T::one().into_affine());
+        scalars.push(pub_scalar.into_repr().0);
+        points.push(pub_secret_dec.into_affine());
+        
+        let scalar_refs: Vec<&[u64; 4]> = scalars.iter().collect();
+        if T::Affine::sum_of_products(&points, &scalar_refs).is_zero() {
+            return Ok(None);
+        }
+    }
+    
+    //find the culprit, if all the decoded ones pass then it's the one that didn't decode
+    match proofs.iter().position(|proof| !proof.check(&pub_secret_dec)).or(undecodable) {
+        Some(i) => Ok(Some(i)),
+        None => Err(PunchCardError::BadProof),
+    }
+}
+
+//check many punches made by merchant in epoch against the public keys at once
+//both groups are batched separately, and the position of the first punch with a bad proof in either is reported
+pub fn pair_batch_verify(pub_secret_g1: &[u8], pub_secret_g2: &[u8], merchant: u32, epoch: u32,
+                         punches: &[PairPunchRecord]) -> Result<(), PunchCardError> {
+    let (base1, base2) = punch_transcripts(merchant, epoch);
+    let g1_punches: Vec<_> = punches.iter().map(|(card, new_card, proof)| (&card.0[..], &new_card.0[..], &proof.0)).collect();
+    let g2_punches: Vec<_> = punches.iter().map(|(card, new_card, proof)| (&card.1[..], &new_card.1[..], &proof.1)).collect();
+    
+    let bad1 = pair_batch_verify_part::<G1>(pub_secret_g1, &base1, &g1_punches)?;
+    let bad2 = pair_batch_verify_part::<G2>(pub_secret_g2, &base2, &g2_punches)?;
+    match bad1.into_iter().chain(bad2).min() {
+        Some(i) => Err(PunchCardError::BadProofAt(i)),
+        None => Ok(()),
+    }
+}
+
 impl PairServerData {
 
-	//set up the server secret and redeemed card 
-    pub fn pair_server_setup() -> PairServerData{
-        let secret = Fr::random(&mut OsRng);
-		let used_cards = HashSet::new();
-        let mut pub_secret_g1 = Vec::<u8>::new();
-        let mut pub_secret_g2 = Vec::<u8>::new();
+	//set up the server secret with an in-memory redeemed card db
+    pub fn pair_server_setup() -> Result<PairServerData, PunchCardError> {
+        PairServerData::pair_server_setup_with_store(MemoryNullifierStore::new())
+    }
+}
+
+impl<N: NullifierStore> PairServerData<N> {
+
+	//set up the server secret, recording redeemed cards in used_cards
+    pub fn pair_server_setup_with_store(used_cards: N) -> Result<PairServerData<N>, PunchCardError> {
+        Self::pair_server_setup_for(DEFAULT_MERCHANT, used_cards)
+    }
+    
+	//set up the secret for merchant (see merchant.rs)
+    pub fn pair_server_setup_for(merchant: u32, used_cards: N) -> Result<PairServerData<N>, PunchCardError> {
+        Self::pair_server_setup_with_dst(merchant, used_cards, BLS12381G1_DST, BLS12381G2_DST)
+    }
+    
+	//set up the secret for merchant, hashing card parts with dst_g1 and dst_g2 instead of BLS12381G1_DST and BLS12381G2_DST
+	//only cards set up with the same DSTs (PairPunchCard::card_setup_with_dst) redeem here
+    pub fn pair_server_setup_with_dst(merchant: u32, used_cards: N, dst_g1: &[u8], dst_g2: &[u8]) -> Result<PairServerData<N>, PunchCardError> {
+        Self::pair_from_secret(Fr::random(&mut OsRng), merchant, used_cards, dst_g1, dst_g2)
+    }
+    
+    fn pair_from_secret(secret: Fr, merchant: u32, used_cards: N, dst_g1: &[u8], dst_g2: &[u8]) -> Result<PairServerData<N>, PunchCardError> {
         let mut temp = G1::one();
         temp.mul_assign(secret);
-        temp.serialize(&mut pub_secret_g1, true).expect("couldn't serialize");
+        let pub_secret_g1 = serialize_elem(&temp)?;
         let mut temp = G2::one();
         temp.mul_assign(secret);
-        temp.serialize(&mut pub_secret_g2, true).expect("couldn't serialize");
-        let new_server = PairServerData {
+        let pub_secret_g2 = serialize_elem(&temp)?;
+        Ok(PairServerData {
             secret,
+            merchant,
+            epoch: DEFAULT_EPOCH,
             used_cards,
             pub_secret_g1,
             pub_secret_g2,
-        };
-        new_server
+            powers: HashMap::new(),
+            check: PairCheck::default(),
+            expiry: None,
+            dst_g1: dst_g1.to_vec(),
+            dst_g2: dst_g2.to_vec(),
+        })
+    }
+    
+    //redemptions usually happen at a few fixed punch counts, so secret^n (and the public powers for punches by n)
+    //can be computed once for each of them instead of on every redemption
+    pub fn pair_precompute_thresholds(&mut self, thresholds: &[u32]) -> Result<(), PunchCardError> {
+        for n in thresholds {
+            let power = self.pair_secret_power(*n)?;
+            self.powers.insert(*n, power);
+        }
+        Ok(())
+    }
+    
+    //secret^n, g1^(secret^n) and g2^(secret^n), from the precomputed thresholds if n is one of them
+    fn pair_secret_power(&self, n: u32) -> Result<(Fr, Vec<u8>, Vec<u8>), PunchCardError> {
+        if let Some(power) = self.powers.get(&n) {
+            return Ok(power.clone());
+        }
+        let exponent = self.secret.pow([n as u64]);
+        let mut pub_power_g1 = G1::one();
+        pub_power_g1.mul_assign(exponent);
+        let mut pub_power_g2 = G2::one();
+        pub_power_g2.mul_assign(exponent);
+        Ok((exponent, serialize_elem(&pub_power_g1)?, serialize_elem(&pub_power_g2)?))
+    }
+    
+    pub fn pair_merchant(&self) -> u32 {
+        self.merchant
+    }
+    
+    pub fn pair_epoch(&self) -> u32 {
+        self.epoch
+    }
+    
+    //the DSTs card parts are hashed with in G1 and G2
+    pub fn pair_dst(&self) -> (&[u8], &[u8]) {
+        (&self.dst_g1, &self.dst_g2)
+    }
+    
+    //bind the proofs from now on to epoch (see epoch.rs), clients have to check them in the same epoch
+    pub fn pair_set_epoch(&mut self, epoch: u32) {
+        self.epoch = epoch;
+    }
+    
+    //key body is secret || merchant (u32 little endian) || epoch (u32 little endian) || pub_secret_g1 || pub_secret_g2
+    //|| dst_g1 || dst_g2, see keyfile.rs for the header and how the DSTs are written
+    pub fn pair_export_key(&self) -> Result<Vec<u8>, PunchCardError> {
+        let mut body = serialize_elem(&self.secret)?;
+        body.extend_from_slice(&self.merchant.to_le_bytes());
+        body.extend_from_slice(&self.epoch.to_le_bytes());
+        body.extend_from_slice(&self.pub_secret_g1);
+        body.extend_from_slice(&self.pub_secret_g2);
+        write_dst(&self.dst_g1, &mut body);
+        write_dst(&self.dst_g2, &mut body);
+        Ok(encode_key(TAG_BLS12_381, &body))
+    }
+    
+    //reload a server exported with pair_export_key
+    //fails with CorruptStore if the public keys don't belong to the secret
+    pub fn pair_import_key(key: &[u8], used_cards: N) -> Result<PairServerData<N>, PunchCardError> {
+        let (body, rest) = decode_key(TAG_BLS12_381, key, FR_LEN + 4 + 4 + G1_LEN + G2_LEN)?;
+        let dsts = read_dsts(rest, 2)?;
+        let fields = split_fields(body, &[FR_LEN, 4, 4, G1_LEN, G2_LEN])?;
+        let secret = Fr::deserialize(&mut &fields[0][..], true).map_err(|_| PunchCardError::MalformedScalar)?;
+        let merchant = u32::from_le_bytes([fields[1][0], fields[1][1], fields[1][2], fields[1][3]]);
+        let epoch = u32::from_le_bytes([fields[2][0], fields[2][1], fields[2][2], fields[2][3]]);
+        
+        let mut server = Self::pair_from_secret(secret, merchant, used_cards, &dsts[0], &dsts[1])?;
+        if server.pub_secret_g1[..] != *fields[3] || server.pub_secret_g2[..] != *fields[4] {
+            return Err(PunchCardError::CorruptStore);
+        }
+        server.pair_set_epoch(epoch);
+        Ok(server)
+    }
+    
+    pub fn pair_server_punch(&self, compressed_card1: &[u8], compressed_card2: &[u8]) -> Result<(Vec<u8>, Vec<u8>, PairProof, PairProof), PunchCardError> {
+            let (base1, base2) = punch_transcripts(self.merchant, self.epoch);
+            
+            let (card1, proof1) = self.pair_server_punch_part::<G1>(compressed_card1, self.secret, &self.pub_secret_g1, &base1)?;
+            let (card2, proof2) = self.pair_server_punch_part::<G2>(compressed_card2, self.secret, &self.pub_secret_g2, &base2)?;
+            
+            Ok((card1, card2, proof1, proof2))
     }
     
-    pub fn pair_server_punch(&self, compressed_card1: &mut Vec<u8>, compressed_card2: &mut Vec<u8>) -> (Vec<u8>, Vec<u8>, PairProof, PairProof)  {
-            let dst1 = [3u8, 0u8, 0u8, 0u8];
-            let dst2 = [4u8, 0u8, 0u8, 0u8];
+    //punch both parts k times at once by multiplying by secret^k
+    //the proofs are against g1^(secret^k) and g2^(secret^k), which clients get from a PairPowerKey
+    pub fn pair_server_punch_by(&self, compressed_card1: &[u8], compressed_card2: &[u8], k: u32) -> Result<(Vec<u8>, Vec<u8>, PairProof, PairProof), PunchCardError> {
+            if k == 0 {
+                return Err(PunchCardError::InvalidPunchCount(k));
+            }
+            let (base1, base2) = punch_transcripts(self.merchant, self.epoch);
+            let (exponent, pub_power_g1, pub_power_g2) = self.pair_secret_power(k)?;
             
-            let (card1, proof1) = self.pair_server_punch_part::<G1>(compressed_card1, dst1);
-            let (card2, proof2) = self.pair_server_punch_part::<G2>(compressed_card2, dst2);
+            let (card1, proof1) = self.pair_server_punch_part::<G1>(compressed_card1, exponent, &pub_power_g1, &base1)?;
+            let (card2, proof2) = self.pair_server_punch_part::<G2>(compressed_card2, exponent, &pub_power_g2, &base2)?;
             
-            (card1, card2, proof1, proof2)
+            Ok((card1, card2, proof1, proof2))
+    }
+    
+    //publish the powers needed to check punches of up to max_k at a time
+    pub fn pair_power_key(&self, max_k: u32) -> Result<PairPowerKey, PunchCardError> {
+        if max_k == 0 {
+            return Err(PunchCardError::InvalidPunchCount(max_k));
+        }
+        let (base1, base2) = punch_transcripts(self.merchant, self.epoch);
+        let (powers_g1, proofs_g1) = self.pair_power_chain::<G1>(&self.pub_secret_g1, max_k, &base1)?;
+        let (powers_g2, proofs_g2) = self.pair_power_chain::<G2>(&self.pub_secret_g2, max_k, &base2)?;
+        Ok(PairPowerKey {
+            merchant: self.merchant,
+            epoch: self.epoch,
+            powers_g1,
+            powers_g2,
+            proofs_g1,
+            proofs_g2,
+            dst_g1: self.dst_g1.clone(),
+            dst_g2: self.dst_g2.clone(),
+        })
+    }
+    
+    //pub_secret, pub_secret^secret, ... with a punch proof for each step
+    fn pair_power_chain<T>(&self, pub_secret: &[u8], max_k: u32, base: &Transcript) -> Result<(Vec<Vec<u8>>, Vec<PairProof>), PunchCardError>
+        where T: CurveProjective + SerDes,
+        <<T as pairing_plus::CurveProjective>::Scalar as ff_zeroize::PrimeField>::Repr: std::convert::From<pairing_plus::bls12_381::Fr>
+    {
+        let mut powers = vec![pub_secret.to_vec()];
+        let mut proofs = Vec::new();
+        for i in 1..max_k as usize {
+            let (next, proof) = self.pair_server_punch_part::<T>(&powers[i - 1], self.secret, pub_secret, base)?;
+            powers.push(next);
+            proofs.push(proof);
+        }
+        Ok((powers, proofs))
     }
     
     //this will have to be called twice, once for each piece of the card
-    //dst is also 3,0,0,0 the first time and 4,0,0,0 the second time
-    //punch card by multiplying by secret
-	//prove that this was done honestly
-    fn pair_server_punch_part<T>(&self, compressed_card: &mut Vec<u8>, dst: [u8; 4]) -> (Vec<u8>, PairProof) 
+    //base is the G1 punch transcript with pub_secret_g1 and the G2 one with pub_secret_g2
+    //punch card by multiplying by exponent (the secret, or a power of it for multi-punches)
+	//prove that this was done honestly against pub_secret = g^exponent
+    fn pair_server_punch_part<T>(&self, compressed_card: &[u8], exponent: Fr, pub_secret: &[u8], base: &Transcript) -> Result<(Vec<u8>, PairProof), PunchCardError>
         where T: CurveProjective + SerDes,
         <<T as pairing_plus::CurveProjective>::Scalar as ff_zeroize::PrimeField>::Repr: std::convert::From<pairing_plus::bls12_381::Fr>
     { 
     
-        let pub_secret: &[u8];
-        if dst[0] == 3 {
-            pub_secret = &self.pub_secret_g1;
-        } else if dst[0] == 4 {
-            pub_secret = &self.pub_secret_g2;
-        } else {
-            panic!("bad dst");
-        }
-    
         //deserialize the card given as parameter
-        let card = T::deserialize(&mut &compressed_card[..], true).expect("couldn't deserialize");
-        let mut new_card = card.clone();
-        new_card.mul_assign(self.secret);
-        let mut new_compressed_card = Vec::<u8>::new();
-        new_card.serialize(&mut new_compressed_card, true).expect("couldn't serialize");
+        let card = deserialize_point::<T>(compressed_card)?;
+        let mut new_card = card;
+        new_card.mul_assign(exponent);
+        let new_compressed_card = serialize_elem(&new_card)?;
         
         //generate Chaum-Pedersen proof
 		//see Boneh Shoup textbook v0.5 Figure 19.7
         let beta_t = Fr::random(&mut OsRng);
         let mut v_t = T::one();
         v_t.mul_assign(beta_t);
-        let mut v_t_compressed = Vec::<u8>::new();
-        v_t.serialize(&mut v_t_compressed, true).expect("couldn't serialize");
+        let v_t_compressed = serialize_elem(&v_t)?;
         
-        let mut w_t = card.clone();
+        let mut w_t = card;
         w_t.mul_assign(beta_t);
-        let mut w_t_compressed = Vec::<u8>::new();
-        w_t.serialize(&mut w_t_compressed, true).expect("couldn't serialize");
+        let w_t_compressed = serialize_elem(&w_t)?;
         
-		let mut hashinput: Vec<u8> = Vec::new();
-		hashinput.extend_from_slice(pub_secret);
-		hashinput.extend_from_slice(&compressed_card);
-		hashinput.extend_from_slice(&new_compressed_card);
-		hashinput.extend_from_slice(&v_t_compressed);
-		hashinput.extend_from_slice(&w_t_compressed);
-		let hashinput_bytes: &[u8] = &hashinput;
-		
-		let chal = hash_to_field::<Fr, ExpandMsgXmd<Sha256>>(hashinput_bytes, &dst, 1)[0];
+		let chal = pair_dleq_challenge(base, pub_secret, compressed_card, &new_compressed_card, &v_t_compressed, &w_t_compressed);
 		
 		let mut beta_z = chal;
-		beta_z.mul_assign(&self.secret);
+		beta_z.mul_assign(&exponent);
 		beta_z.add_assign(&beta_t);
 		
-        let mut beta_z_compressed = Vec::<u8>::new();
-        beta_z.serialize(&mut beta_z_compressed, true).expect("couldn't serialize");
+        let beta_z_compressed = serialize_elem(&beta_z)?;
 				
 		//println!("size of Fr compressed: {}", beta_z_compressed.len());//it's 32
 		let proof = PairProof {
@@ -150,53 +681,202 @@ impl PairServerData {
 			beta_z: beta_z_compressed,
 		};
 		
-		(new_compressed_card, proof)
+		Ok((new_compressed_card, proof))
         
     }
     
     
-	//check that the punch card is valid with num_punches
-	//check that the punch card secret is new
-    pub fn pair_server_verify(&mut self, compressed_card1: &mut Vec<u8>, secret1: [u8; 32], secret2: [u8; 32], num_punches: u32) -> bool {
-    
-        let csuite1 = [0u8; 4];
-        let csuite2 = [1u8, 0u8, 0u8, 0u8];
-    
+	//how redeemed cards are checked from now on
+	pub fn pair_set_check(&mut self, check: PairCheck) {
+		self.check = check;
+	}
+	
+	pub fn pair_check(&self) -> PairCheck {
+		self.check
+	}
+	
+	//check every card secret taken from now on against policy (see expiry.rs), None takes any
+	pub fn pair_set_expiry_policy(&mut self, policy: Option<ExpiryPolicy>) {
+		self.expiry = policy;
+	}
+	
+	pub fn pair_expiry_policy(&self) -> Option<ExpiryPolicy> {
+		self.expiry
+	}
+	
+	//the merged card e(H1(secret1), H2(secret2))^(x^num_punches) that a pairing of two cards should give
+	fn pair_expected_card(&self, secret1: [u8; 32], secret2: [u8; 32], num_punches: u32) -> Fq12 {
+        let (expcard_1_1, expcard_2_2) = self.pair_expected_parts(secret1, secret2, num_punches);
+        Bls12::pairing(expcard_1_1, expcard_2_2)
+	}
+	
+	//H1(secret1)^(x^num_punches) and H2(secret2), whose pairing is the expected merged card
+	fn pair_expected_parts(&self, secret1: [u8; 32], secret2: [u8; 32], num_punches: u32) -> (G1, G2) {
+	
         //compute the values and pairings you would expect
-        let num_punches = self.secret.pow([num_punches as u64]);
-        let mut expcard_1_1 = <G1 as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(&secret1, &csuite1);
+        let num_punches = match self.powers.get(&num_punches) {
+            Some((exponent, _, _)) => *exponent,
+            None => self.secret.pow([num_punches as u64]),
+        };
+        let mut expcard_1_1 = card_hash::<G1>(self.merchant, &secret1, &self.dst_g1);
         //let expcard_1_2 = <G2 as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(&secret1, &csuite2);
         //let mut expcard_2_1 = <G1 as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(&secret2, &csuite1);
-        let expcard_2_2 = <G2 as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(&secret2, &csuite2);
+        let expcard_2_2 = card_hash::<G2>(self.merchant, &secret2, &self.dst_g2);
         
         expcard_1_1.mul_assign(num_punches);
         //expcard_2_1.mul_assign(num_punches);
         
-        let exp_pairing_1 = Bls12::pairing(expcard_1_1, expcard_2_2);
-        //let exp_pairing_2 = Bls12::pairing(expcard_2_1, expcard_1_2);
-        
+        (expcard_1_1, expcard_2_2)
+	}
+    
+	//check that the punch card is valid with num_punches
+	//check that the punch card secrets are new and taken by the expiry policy
+	//compressed_card2 is the second pairing (card 2's G1 part with card 1's G2 part), which only the Both and Product checks use
+    pub fn pair_server_verify(&mut self, compressed_card1: &[u8], compressed_card2: &[u8], secret1: [u8; 32], secret2: [u8; 32], num_punches: u32) -> Result<(), PunchCardError> {
+    
+        check_expiry(self.expiry, &[secret1, secret2])?;
+
+        //check that the card is valid (real and expected values match)
+        if !self.pair_cards_valid(compressed_card1, compressed_card2, secret1, secret2, num_punches)? {
+            return Err(PunchCardError::InvalidCard);
+        }
         
+        //check that the secrets are new, and that the same card isn't being merged with itself
+        //(one card's G1 and G2 halves paired together would also carry twice its punches)
+        //insert_all records both or neither
+        if secret1 == secret2 || !self.used_cards.insert_all(&[secret1, secret2])? {
+            return Err(PunchCardError::DoubleRedemption);
+        }
+        Ok(())
+    }
+
+	//whether the two pairings of the cards with secret1 and secret2 hold num_punches, as far as self.check looks
+    fn pair_cards_valid(&self, compressed_card1: &[u8], compressed_card2: &[u8], secret1: [u8; 32], secret2: [u8; 32], num_punches: u32) -> Result<bool, PunchCardError> {
+    
         //deserialize the cards given as parameters
-        let card1 = Fq12::deserialize(&mut &compressed_card1[..], true).expect("couldn't deserialize");
-        //let card2 = Fq12::deserialize(&mut &compressed_card2[..], true).expect("couldn't deserialize");
+        let card1 = deserialize_point::<Fq12>(compressed_card1)?;
         
-        //check that the card is valid (real and expected values match)
-        if card1 == exp_pairing_1 {
-            //check that the secrets are new
-            //returns true if this was not in the set
-            self.used_cards.insert(secret1) && self.used_cards.insert(secret2)
-        } else {
-            false
+        Ok(match self.check {
+            PairCheck::Single => card1 == self.pair_expected_card(secret1, secret2, num_punches),
+            PairCheck::Both => {
+                let card2 = deserialize_point::<Fq12>(compressed_card2)?;
+                card1 == self.pair_expected_card(secret1, secret2, num_punches)
+                    && card2 == self.pair_expected_card(secret2, secret1, num_punches)
+            },
+            PairCheck::Product => {
+                let mut product = card1;
+                product.mul_assign(&deserialize_point::<Fq12>(compressed_card2)?);
+                let (expcard_1_1, expcard_2_2) = self.pair_expected_parts(secret1, secret2, num_punches);
+                let (expcard_2_1, expcard_1_2) = self.pair_expected_parts(secret2, secret1, num_punches);
+                product == Bls12::pairing_product(expcard_1_1, expcard_2_2, expcard_2_1, expcard_1_2)
+            },
+        })
+    }
+
+	//check a merge of any number of cards (see PairMergeToken) holds num_punches punches in total
+	//each pair is checked against its own count as self.check says, and all the card secrets must be new, different from each other
+	//and taken by the expiry policy
+    pub fn pair_server_verify_merged(&mut self, token: &PairMergeToken, num_punches: u32) -> Result<(), PunchCardError> {
+    
+        let num_cards = token.card_secrets.len();
+        if num_cards < 2 {
+            return Err(PunchCardError::WrongCardCount { expected: 2, got: num_cards });
+        }
+        check_expiry(self.expiry, &token.card_secrets)?;
+        //the full pairs, plus the odd card paired with itself
+        let num_pairs = num_cards / 2 + num_cards % 2;
+        if token.cards.len() != num_pairs || token.counts.len() != num_pairs {
+            return Err(PunchCardError::BadLength { expected: num_pairs, got: token.cards.len().min(token.counts.len()) });
+        }
+        if token.cards2.len() != num_cards / 2 {
+            return Err(PunchCardError::BadLength { expected: num_cards / 2, got: token.cards2.len() });
+        }
+        let total = token.counts.iter().try_fold(0u32, |total, count| total.checked_add(*count));
+        if total != Some(num_punches) {
+            return Err(PunchCardError::InvalidCard);
+        }
+        
+        for (j, (compressed_card, count)) in token.cards.iter().zip(&token.counts).enumerate() {
+            let secret1 = token.card_secrets[2*j];
+            let valid = match token.card_secrets.get(2*j + 1) {
+                Some(secret2) => self.pair_cards_valid(compressed_card, &token.cards2[j], secret1, *secret2, *count)?,
+                //a card paired with itself has its punches in both parts
+                None => {
+                    let exponent = count.checked_mul(2).ok_or(PunchCardError::InvalidPunchCount(*count))?;
+                    deserialize_point::<Fq12>(compressed_card)? == self.pair_expected_card(secret1, secret1, exponent)
+                },
+            };
+            if !valid {
+                return Err(PunchCardError::InvalidCard);
+            }
+        }
+        
+        let mut distinct = token.card_secrets.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() != num_cards || !self.used_cards.insert_all(&token.card_secrets)? {
+            return Err(PunchCardError::DoubleRedemption);
         }
+        Ok(())
     }
 
+	//pair_server_verify that also proves how it decided, for the client or an auditor
+	//Ok only if the token was redeemed, a rejected or double redeemed token is an AuditedRejection carrying its receipt,
+	//without one for failures that leave nothing to audit
+	//both pairings are checked whatever pair_check says, since that's what a receipt can prove, and against
+	//num_punches whatever the token claims, a receipt for a rejected token proves it doesn't hold num_punches
+	//like pair_server_verify only valid cards are used up, a rejected token gets a proof that one of its pairings
+	//doesn't match (see PairRedemptionEvidence) and can be sent again
+	pub fn pair_server_verify_audited(&mut self, token: PairRedeemToken, num_punches: u32) -> Result<PairRedemptionReceipt, AuditedRejection<PairRedemptionReceipt>> {
+		//anyone can check this from the card secrets, so it's refused before there's anything to audit
+		check_expiry(self.expiry, &[token.card_secret1, token.card_secret2])?;
+		let cards = [deserialize_point::<Fq12>(&token.card)?, deserialize_point::<Fq12>(&token.card2)?];
+		if !cards.iter().all(in_target_group) {
+			return Err(PunchCardError::MalformedPoint.into());
+		}
+		let (exponent, pub_power_g1, _) = self.pair_secret_power(num_punches)?;
+		let (base1, _) = punch_transcripts(self.merchant, self.epoch);
+		
+		let mut parts = Vec::with_capacity(2);
+		let mut mismatch = None;
+		for (j, (card, (g1_part, g2_part))) in cards.iter().zip(redemption_halves(self.merchant, &token.card_secret1, &token.card_secret2, &self.dst_g1, &self.dst_g2)).enumerate() {
+			let base = Bls12::pairing(g1_part, g2_part);
+			if base.pow(exponent.into_repr()) != *card {
+				mismatch = Some((j, pair_mismatch_prove(self.merchant, self.epoch, &base, card, exponent, &pub_power_g1)?));
+				break;
+			}
+			parts.push(self.pair_server_punch_part::<G1>(&serialize_elem(&g1_part)?, exponent, &pub_power_g1, &base1)?);
+		}
+		
+		let (evidence, outcome) = match mismatch {
+			Some((j, proof)) => (PairRedemptionEvidence::Invalid(j, proof), Err(PunchCardError::InvalidCard)),
+			None => {
+				//as in pair_server_verify, a card merged with itself counts as redeemed twice
+				let fresh = token.card_secret1 != token.card_secret2 && self.used_cards.insert_all(&[token.card_secret1, token.card_secret2])?;
+				let outcome = if fresh { Ok(()) } else { Err(PunchCardError::DoubleRedemption) };
+				(PairRedemptionEvidence::Valid(parts), outcome)
+			},
+		};
+		let receipt = PairRedemptionReceipt {
+			token,
+			num_punches,
+			evidence,
+			outcome,
+		};
+		match receipt.outcome.clone() {
+			Ok(()) => Ok(receipt),
+			Err(error) => Err(AuditedRejection { error, receipt: Some(Box::new(receipt)) }),
+		}
+	}
+
 	//preload the database of used cards with num entries
-	pub fn pair_cheat_setup_db(&mut self, num:u32) {
+	pub fn pair_cheat_setup_db(&mut self, num:u32) -> Result<(), PunchCardError> {
 		for i in 0..num {
             //this is weird, but it's a hack anyway
 			let temp = Scalar::from(i).to_bytes();
-			self.used_cards.insert(temp);
+			self.used_cards.insert(temp)?;
 		}
+		Ok(())
 	}
 
 	pub fn pair_count_cards(&self) -> usize {
@@ -208,37 +888,67 @@ impl PairServerData {
 impl PairPunchCard {
 
     //new mergable punchcard
-    pub fn card_setup() -> (Vec<u8>, Vec<u8>, PairPunchCard) {
+    pub fn card_setup() -> Result<(Vec<u8>, Vec<u8>, PairPunchCard), PunchCardError> {
+        Self::card_setup_for(DEFAULT_MERCHANT)
+    }
+    
+    //new mergable punchcard for merchant (see merchant.rs)
+    pub fn card_setup_for(merchant: u32) -> Result<(Vec<u8>, Vec<u8>, PairPunchCard), PunchCardError> {
     
-        //giving the same secret to both cards
-        //different domain separators
         let mut card_secret = [0u8; 32];
 		OsRng.fill_bytes(&mut card_secret);
-        let csuite1 = [0u8; 4];
-        let csuite2 = [1u8, 0u8, 0u8, 0u8];
-        
-        let (card1, client1) = Self::card_part_setup::<G1>(card_secret, csuite1);
-        let (card2, client2) = Self::card_part_setup::<G2>(card_secret, csuite2);
+        Self::card_setup_with_secret(merchant, card_secret, BLS12381G1_DST, BLS12381G2_DST)
+    }
+    
+    //create a new punchcard for merchant that expires after epoch expiry (see expiry.rs)
+    pub fn card_setup_until(merchant: u32, expiry: u32) -> Result<(Vec<u8>, Vec<u8>, PairPunchCard), PunchCardError> {
+        Self::card_setup_with_secret(merchant, secret_until(expiry), BLS12381G1_DST, BLS12381G2_DST)
+    }
+    
+    //create a new punchcard for merchant with its parts hashed with dst_g1 and dst_g2, for a server set up with
+    //PairServerData::pair_server_setup_with_dst, expiring after epoch expiry if there is one
+    pub fn card_setup_with_dst(merchant: u32, expiry: Option<u32>, dst_g1: &[u8], dst_g2: &[u8]) -> Result<(Vec<u8>, Vec<u8>, PairPunchCard), PunchCardError> {
+        let card_secret = match expiry {
+            Some(expiry) => secret_until(expiry),
+            None => {
+                let mut card_secret = [0u8; 32];
+                OsRng.fill_bytes(&mut card_secret);
+                card_secret
+            },
+        };
+        Self::card_setup_with_secret(merchant, card_secret, dst_g1, dst_g2)
+    }
+    
+    fn card_setup_with_secret(merchant: u32, card_secret: [u8; 32], dst_g1: &[u8], dst_g2: &[u8]) -> Result<(Vec<u8>, Vec<u8>, PairPunchCard), PunchCardError> {
+    
+        //giving the same secret to both cards
+        //different domain separators
+        let (card1, client1) = Self::card_part_setup::<G1>(merchant, card_secret, dst_g1)?;
+        let (card2, client2) = Self::card_part_setup::<G2>(merchant, card_secret, dst_g2)?;
         
         let new_card = PairPunchCard {
+            merchant,
+            epoch: DEFAULT_EPOCH,
             g1card: client1,
             g2card: client2,
+            dst_g1: dst_g1.to_vec(),
+            dst_g2: dst_g2.to_vec(),
         };
         
-        (card1, card2, new_card)
+        Ok((card1, card2, new_card))
         
     }
 
 	//create a new punchcard part
 	//punch card is already masked after this function
-	fn card_part_setup<T>(card_secret: [u8; 32], csuite: [u8; 4]) -> (Vec<u8>, PairPunchCardPart::<T>) 
-        where T: CurveProjective + SerDes + HashToCurve<ExpandMsgXmd<Sha256>>,
+	fn card_part_setup<T>(merchant: u32, card_secret: [u8; 32], dst: &[u8]) -> Result<(Vec<u8>, PairPunchCardPart::<T>), PunchCardError>
+        where T: CurveProjective + SerDes + HashToBls12381,
               <<T as CurveProjective>::Scalar as PrimeField>::Repr: std::convert::From<Fr>
     {
 		
         let last_mask = Fr::random(&mut OsRng);
         
-        let mut punch_card = <T as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(&card_secret, &csuite);
+        let mut punch_card = card_hash::<T>(merchant, &card_secret, dst);
         punch_card.mul_assign(last_mask);
         
         let new_punch_card = PairPunchCardPart::<T> {
@@ -248,119 +958,231 @@ impl PairPunchCard {
             count: 0,
         };
 
-        let mut card_compressed = Vec::<u8>::new();
-        new_punch_card.punch_card.serialize(&mut card_compressed, true).expect("couldn't serialize");
+        let card_compressed = serialize_elem(&new_punch_card.punch_card)?;
 
-        (card_compressed, new_punch_card)
+        Ok((card_compressed, new_punch_card))
 		
 	}
 	
-	pub fn verify_remask(&mut self, compressed_card1: Vec<u8>, compressed_card2: Vec<u8>, pub_secret_g1: &Vec<u8>, pub_secret_g2: &Vec<u8>, proof1: PairProof, proof2: PairProof) -> (Vec<u8>, Vec<u8>, bool) {
+	//verify both proofs before touching either part so the two halves never get out of sync
+	//on failure both parts keep their old punchcard and count
+	pub fn verify_remask(&mut self, compressed_card1: Vec<u8>, compressed_card2: Vec<u8>, pub_secret_g1: &[u8], pub_secret_g2: &[u8], proof1: PairProof, proof2: PairProof) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+            self.verify_remask_with(compressed_card1, compressed_card2, pub_secret_g1, pub_secret_g2, 1, proof1, proof2)
+	}
+	
+	//verify_remask for a punch by k, checked against the powers for k
+	//powers should have been checked with PairPowerKey::verify
+	pub fn verify_remask_by(&mut self, compressed_card1: Vec<u8>, compressed_card2: Vec<u8>, powers: &PairPowerKey, k: u32, proof1: PairProof, proof2: PairProof) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+            let (pub_power_g1, pub_power_g2) = powers.power(k).ok_or(PunchCardError::InvalidPunchCount(k))?;
+            self.verify_remask_with(compressed_card1, compressed_card2, pub_power_g1, pub_power_g2, k, proof1, proof2)
+	}
+	
+	#[allow(clippy::too_many_arguments)]
+	fn verify_remask_with(&mut self, compressed_card1: Vec<u8>, compressed_card2: Vec<u8>, pub_secret_g1: &[u8], pub_secret_g2: &[u8], k: u32, proof1: PairProof, proof2: PairProof) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
 	
-            let dst1 = [3u8, 0u8, 0u8, 0u8];
-            let dst2 = [4u8, 0u8, 0u8, 0u8];
+            if self.g1card.count.checked_add(k).is_none() || self.g2card.count.checked_add(k).is_none() {
+                return Err(PunchCardError::InvalidPunchCount(k));
+            }
+            let (base1, base2) = punch_transcripts(self.merchant, self.epoch);
             
-            let (card1, success1) = Self::verify_remask_part::<G1>(&mut self.g1card, compressed_card1, pub_secret_g1, proof1, dst1);
-            let (card2, success2) = Self::verify_remask_part::<G2>(&mut self.g2card, compressed_card2, pub_secret_g2, proof2, dst2);
+            let new_card1 = Self::verify_part::<G1>(&base1, &self.g1card, &compressed_card1, pub_secret_g1, &proof1)?;
+            let new_card2 = Self::verify_part::<G2>(&base2, &self.g2card, &compressed_card2, pub_secret_g2, &proof2)?;
             
-            if success1 != success2 {panic!("success values don't match");}
+            let card1 = Self::remask_part::<G1>(&mut self.g1card, new_card1, k)?;
+            let card2 = Self::remask_part::<G2>(&mut self.g2card, new_card2, k)?;
             
-            (card1, card2, success1)
+            Ok((card1, card2))
 	}
 	
 	//verify proof from the server
-	//if accepted, unmask punchcard, remask with new mask, increment count
-	//otherwise reuse old punchcard, same count
-	fn verify_remask_part<T>(card: &mut PairPunchCardPart<T>, new_compressed_card: Vec<u8>, pub_secret: &Vec<u8>, proof: PairProof, dst: [u8; 4]) -> (Vec<u8>, bool) 
+	//returns the decompressed punched card if the proof is accepted
+	fn verify_part<T>(base: &Transcript, card: &PairPunchCardPart<T>, new_compressed_card: &[u8], pub_secret: &[u8], proof: &PairProof) -> Result<T, PunchCardError>
         where T: CurveProjective + SerDes,
         <<T as pairing_plus::CurveProjective>::Scalar as ff_zeroize::PrimeField>::Repr: std::convert::From<pairing_plus::bls12_381::Fr>
 	{
-	
-        //serialize the punch card so it can be used here
-        let mut compressed_card = Vec::<u8>::new();
-        card.punch_card.serialize(&mut compressed_card, true).expect("couldn't serialize");
-	
         //verify Chaum-Pedersen proof
-		//see Boneh Shoup textbook v0.5 Figure 19.7
-		let mut hashinput: Vec<u8> = Vec::new();
-		hashinput.extend_from_slice(pub_secret);
-		hashinput.extend_from_slice(&compressed_card);
-		hashinput.extend_from_slice(&new_compressed_card);
-		hashinput.extend_from_slice(&proof.v_t);
-		hashinput.extend_from_slice(&proof.w_t);
-		let hashinput_bytes: &[u8] = &hashinput;
-		let chal = hash_to_field::<Fr, ExpandMsgXmd<Sha256>>(hashinput_bytes, &dst, 1)[0];
-		
-		//decompress proof elements and remaining inputs
-		let pub_secret = T::deserialize(&mut &pub_secret[..], true).expect("couldn't deserialize");
-        let v_t = T::deserialize(&mut &proof.v_t[..], true).expect("couldn't deserialize");
-		let w_t = T::deserialize(&mut &proof.w_t[..], true).expect("couldn't deserialize");
-		let mut new_card = T::deserialize(&mut &new_compressed_card[..], true).expect("couldn't deserialize");
-        let beta_z = Fr::deserialize(&mut &proof.beta_z[..], true).expect("couldn't deserialize");
-
-		let mut gbz = T::one();
-		gbz.mul_assign(beta_z);
-        
-        let mut vtvc = v_t;
-        let mut part = pub_secret;
-        part.mul_assign(chal);
-        vtvc.add_assign(&part);
-        
-        let mut ubz = card.punch_card;
-        ubz.mul_assign(beta_z);
-        
-        let mut wtwc = w_t;
-        let mut part = new_card;
-        part.mul_assign(chal);
-        wtwc.add_assign(&part);
-        
-        let mut success = true;
-        if gbz == vtvc && ubz == wtwc {
-            new_card.mul_assign(card.last_mask.inverse().expect("couldn't invert!"));
-            card.last_mask = Fr::random(&mut OsRng);
-            new_card.mul_assign(card.last_mask);
-            card.punch_card = new_card;
-            card.count += 1;
-        } else {
-            success = false;
+        let dleq = PairDleq::decode(base, pub_secret, card.punch_card, new_compressed_card, proof)?;
+        if !dleq.check(&deserialize_point::<T>(pub_secret)?) {
+            return Err(PunchCardError::BadProof);
         }
         
-        //serialize new card
-        let mut new_card_compressed = Vec::<u8>::new();
-        new_card.serialize(&mut new_card_compressed, true).expect("couldn't serialize");
+        Ok(dleq.c)
+	}
+	
+	//unmask punchcard, remask with new mask, add k to count
+	fn remask_part<T>(card: &mut PairPunchCardPart<T>, mut new_card: T, k: u32) -> Result<Vec<u8>, PunchCardError>
+        where T: CurveProjective + SerDes,
+        <<T as pairing_plus::CurveProjective>::Scalar as ff_zeroize::PrimeField>::Repr: std::convert::From<pairing_plus::bls12_381::Fr>
+	{
+        new_card.mul_assign(card.last_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+        card.last_mask = Fr::random(&mut OsRng);
+        new_card.mul_assign(card.last_mask);
+        card.punch_card = new_card;
+        card.count += k;
         
-        (new_card_compressed, success)
+        //serialize new card
+        serialize_elem(&new_card)
 	}
 	
 	//unmask the punch card, use pairings to merge, and return relevant contents
-	pub fn pair_unmask_redeem(&mut self, mut other: PairPunchCard) -> ([u8; 32], [u8; 32], Vec<u8>) {
+	pub fn pair_unmask_redeem(&mut self, mut other: PairPunchCard) -> Result<PairRedeemToken, PunchCardError> {
+        
+        let num_punches = self.pair_get_count()?.checked_add(other.pair_get_count()?)
+            .ok_or(PunchCardError::InvalidPunchCount(u32::MAX))?;
         
         //unmask the punch cards
-        self.g1card.punch_card.mul_assign(self.g1card.last_mask.inverse().expect("couldn't invert!"));
-        //self.g2card.punch_card.mul_assign(self.g2card.last_mask.inverse().expect("couldn't invert!"));
-        //other.g1card.punch_card.mul_assign(other.g1card.last_mask.inverse().expect("couldn't invert!"));
-        other.g2card.punch_card.mul_assign(other.g2card.last_mask.inverse().expect("couldn't invert!"));
+        self.g1card.punch_card.mul_assign(self.g1card.last_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+        self.g2card.punch_card.mul_assign(self.g2card.last_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+        other.g1card.punch_card.mul_assign(other.g1card.last_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+        other.g2card.punch_card.mul_assign(other.g2card.last_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
         
         //pairings of the parts of the punch cards
+        //both are sent so the server can check either or both (see PairCheck)
         let pairing1 = Bls12::pairing(self.g1card.punch_card, other.g2card.punch_card);
-        //let pairing2 = Bls12::pairing(other.g1card.punch_card, self.g2card.punch_card);
+        let pairing2 = Bls12::pairing(other.g1card.punch_card, self.g2card.punch_card);
 	
         //serialize pairing outputs
-        let mut pairing1_compressed = Vec::<u8>::new();
-        //let mut pairing2_compressed = Vec::<u8>::new();
-        pairing1.serialize(&mut pairing1_compressed, true).expect("couldn't serialize");
-        //pairing2.serialize(&mut pairing2_compressed, true).expect("couldn't serialize");
+        let pairing1_compressed = serialize_elem(&pairing1)?;
+        let pairing2_compressed = serialize_elem(&pairing2)?;
 
         
         //return the secrets from the punch cards and the results of the pairings
         //since both parts of each card use the same secret, we only need one from each
-        (self.g1card.card_secret, other.g1card.card_secret, pairing1_compressed)//, pairing2_compressed)
+        Ok(PairRedeemToken {
+            card_secret1: self.g1card.card_secret,
+            card_secret2: other.g1card.card_secret,
+            card: pairing1_compressed,
+            card2: pairing2_compressed,
+            num_punches,
+        })
+	}
+	
+	//unmask any number (at least two) of cards and merge them, see PairMergeToken
+	pub fn pair_unmask_merge(cards: Vec<PairPunchCard>) -> Result<PairMergeToken, PunchCardError> {
+        
+        if cards.len() < 2 {
+            return Err(PunchCardError::WrongCardCount { expected: 2, got: cards.len() });
+        }
+        let mut token = PairMergeToken {
+            card_secrets: cards.iter().map(|card| card.g1card.card_secret).collect(),
+            cards: Vec::with_capacity(cards.len() / 2 + cards.len() % 2),
+            cards2: Vec::with_capacity(cards.len() / 2),
+            counts: Vec::with_capacity(cards.len() / 2 + cards.len() % 2),
+        };
+        for pair in cards.chunks(2) {
+            let (first, second, count) = match pair {
+                [first, second] => (first, second, first.pair_get_count()?.checked_add(second.pair_get_count()?)),
+                _ => (&pair[0], &pair[0], Some(pair[0].pair_get_count()?)),
+            };
+            let (first_g1, first_g2) = first.unmasked_parts()?;
+            let (second_g1, second_g2) = second.unmasked_parts()?;
+            token.cards.push(serialize_elem(&Bls12::pairing(first_g1, second_g2))?);
+            if pair.len() == 2 {
+                token.cards2.push(serialize_elem(&Bls12::pairing(second_g1, first_g2))?);
+            }
+            token.counts.push(count.ok_or(PunchCardError::InvalidPunchCount(u32::MAX))?);
+        }
+        Ok(token)
 	}
 	
-	pub fn pair_get_count(&self) -> u32 {
-		if self.g1card.count != self.g2card.count {panic!("card counts misaligned!")}
+	//the G1 and G2 parts with their masks removed
+	fn unmasked_parts(&self) -> Result<(G1, G2), PunchCardError> {
+		let mut part1 = self.g1card.punch_card;
+		part1.mul_assign(self.g1card.last_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+		let mut part2 = self.g2card.punch_card;
+		part2.mul_assign(self.g2card.last_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+		Ok((part1, part2))
+	}
+
+	pub fn pair_get_count(&self) -> Result<u32, PunchCardError> {
+		if self.g1card.count != self.g2card.count {
+			return Err(PunchCardError::CountMismatch);
+		}
 		
-		self.g1card.count
+		Ok(self.g1card.count)
+	}
+	
+	//the epoch whose punches this card checks (see epoch.rs), it has to match the server's
+	pub fn set_epoch(&mut self, epoch: u32) {
+		self.epoch = epoch;
+	}
+	
+	//the current masked card parts, to send for the next punch
+	pub fn get_cards(&self) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+		Ok((serialize_elem(&self.g1card.punch_card)?, serialize_elem(&self.g2card.punch_card)?))
+	}
+	
+	//state body is merchant (u32 little endian) || epoch (u32 little endian) || card_secret || count (u32 little endian)
+	//|| g1 punch_card || g1 last_mask || g2 punch_card || g2 last_mask || dst_g1 || dst_g2
+	//see wallet.rs for the header and keyfile.rs for how the DSTs are written
+	pub fn save_state(&self) -> Result<Vec<u8>, PunchCardError> {
+		let count = self.pair_get_count()?;
+		let mut body = Vec::with_capacity(CARD_STATE_LEN + 8 + self.dst_g1.len() + self.dst_g2.len());
+		body.extend_from_slice(&self.merchant.to_le_bytes());
+		body.extend_from_slice(&self.epoch.to_le_bytes());
+		body.extend_from_slice(&self.g1card.card_secret);
+		body.extend_from_slice(&count.to_le_bytes());
+		body.extend_from_slice(&serialize_elem(&self.g1card.punch_card)?);
+		body.extend_from_slice(&serialize_elem(&self.g1card.last_mask)?);
+		body.extend_from_slice(&serialize_elem(&self.g2card.punch_card)?);
+		body.extend_from_slice(&serialize_elem(&self.g2card.last_mask)?);
+		write_dst(&self.dst_g1, &mut body);
+		write_dst(&self.dst_g2, &mut body);
+		Ok(encode_card_state(TAG_BLS12_381, &body))
+	}
+	
+	//restore a card saved with save_state
+	//both unmasked parts are H(secret)^(x^count) in their group, so without knowing x we can still
+	//check they agree with each other: e(part1, H2(secret)) == e(H1(secret), part2)
+	//a card with no punches must be exactly the hash of its secret
+	pub fn restore_state(state: &[u8]) -> Result<PairPunchCard, PunchCardError> {
+		let (body, rest) = decode_card_state(TAG_BLS12_381, state, CARD_STATE_LEN)?;
+		let dsts = read_dsts(rest, 2)?;
+		let (dst_g1, dst_g2) = (dsts[0].clone(), dsts[1].clone());
+		let (context, body) = body.split_at(8);
+		let merchant = u32::from_le_bytes([context[0], context[1], context[2], context[3]]);
+		let epoch = u32::from_le_bytes([context[4], context[5], context[6], context[7]]);
+		let fields = split_fields(body, &[32, 4, G1_LEN, FR_LEN, G2_LEN, FR_LEN])?;
+		let card_secret = read_array32(fields[0])?;
+		let count = u32::from_le_bytes([fields[1][0], fields[1][1], fields[1][2], fields[1][3]]);
+		let g1_card = deserialize_point::<G1>(fields[2])?;
+		let g1_mask = Fr::deserialize(&mut &fields[3][..], true).map_err(|_| PunchCardError::MalformedScalar)?;
+		let g2_card = deserialize_point::<G2>(fields[4])?;
+		let g2_mask = Fr::deserialize(&mut &fields[5][..], true).map_err(|_| PunchCardError::MalformedScalar)?;
+		
+		let hash1 = card_hash::<G1>(merchant, &card_secret, &dst_g1);
+		let hash2 = card_hash::<G2>(merchant, &card_secret, &dst_g2);
+		
+		let mut unmasked1 = g1_card;
+		unmasked1.mul_assign(g1_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+		let mut unmasked2 = g2_card;
+		unmasked2.mul_assign(g2_mask.inverse().ok_or(PunchCardError::NotInvertible)?);
+		
+		if Bls12::pairing(unmasked1, hash2) != Bls12::pairing(hash1, unmasked2) {
+			return Err(PunchCardError::InvalidCard);
+		}
+		if count == 0 && (unmasked1 != hash1 || unmasked2 != hash2) {
+			return Err(PunchCardError::InvalidCard);
+		}
+		
+		Ok(PairPunchCard {
+			merchant,
+			epoch,
+			g1card: PairPunchCardPart::<G1> {
+				card_secret,
+				punch_card: g1_card,
+				last_mask: g1_mask,
+				count,
+			},
+			g2card: PairPunchCardPart::<G2> {
+				card_secret,
+				punch_card: g2_card,
+				last_mask: g2_mask,
+				count,
+			},
+			dst_g1,
+			dst_g2,
+		})
 	}
 	
     pub fn exp_test_g1(&mut self) -> G1{
@@ -378,3 +1200,543 @@ impl PairPunchCard {
     }
 
 }
+
+impl PairPowerKey {
+
+    //g1^(secret^k) and g2^(secret^k), if k is covered
+    pub fn power(&self, k: u32) -> Option<(&[u8], &[u8])> {
+        if k == 0 {
+            return None;
+        }
+        let i = k as usize - 1;
+        Some((self.powers_g1.get(i)?, self.powers_g2.get(i)?))
+    }
+    
+    //largest k this key can check
+    pub fn max_k(&self) -> u32 {
+        self.powers_g1.len().min(self.powers_g2.len()) as u32
+    }
+    
+    //check that the powers in both groups start at the public keys and each is the previous one raised to the secret
+    pub fn verify(&self, pub_secret_g1: &[u8], pub_secret_g2: &[u8]) -> Result<(), PunchCardError> {
+        let n = self.powers_g1.len();
+        if n == 0 || self.powers_g2.len() != n || self.proofs_g1.len() + 1 != n || self.proofs_g2.len() + 1 != n
+            || self.powers_g1[0] != pub_secret_g1 || self.powers_g2[0] != pub_secret_g2 {
+            return Err(PunchCardError::BadProof);
+        }
+        let chain_g1: Vec<_> = self.proofs_g1.iter().enumerate()
+            .map(|(i, proof)| (&self.powers_g1[i][..], &self.powers_g1[i + 1][..], proof))
+            .collect();
+        let chain_g2: Vec<_> = self.proofs_g2.iter().enumerate()
+            .map(|(i, proof)| (&self.powers_g2[i][..], &self.powers_g2[i + 1][..], proof))
+            .collect();
+        let (base1, base2) = punch_transcripts(self.merchant, self.epoch);
+        let bad1 = pair_batch_verify_part::<G1>(pub_secret_g1, &base1, &chain_g1)?;
+        let bad2 = pair_batch_verify_part::<G2>(pub_secret_g2, &base2, &chain_g2)?;
+        match bad1.into_iter().chain(bad2).min() {
+            Some(i) => Err(PunchCardError::BadProofAt(i)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PairRedemptionReceipt {
+
+    //check the server's evidence and that outcome follows from it
+    //powers must reach num_punches and should have been checked with PairPowerKey::verify
+    //BadProof if the evidence doesn't verify, DishonestRedemption if the outcome doesn't match it
+    pub fn audit(&self, powers: &PairPowerKey) -> Result<(), PunchCardError> {
+        let pub_power_g1 = match self.num_punches {
+            0 => serialize_elem(&G1::one())?,
+            n => powers.power(n).ok_or(PunchCardError::InvalidPunchCount(n))?.0.to_vec(),
+        };
+        let cards = [deserialize_point::<Fq12>(&self.token.card)?, deserialize_point::<Fq12>(&self.token.card2)?];
+        let halves = redemption_halves(powers.merchant, &self.token.card_secret1, &self.token.card_secret2, &powers.dst_g1, &powers.dst_g2);
+        let valid = match &self.evidence {
+            PairRedemptionEvidence::Valid(parts) => {
+                if parts.len() != 2 {
+                    return Err(PunchCardError::BadProof);
+                }
+                let (base1, _) = punch_transcripts(powers.merchant, powers.epoch);
+                let pub_power_dec = deserialize_point::<G1>(&pub_power_g1)?;
+                //each G1 half was raised to the published power, and its pairing with the G2 half is the card
+                for ((card, (g1_part, g2_part)), (punched, proof)) in cards.iter().zip(halves).zip(parts) {
+                    let dleq = PairDleq::decode(&base1, &pub_power_g1, g1_part, punched, proof)?;
+                    if !dleq.check(&pub_power_dec) || Bls12::pairing(dleq.c, g2_part) != *card {
+                        return Err(PunchCardError::BadProof);
+                    }
+                }
+                true
+            },
+            PairRedemptionEvidence::Invalid(j, proof) => {
+                let (card, (g1_part, g2_part)) = cards.get(*j).zip(halves.get(*j)).ok_or(PunchCardError::BadProof)?;
+                pair_mismatch_verify(powers.merchant, powers.epoch, &pub_power_g1, &Bls12::pairing(*g1_part, *g2_part), card, proof)?;
+                false
+            },
+        };
+        check_outcome(valid, &self.outcome)
+    }
+}
+
+//the mergeable scheme over BLS12-381
+//cards, proofs and public keys come in (G1, G2) pairs
+//N is where the server keeps redeemed card secrets
+#[derive(Debug)]
+pub struct PairingScheme<N = MemoryNullifierStore>(PhantomData<N>);
+
+impl<N: NullifierStore> PunchCardScheme for PairingScheme<N> {
+    type Store = N;
+    type Server = PairServerData<N>;
+    type PublicKey = (Vec<u8>, Vec<u8>);
+    type Client = PairPunchCard;
+    type Card = (Vec<u8>, Vec<u8>);
+    type Proof = (PairProof, PairProof);
+    type RedeemToken = PairRedeemToken;
+    type PowerKey = PairPowerKey;
+    type Dst = (Vec<u8>, Vec<u8>);
+
+    const NAME: &'static str = "BLS12_381 group with merging";
+    const CARDS_PER_REDEMPTION: u32 = 2;
+
+    fn default_dst() -> (Vec<u8>, Vec<u8>) {
+        (BLS12381G1_DST.to_vec(), BLS12381G2_DST.to_vec())
+    }
+
+    fn server_setup_with_dst(merchant: u32, store: N, dst: &(Vec<u8>, Vec<u8>)) -> Result<((Vec<u8>, Vec<u8>), PairServerData<N>), PunchCardError> {
+        let server = PairServerData::pair_server_setup_with_dst(merchant, store, &dst.0, &dst.1)?;
+        Ok(((server.pub_secret_g1.clone(), server.pub_secret_g2.clone()), server))
+    }
+
+    fn export_key(server: &PairServerData<N>) -> Result<Vec<u8>, PunchCardError> {
+        server.pair_export_key()
+    }
+
+    fn import_key(key: &[u8], store: N) -> Result<((Vec<u8>, Vec<u8>), PairServerData<N>), PunchCardError> {
+        let server = PairServerData::pair_import_key(key, store)?;
+        Ok(((server.pub_secret_g1.clone(), server.pub_secret_g2.clone()), server))
+    }
+
+    fn merchant(server: &PairServerData<N>) -> u32 {
+        server.pair_merchant()
+    }
+
+    fn set_epoch(server: &mut PairServerData<N>, epoch: u32) {
+        server.pair_set_epoch(epoch);
+    }
+
+    fn set_expiry_policy(server: &mut PairServerData<N>, policy: Option<ExpiryPolicy>) {
+        server.pair_set_expiry_policy(policy);
+    }
+
+    fn expiry_policy(server: &PairServerData<N>) -> Option<ExpiryPolicy> {
+        server.pair_expiry_policy()
+    }
+
+    fn set_card_epoch(client: &mut PairPunchCard, epoch: u32) {
+        client.set_epoch(epoch);
+    }
+
+    fn card_setup_with_dst(merchant: u32, expiry: Option<u32>, dst: &(Vec<u8>, Vec<u8>)) -> Result<((Vec<u8>, Vec<u8>), PairPunchCard), PunchCardError> {
+        let (card1, card2, client) = PairPunchCard::card_setup_with_dst(merchant, expiry, &dst.0, &dst.1)?;
+        Ok(((card1, card2), client))
+    }
+
+    fn server_punch(server: &PairServerData<N>, card: &(Vec<u8>, Vec<u8>)) -> Result<((Vec<u8>, Vec<u8>), (PairProof, PairProof)), PunchCardError> {
+        let (card1, card2, proof1, proof2) = server.pair_server_punch(&card.0, &card.1)?;
+        Ok(((card1, card2), (proof1, proof2)))
+    }
+
+    fn verify_remask(client: &mut PairPunchCard, card: (Vec<u8>, Vec<u8>), pub_key: &(Vec<u8>, Vec<u8>),
+                     proof: (PairProof, PairProof)) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+        client.verify_remask(card.0, card.1, &pub_key.0, &pub_key.1, proof.0, proof.1)
+    }
+
+    fn precompute_thresholds(server: &mut PairServerData<N>, thresholds: &[u32]) -> Result<(), PunchCardError> {
+        server.pair_precompute_thresholds(thresholds)
+    }
+
+    fn publish_powers(server: &PairServerData<N>, max_k: u32) -> Result<PairPowerKey, PunchCardError> {
+        server.pair_power_key(max_k)
+    }
+
+    fn verify_powers(pub_key: &(Vec<u8>, Vec<u8>), powers: &PairPowerKey) -> Result<(), PunchCardError> {
+        powers.verify(&pub_key.0, &pub_key.1)
+    }
+
+    fn server_punch_by(server: &PairServerData<N>, card: &(Vec<u8>, Vec<u8>), k: u32) -> Result<((Vec<u8>, Vec<u8>), (PairProof, PairProof)), PunchCardError> {
+        let (card1, card2, proof1, proof2) = server.pair_server_punch_by(&card.0, &card.1, k)?;
+        Ok(((card1, card2), (proof1, proof2)))
+    }
+
+    fn verify_remask_by(client: &mut PairPunchCard, card: (Vec<u8>, Vec<u8>), powers: &PairPowerKey, k: u32,
+                        proof: (PairProof, PairProof)) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+        client.verify_remask_by(card.0, card.1, powers, k, proof.0, proof.1)
+    }
+
+    fn batch_verify(pub_key: &(Vec<u8>, Vec<u8>), merchant: u32, epoch: u32, punches: &[PairPunchRecord]) -> Result<(), PunchCardError> {
+        pair_batch_verify(&pub_key.0, &pub_key.1, merchant, epoch, punches)
+    }
+
+    fn get_count(client: &PairPunchCard) -> Result<u32, PunchCardError> {
+        client.pair_get_count()
+    }
+
+    fn get_card(client: &PairPunchCard) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+        client.get_cards()
+    }
+
+    fn save_client(client: &PairPunchCard) -> Result<Vec<u8>, PunchCardError> {
+        client.save_state()
+    }
+
+    fn restore_client(state: &[u8]) -> Result<PairPunchCard, PunchCardError> {
+        PairPunchCard::restore_state(state)
+    }
+
+    fn unmask_redeem(mut clients: Vec<PairPunchCard>) -> Result<PairRedeemToken, PunchCardError> {
+        let got = clients.len();
+        match (clients.pop(), clients.pop(), got) {
+            (Some(second), Some(mut first), 2) => first.pair_unmask_redeem(second),
+            _ => Err(PunchCardError::WrongCardCount { expected: 2, got }),
+        }
+    }
+
+    fn server_verify(server: &mut PairServerData<N>, token: PairRedeemToken, num_punches: u32) -> Result<(), PunchCardError> {
+        //the card is checked against the count the client gives, which has to be the one asked for
+        //(a card claiming some other count is as wrong as one whose pairing doesn't match)
+        if token.num_punches != num_punches {
+            return Err(PunchCardError::InvalidCard);
+        }
+        server.pair_server_verify(&token.card, &token.card2, token.card_secret1, token.card_secret2, token.num_punches)
+    }
+
+    fn nullifiers(token: &PairRedeemToken) -> Vec<[u8; 32]> {
+        vec![token.card_secret1, token.card_secret2]
+    }
+
+    fn store_mut(server: &mut PairServerData<N>) -> &mut N {
+        &mut server.used_cards
+    }
+
+    fn count_cards(server: &PairServerData<N>) -> usize {
+        server.pair_count_cards()
+    }
+
+    fn cheat_setup_db(server: &mut PairServerData<N>, num: u32) -> Result<(), PunchCardError> {
+        server.pair_cheat_setup_db(num)
+    }
+}
+
+impl<N: NullifierStore> AuditedScheme for PairingScheme<N> {
+    type Receipt = PairRedemptionReceipt;
+
+    fn server_verify_audited(server: &mut PairServerData<N>, token: PairRedeemToken, num_punches: u32) -> Result<PairRedemptionReceipt, AuditedRejection<PairRedemptionReceipt>> {
+        server.pair_server_verify_audited(token, num_punches)
+    }
+
+    fn audit(receipt: &PairRedemptionReceipt, powers: &PairPowerKey) -> Result<(), PunchCardError> {
+        receipt.audit(powers)
+    }
+}
+
+//split a length checked slice into the given field sizes
+fn split_fields<'a>(bytes: &'a [u8], lens: &[usize]) -> Result<Vec<&'a [u8]>, PunchCardError> {
+    let total = lens.iter().sum();
+    if bytes.len() != total {
+        return Err(PunchCardError::BadLength { expected: total, got: bytes.len() });
+    }
+    let mut rest = bytes;
+    let mut fields = Vec::with_capacity(lens.len());
+    for len in lens {
+        let (field, tail) = rest.split_at(*len);
+        fields.push(field);
+        rest = tail;
+    }
+    Ok(fields)
+}
+
+//G1 part always comes before the G2 part
+impl<N: NullifierStore> WireScheme for PairingScheme<N> {
+    const SCHEME_TAG: u8 = TAG_BLS12_381;
+    const PUBLIC_KEY_LEN: usize = G1_LEN + G2_LEN;
+    const CARD_LEN: usize = G1_LEN + G2_LEN;
+    const PROOF_LEN: usize = (2*G1_LEN + FR_LEN) + (2*G2_LEN + FR_LEN);
+    const REDEEM_TOKEN_LEN: usize = 32 + 32 + 4 + 2*FQ12_LEN;
+
+    fn write_public_key(pub_key: &(Vec<u8>, Vec<u8>), out: &mut Vec<u8>) {
+        out.extend_from_slice(&pub_key.0);
+        out.extend_from_slice(&pub_key.1);
+    }
+
+    fn read_public_key(bytes: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+        let fields = split_fields(bytes, &[G1_LEN, G2_LEN])?;
+        Ok((fields[0].to_vec(), fields[1].to_vec()))
+    }
+
+    fn write_card(card: &(Vec<u8>, Vec<u8>), out: &mut Vec<u8>) {
+        out.extend_from_slice(&card.0);
+        out.extend_from_slice(&card.1);
+    }
+
+    fn read_card(bytes: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PunchCardError> {
+        let fields = split_fields(bytes, &[G1_LEN, G2_LEN])?;
+        Ok((fields[0].to_vec(), fields[1].to_vec()))
+    }
+
+    //each proof is v_t || w_t || beta_z
+    fn write_proof(proof: &(PairProof, PairProof), out: &mut Vec<u8>) {
+        for part in &[&proof.0, &proof.1] {
+            out.extend_from_slice(&part.v_t);
+            out.extend_from_slice(&part.w_t);
+            out.extend_from_slice(&part.beta_z);
+        }
+    }
+
+    fn read_proof(bytes: &[u8]) -> Result<(PairProof, PairProof), PunchCardError> {
+        let fields = split_fields(bytes, &[G1_LEN, G1_LEN, FR_LEN, G2_LEN, G2_LEN, FR_LEN])?;
+        let proof1 = PairProof {
+            v_t: fields[0].to_vec(),
+            w_t: fields[1].to_vec(),
+            beta_z: fields[2].to_vec(),
+        };
+        let proof2 = PairProof {
+            v_t: fields[3].to_vec(),
+            w_t: fields[4].to_vec(),
+            beta_z: fields[5].to_vec(),
+        };
+        Ok((proof1, proof2))
+    }
+
+    //card_secret1 || card_secret2 || num_punches (u32 little endian) || merged card || second pairing
+    fn write_redeem_token(token: &PairRedeemToken, out: &mut Vec<u8>) {
+        out.extend_from_slice(&token.card_secret1);
+        out.extend_from_slice(&token.card_secret2);
+        out.extend_from_slice(&token.num_punches.to_le_bytes());
+        out.extend_from_slice(&token.card);
+        out.extend_from_slice(&token.card2);
+    }
+
+    fn read_redeem_token(bytes: &[u8]) -> Result<PairRedeemToken, PunchCardError> {
+        let fields = split_fields(bytes, &[32, 32, 4, FQ12_LEN, FQ12_LEN])?;
+        Ok(PairRedeemToken {
+            card_secret1: read_array32(fields[0])?,
+            card_secret2: read_array32(fields[1])?,
+            card: fields[3].to_vec(),
+            card2: fields[4].to_vec(),
+            num_punches: u32::from_le_bytes([fields[2][0], fields[2][1], fields[2][2], fields[2][3]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a card punched punches times
+    fn punched(server: &PairServerData, punches: u32) -> PairPunchCard {
+        let (mut card1, mut card2, mut client) = PairPunchCard::card_setup().unwrap();
+        for _ in 0..punches {
+            let (new_card1, new_card2, proof1, proof2) = server.pair_server_punch(&card1, &card2).unwrap();
+            let cards = client.verify_remask(new_card1, new_card2, &server.pub_secret_g1, &server.pub_secret_g2, proof1, proof2).unwrap();
+            card1 = cards.0;
+            card2 = cards.1;
+        }
+        client
+    }
+
+    fn merge(server: &PairServerData, punches: &[u32]) -> PairMergeToken {
+        PairPunchCard::pair_unmask_merge(punches.iter().map(|n| punched(server, *n)).collect()).unwrap()
+    }
+
+    #[test]
+    fn merges_any_number_of_cards() {
+        let mut server = PairServerData::pair_server_setup().unwrap();
+
+        //odd number of cards, the last is paired with itself
+        let token = merge(&server, &[2, 3, 4]);
+        assert_eq!(token.counts, vec![5, 4]);
+        server.pair_server_verify_merged(&token, 9).unwrap();
+
+        let token = merge(&server, &[1, 0, 2, 3]);
+        assert_eq!(server.pair_server_verify_merged(&token, 7), Err(PunchCardError::InvalidCard));
+        server.pair_server_verify_merged(&token, 6).unwrap();
+        assert_eq!(server.pair_count_cards(), 7);
+
+        //claiming more punches for a pair than it carries
+        let mut token = merge(&server, &[1, 1, 1]);
+        token.counts = vec![3, 0];
+        assert_eq!(server.pair_server_verify_merged(&token, 3), Err(PunchCardError::InvalidCard));
+
+        assert_eq!(PairPunchCard::pair_unmask_merge(vec![punched(&server, 1)]).err(),
+            Some(PunchCardError::WrongCardCount { expected: 2, got: 1 }));
+    }
+
+    //the two cards of a redemption can hold any split of the punches
+    #[test]
+    fn merges_unequal_counts() {
+        let mut server = PairServerData::pair_server_setup().unwrap();
+        for (punches1, punches2) in [(3, 7), (7, 3), (0, 10), (10, 0), (1, 2)] {
+            let mut first = punched(&server, punches1);
+            let token = first.pair_unmask_redeem(punched(&server, punches2)).unwrap();
+            assert_eq!(token.num_punches, punches1 + punches2);
+            assert_eq!(<PairingScheme>::server_verify(&mut server, token, punches1 + punches2), Ok(()));
+        }
+
+        //the total has to be the one asked for, and has to be what the card really holds
+        let mut first = punched(&server, 3);
+        let token = first.pair_unmask_redeem(punched(&server, 7)).unwrap();
+        assert_eq!(<PairingScheme>::server_verify(&mut server, token, 9), Err(PunchCardError::InvalidCard));
+        let mut first = punched(&server, 3);
+        let mut token = first.pair_unmask_redeem(punched(&server, 6)).unwrap();
+        token.num_punches = 10;
+        assert_eq!(<PairingScheme>::server_verify(&mut server, token, 10), Err(PunchCardError::InvalidCard));
+    }
+
+    //a redemption where the first card's G2 half doesn't match its G1 half
+    //Single never looks at that half, the other checks catch it
+    #[test]
+    fn check_modes() {
+        for check in [PairCheck::Single, PairCheck::Both, PairCheck::Product] {
+            let mut server = PairServerData::pair_server_setup().unwrap();
+            server.pair_set_check(check);
+            let mut first = punched(&server, 2);
+            let token = first.pair_unmask_redeem(punched(&server, 3)).unwrap();
+            assert_eq!(<PairingScheme>::server_verify(&mut server, token, 5), Ok(()));
+
+            let mut first = punched(&server, 2);
+            first.g2card.punch_card.mul_assign(Fr::random(&mut OsRng));
+            let token = first.pair_unmask_redeem(punched(&server, 3)).unwrap();
+            let expected = if check == PairCheck::Single { Ok(()) } else { Err(PunchCardError::InvalidCard) };
+            assert_eq!(<PairingScheme>::server_verify(&mut server, token, 5), expected);
+        }
+    }
+
+    //card 0's G1 half punched together with card 1's G2 half, k times, with both cards claiming k punches
+    //the first pairing then carries 2k punches although only k were given out
+    #[test]
+    fn merged_check_modes() {
+        assert_eq!(PairCheck::default(), PairCheck::Both);
+        let k = 3;
+        for check in [PairCheck::Single, PairCheck::Both, PairCheck::Product] {
+            let mut server = PairServerData::pair_server_setup().unwrap();
+            server.pair_set_check(check);
+            let mut cards = vec![punched(&server, 0), punched(&server, 0), punched(&server, 1)];
+            let punches = server.secret.pow([k as u64]);
+            cards[0].g1card.punch_card.mul_assign(punches);
+            cards[1].g2card.punch_card.mul_assign(punches);
+            for card in cards.iter_mut().take(2) {
+                card.g1card.count = k;
+                card.g2card.count = k;
+            }
+            let token = PairPunchCard::pair_unmask_merge(cards).unwrap();
+            assert_eq!(token.cards2.len(), 1);
+            let expected = if check == PairCheck::Single { Ok(()) } else { Err(PunchCardError::InvalidCard) };
+            assert_eq!(server.pair_server_verify_merged(&token, 2 * k + 1), expected);
+
+            //honest cards pass every check, and the second pairings can't be left out
+            let mut token = merge(&server, &[2, 1, 1]);
+            token.cards2.clear();
+            assert_eq!(server.pair_server_verify_merged(&token, 4), Err(PunchCardError::BadLength { expected: 1, got: 0 }));
+            server.pair_server_verify_merged(&merge(&server, &[2, 1, 1]), 4).unwrap();
+        }
+    }
+
+    #[test]
+    fn merchant_bound() {
+        let secret = Fr::random(&mut OsRng);
+        let shop1 = PairServerData::pair_from_secret(secret, 1, MemoryNullifierStore::new(), BLS12381G1_DST, BLS12381G2_DST).unwrap();
+        let mut shop2 = PairServerData::pair_from_secret(secret, 2, MemoryNullifierStore::new(), BLS12381G1_DST, BLS12381G2_DST).unwrap();
+        let (pub_g1, pub_g2) = (shop1.pub_secret_g1.clone(), shop1.pub_secret_g2.clone());
+
+        let (card1, card2, mut client) = PairPunchCard::card_setup_for(1).unwrap();
+        let (new_card1, new_card2, proof1, proof2) = shop2.pair_server_punch(&card1, &card2).unwrap();
+        assert_eq!(client.verify_remask(new_card1, new_card2, &pub_g1, &pub_g2, proof1, proof2).err(), Some(PunchCardError::BadProof));
+        let (new_card1, new_card2, proof1, proof2) = shop1.pair_server_punch(&card1, &card2).unwrap();
+        client.verify_remask(new_card1, new_card2, &pub_g1, &pub_g2, proof1, proof2).unwrap();
+
+        let (other1, other2, mut other) = PairPunchCard::card_setup_for(1).unwrap();
+        let (new_card1, new_card2, proof1, proof2) = shop1.pair_server_punch(&other1, &other2).unwrap();
+        other.verify_remask(new_card1, new_card2, &pub_g1, &pub_g2, proof1, proof2).unwrap();
+        let token = client.pair_unmask_redeem(other).unwrap();
+        assert_eq!(<PairingScheme>::server_verify(&mut shop2, token, 2), Err(PunchCardError::InvalidCard));
+    }
+
+    #[test]
+    fn merged_cards_are_used_up() {
+        let mut server = PairServerData::pair_server_setup().unwrap();
+        let cards: Vec<PairPunchCard> = (0..3).map(|_| punched(&server, 1)).collect();
+        let saved: Vec<Vec<u8>> = cards.iter().map(|card| card.save_state().unwrap()).collect();
+        server.pair_server_verify_merged(&PairPunchCard::pair_unmask_merge(cards).unwrap(), 3).unwrap();
+
+        //the same cards again, or one of them merged with a new card
+        let copies = saved.iter().map(|state| PairPunchCard::restore_state(state).unwrap()).collect();
+        let token = PairPunchCard::pair_unmask_merge(copies).unwrap();
+        assert_eq!(server.pair_server_verify_merged(&token, 3), Err(PunchCardError::DoubleRedemption));
+        let token = PairPunchCard::pair_unmask_merge(vec![PairPunchCard::restore_state(&saved[0]).unwrap(), punched(&server, 1)]).unwrap();
+        assert_eq!(server.pair_server_verify_merged(&token, 2), Err(PunchCardError::DoubleRedemption));
+
+        //one card listed twice
+        let state = punched(&server, 1).save_state().unwrap();
+        let twice = vec![PairPunchCard::restore_state(&state).unwrap(), PairPunchCard::restore_state(&state).unwrap(), punched(&server, 0)];
+        let token = PairPunchCard::pair_unmask_merge(twice).unwrap();
+        assert_eq!(server.pair_server_verify_merged(&token, 2), Err(PunchCardError::DoubleRedemption));
+        assert_eq!(server.pair_count_cards(), 3);
+    }
+
+    //every card of a merge is checked against the expiry policy
+    #[test]
+    fn merged_cards_expire() {
+        let mut server = PairServerData::pair_server_setup().unwrap();
+        server.pair_set_expiry_policy(Some(ExpiryPolicy::new(5, 2)));
+        let (_, _, expired) = PairPunchCard::card_setup_until(DEFAULT_MERCHANT, 4).unwrap();
+        let (_, _, current) = PairPunchCard::card_setup_until(DEFAULT_MERCHANT, 5).unwrap();
+        let token = PairPunchCard::pair_unmask_merge(vec![current, expired]).unwrap();
+        assert_eq!(server.pair_server_verify_merged(&token, 0), Err(PunchCardError::Expired(4)));
+        assert_eq!(server.pair_count_cards(), 0);
+    }
+
+    //a receipt proves which pairing is wrong, and an audit catches outcomes or evidence that don't match
+    #[test]
+    fn audited_redemption() {
+        let mut server = PairServerData::pair_server_setup().unwrap();
+        let powers = server.pair_power_key(2).unwrap();
+        powers.verify(&server.pub_secret_g1, &server.pub_secret_g2).unwrap();
+        let token = |server: &PairServerData| punched(server, 1).pair_unmask_redeem(punched(server, 1)).unwrap();
+
+        //only the second pairing is wrong
+        let mut bad = token(&server);
+        bad.card2 = token(&server).card2;
+        let mut rejected = *server.pair_server_verify_audited(bad, 2).unwrap_err().receipt.unwrap();
+        assert!(matches!(rejected.evidence, PairRedemptionEvidence::Invalid(1, _)));
+        rejected.audit(&powers).unwrap();
+        let mut receipt = server.pair_server_verify_audited(token(&server), 2).unwrap();
+        receipt.audit(&powers).unwrap();
+
+        //rejecting a valid card, taking an invalid one, or proving the wrong thing
+        receipt.outcome = Err(PunchCardError::InvalidCard);
+        assert_eq!(receipt.audit(&powers), Err(PunchCardError::DishonestRedemption));
+        rejected.outcome = Ok(());
+        assert_eq!(rejected.audit(&powers), Err(PunchCardError::DishonestRedemption));
+        receipt.evidence = rejected.evidence;
+        assert_eq!(receipt.audit(&powers), Err(PunchCardError::BadProof));
+
+        //a card outside the target group isn't a pairing of anything, there's nothing to audit
+        let mut bad = token(&server);
+        let mut outside = Fq12::one();
+        outside.double();
+        bad.card = serialize_elem(&outside).unwrap();
+        let rejection = server.pair_server_verify_audited(bad, 2).unwrap_err();
+        assert_eq!(rejection.error, PunchCardError::MalformedPoint);
+        assert!(rejection.receipt.is_none());
+        assert_eq!(server.pair_count_cards(), 2);
+
+        //a mismatch proof for a pairing that does match doesn't verify
+        let valid = token(&server);
+        let (exponent, pub_power_g1, _) = server.pair_secret_power(2).unwrap();
+        let [(g1_part, g2_part), _] = redemption_halves(DEFAULT_MERCHANT, &valid.card_secret1, &valid.card_secret2, BLS12381G1_DST, BLS12381G2_DST);
+        let (base, card) = (Bls12::pairing(g1_part, g2_part), deserialize_point::<Fq12>(&valid.card).unwrap());
+        let forged = pair_mismatch_prove(DEFAULT_MERCHANT, DEFAULT_EPOCH, &base, &card, exponent, &pub_power_g1).unwrap();
+        assert_eq!(pair_mismatch_verify(DEFAULT_MERCHANT, DEFAULT_EPOCH, &pub_power_g1, &base, &card, &forged),
+                   Err(PunchCardError::BadProof));
+    }
+}
@@ -0,0 +1,224 @@
+//Key rotation: a server with one secret per epoch
+//a card belongs to the epoch whose secret punched it, and the client says which epoch that is with every request
+//the current epoch and the `grace` epochs before it accept punches and redemptions, older ones are forgotten
+//
+//migration moves a card from an old epoch to the current one: the old card is redeemed in its own epoch
+//(so its secret is recorded as used there) and a fresh card from the client is punched with the same count
+//by the current secret in one go, proved with server_punch_by against the current epoch's published powers
+//the count is revealed, as it is at any redemption, but the fresh card is unlinkable to the old one
+//for the pairing scheme the old cards are migrated two at a time, like they are redeemed,
+//and the fresh card holds both their punches
+//
+//every proof is bound to the number of the epoch it was made in (see transcript.rs), so a client sets its card's
+//epoch with set_card_epoch before it checks punches from that epoch
+
+use std::collections::BTreeMap;
+use super::error::PunchCardError;
+use super::scheme::{PunchCardScheme, Punched};
+use super::expiry::ExpiryPolicy;
+
+//the epoch of servers and cards that never had one set, e.g. outside an EpochServer
+pub const DEFAULT_EPOCH: u32 = 0;
+
+//everything the server keeps for one epoch
+struct Epoch<S: PunchCardScheme> {
+	pub_key: S::PublicKey,
+	powers: S::PowerKey,
+	server: S::Server,
+}
+
+pub struct EpochServer<S: PunchCardScheme> {
+	epochs: BTreeMap<u32, Epoch<S>>,
+	current: u32,
+	//how many epochs before the current one are still accepted
+	grace: usize,
+	//largest count a card can be migrated with (the powers published for each epoch go up to this)
+	max_migrate: u32,
+	//given to every epoch's server (see expiry.rs)
+	expiry: Option<ExpiryPolicy>,
+}
+
+impl<S: PunchCardScheme> EpochServer<S> {
+
+	//start at epoch with a fresh secret that records redeemed cards in store
+	pub fn new(epoch: u32, grace: usize, max_migrate: u32, store: S::Store) -> Result<EpochServer<S>, PunchCardError> {
+		let mut server = EpochServer {
+			epochs: BTreeMap::new(),
+			current: epoch,
+			grace,
+			max_migrate,
+			expiry: None,
+		};
+		server.epochs.insert(epoch, Self::new_epoch(epoch, max_migrate, None, store)?);
+		Ok(server)
+	}
+
+	fn new_epoch(epoch: u32, max_migrate: u32, expiry: Option<ExpiryPolicy>, store: S::Store) -> Result<Epoch<S>, PunchCardError> {
+		let (pub_key, mut server) = S::server_setup_with_store(store)?;
+		S::set_epoch(&mut server, epoch);
+		S::set_expiry_policy(&mut server, expiry);
+		let powers = S::publish_powers(&server, max_migrate)?;
+		Ok(Epoch {
+			pub_key,
+			powers,
+			server,
+		})
+	}
+
+	//switch to a new secret for epoch, which must come after the current one
+	//epochs that fall out of the grace period are dropped
+	pub fn rotate(&mut self, epoch: u32, store: S::Store) -> Result<(), PunchCardError> {
+		if epoch <= self.current {
+			return Err(PunchCardError::StaleEpoch(epoch));
+		}
+		self.epochs.insert(epoch, Self::new_epoch(epoch, self.max_migrate, self.expiry, store)?);
+		self.current = epoch;
+		let expired: Vec<u32> = self.epochs.keys().copied()
+			.take(self.epochs.len().saturating_sub(self.grace + 1))
+			.collect();
+		for old in expired {
+			self.epochs.remove(&old);
+		}
+		Ok(())
+	}
+
+	//check the cards every epoch takes against policy from now on, including epochs rotated to later
+	pub fn set_expiry_policy(&mut self, policy: Option<ExpiryPolicy>) {
+		self.expiry = policy;
+		for epoch in self.epochs.values_mut() {
+			S::set_expiry_policy(&mut epoch.server, policy);
+		}
+	}
+
+	pub fn current_epoch(&self) -> u32 {
+		self.current
+	}
+
+	//epochs that are still accepted, oldest first
+	pub fn epochs(&self) -> Vec<u32> {
+		self.epochs.keys().copied().collect()
+	}
+
+	fn epoch(&self, epoch: u32) -> Result<&Epoch<S>, PunchCardError> {
+		self.epochs.get(&epoch).ok_or(PunchCardError::UnknownEpoch(epoch))
+	}
+
+	fn epoch_mut(&mut self, epoch: u32) -> Result<&mut Epoch<S>, PunchCardError> {
+		self.epochs.get_mut(&epoch).ok_or(PunchCardError::UnknownEpoch(epoch))
+	}
+
+	//what clients check punches in epoch against
+	pub fn public_key(&self, epoch: u32) -> Result<&S::PublicKey, PunchCardError> {
+		Ok(&self.epoch(epoch)?.pub_key)
+	}
+
+	//what clients check migrated cards against (see PunchCardScheme::verify_powers)
+	pub fn powers(&self, epoch: u32) -> Result<&S::PowerKey, PunchCardError> {
+		Ok(&self.epoch(epoch)?.powers)
+	}
+
+	pub fn server_punch(&self, epoch: u32, card: &S::Card) -> Result<Punched<S>, PunchCardError> {
+		S::server_punch(&self.epoch(epoch)?.server, card)
+	}
+
+	pub fn server_verify(&mut self, epoch: u32, token: S::RedeemToken, num_punches: u32) -> Result<(), PunchCardError> {
+		S::server_verify(&mut self.epoch_mut(epoch)?.server, token, num_punches)
+	}
+
+	//redeem the token's card(s) in their old epoch and punch fresh_card num_punches times in the current epoch
+	//returns None if there were no punches to move
+	//the client checks the result with verify_remask_by against powers(current_epoch())
+	pub fn migrate(&mut self, old_epoch: u32, token: S::RedeemToken, num_punches: u32,
+				   fresh_card: &S::Card) -> Result<Option<Punched<S>>, PunchCardError> {
+		if num_punches > self.max_migrate {
+			return Err(PunchCardError::InvalidPunchCount(num_punches));
+		}
+		self.epoch(old_epoch)?;
+		//punch the fresh card first so a bad one doesn't cost the client the old card
+		let punched = if num_punches > 0 {
+			Some(S::server_punch_by(&self.epoch(self.current)?.server, fresh_card, num_punches)?)
+		} else {
+			None
+		};
+		self.server_verify(old_epoch, token, num_punches)?;
+		Ok(punched)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::scheme::testing::{both_schemes, punch_more, punched_cards};
+
+	//CARDS_PER_REDEMPTION cards punched `punches` times in total in epoch
+	fn epoch_cards<S: PunchCardScheme>(server: &EpochServer<S>, epoch: u32, punches: u32) -> Vec<S::Client> {
+		let setup = || S::card_setup().map(|(card, mut client)| {
+			S::set_card_epoch(&mut client, epoch);
+			(card, client)
+		});
+		punched_cards::<S>(punches, server.public_key(epoch).unwrap(), setup, |card| server.server_punch(epoch, card))
+	}
+
+	fn rotation<S: PunchCardScheme>() where S::Store: Default {
+		let mut server = EpochServer::<S>::new(1, 1, 20, S::Store::default()).unwrap();
+		let old_cards = epoch_cards(&server, 1, 3 * S::CARDS_PER_REDEMPTION);
+		let spare_cards = epoch_cards(&server, 1, S::CARDS_PER_REDEMPTION);
+
+		//epoch 1 is still in its grace period
+		server.rotate(2, S::Store::default()).unwrap();
+		assert_eq!(server.epochs(), vec![1, 2]);
+		assert_eq!(server.rotate(2, S::Store::default()), Err(PunchCardError::StaleEpoch(2)));
+
+		//move the old cards to epoch 2
+		let total = 3 * S::CARDS_PER_REDEMPTION;
+		let (fresh_card, mut fresh) = S::card_setup().unwrap();
+		S::set_card_epoch(&mut fresh, 2);
+		let token = S::unmask_redeem(old_cards).unwrap();
+		let (card, proof) = server.migrate(1, token, total, &fresh_card).unwrap().unwrap();
+		let powers = server.powers(2).unwrap();
+		S::verify_powers(server.public_key(2).unwrap(), powers).unwrap();
+		S::verify_remask_by(&mut fresh, card, powers, total, proof).unwrap();
+
+		//a punch from epoch 2 doesn't check on a card that thinks it's in epoch 1
+		let (card, mut stale) = S::card_setup().unwrap();
+		S::set_card_epoch(&mut stale, 1);
+		let (new_card, proof) = server.server_punch(2, &card).unwrap();
+		assert_eq!(S::verify_remask(&mut stale, new_card, server.public_key(2).unwrap(), proof).err(), Some(PunchCardError::BadProof));
+
+		//the migrated card keeps collecting punches in epoch 2 and redeems there
+		punch_more::<S>(&mut fresh, 1, server.public_key(2).unwrap(), &mut |card| server.server_punch(2, card));
+		let mut clients = vec![fresh];
+		for _ in 1..S::CARDS_PER_REDEMPTION {
+			clients.push(S::card_setup().unwrap().1);
+		}
+		server.server_verify(2, S::unmask_redeem(clients).unwrap(), total + 1).unwrap();
+
+		//once epoch 1 is out of its grace period its cards are no longer accepted
+		server.rotate(3, S::Store::default()).unwrap();
+		assert_eq!(server.epochs(), vec![2, 3]);
+		assert_eq!(server.server_verify(1, S::unmask_redeem(spare_cards).unwrap(), S::CARDS_PER_REDEMPTION).err(),
+			Some(PunchCardError::UnknownEpoch(1)));
+	}
+
+	//an old card that was migrated can't also be redeemed in its old epoch
+	fn no_double_migration<S: PunchCardScheme>() where S::Store: Default {
+		let mut server = EpochServer::<S>::new(1, 1, 20, S::Store::default()).unwrap();
+		let old_cards = epoch_cards(&server, 1, 2 * S::CARDS_PER_REDEMPTION);
+		let saved: Vec<Vec<u8>> = old_cards.iter().map(|c| S::save_client(c).unwrap()).collect();
+		server.rotate(2, S::Store::default()).unwrap();
+
+		let (fresh_card, _) = S::card_setup().unwrap();
+		let total = 2 * S::CARDS_PER_REDEMPTION;
+		server.migrate(1, S::unmask_redeem(old_cards).unwrap(), total, &fresh_card).unwrap();
+
+		let copies = saved.iter().map(|s| S::restore_client(s).unwrap()).collect();
+		assert_eq!(server.server_verify(1, S::unmask_redeem(copies).unwrap(), total).err(), Some(PunchCardError::DoubleRedemption));
+	}
+
+	fn rotation_and_migration<S: PunchCardScheme>() where S::Store: Default {
+		rotation::<S>();
+		no_double_migration::<S>();
+	}
+
+	both_schemes!(rotation_and_migration, group_rotation, pairing_rotation);
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::io;
+
+//everything that can go wrong in the punch card protocols
+//inputs from the other party are never trusted, so bad bytes end up here instead of in a panic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PunchCardError {
+	//bytes did not decode to a valid group element
+	MalformedPoint,
+	//bytes did not decode to a canonical scalar
+	MalformedScalar,
+	//a group element could not be encoded
+	Serialization,
+	//Chaum-Pedersen proof did not verify
+	BadProof,
+	//proof at this position in a batch did not verify (or did not decode)
+	BadProofAt(usize),
+	//card does not match the card secret and number of punches
+	InvalidCard,
+	//card secret was already redeemed
+	DoubleRedemption,
+	//the parts of a card disagree on how many punches they hold
+	CountMismatch,
+	//wrong number of cards given for a redemption
+	WrongCardCount { expected: usize, got: usize },
+	//can't punch by this many at once (zero, past the published powers, or the count would overflow)
+	InvalidPunchCount(u32),
+	//no reward tier is redeemed at this many punches
+	UnknownReward(u32),
+	//two reward tiers cost the same number of punches
+	DuplicateReward(u32),
+	//card doesn't hold enough punches for the reward claimed
+	NotEnoughPunches { needed: u32, got: u32 },
+	//epoch is not one the server accepts (never existed or past its grace period)
+	UnknownEpoch(u32),
+	//a new epoch has to come after the current one
+	StaleEpoch(u32),
+	//card expired after this epoch
+	Expired(u32),
+	//card claims to expire further ahead than the server allows
+	ExpiryTooFar(u32),
+	//card was made without an expiry and the server requires one
+	NoExpiry,
+	//no merchant with this ID is registered
+	UnknownMerchant(u32),
+	//a merchant with this ID is already registered
+	DuplicateMerchant(u32),
+	//a threshold has to be at least 1 and at most the number of signers
+	BadThreshold { threshold: u32, signers: u32 },
+	//fewer signers took part than the threshold (or than started the punch)
+	NotEnoughSigners { needed: u32, got: u32 },
+	//no signer with this index holds a share
+	UnknownSigner(u32),
+	//the same signer took part twice
+	DuplicateSigner(u32),
+	//a redemption receipt's outcome doesn't follow from its expected card
+	DishonestRedemption,
+	//a mask could not be inverted (it was zero)
+	NotInvertible,
+	//expand_message_xmd can't produce this many bytes (see hash_to_curve.rs)
+	ExpandTooLong(usize),
+	//wire message is shorter or longer than its type requires
+	BadLength { expected: usize, got: usize },
+	//wire message was encoded with a version we don't speak
+	UnsupportedVersion(u8),
+	//wire message belongs to the other backend
+	WrongScheme(u8),
+	//wire message is a different kind of message than expected
+	WrongMessageType(u8),
+	//reading or writing persistent state failed
+	Io(io::ErrorKind),
+	//persistent state exists but is damaged or not ours
+	CorruptStore,
+}
+
+impl fmt::Display for PunchCardError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PunchCardError::MalformedPoint => write!(f, "malformed group element"),
+			PunchCardError::MalformedScalar => write!(f, "malformed scalar"),
+			PunchCardError::Serialization => write!(f, "couldn't serialize group element"),
+			PunchCardError::BadProof => write!(f, "proof did not verify"),
+			PunchCardError::BadProofAt(i) => write!(f, "proof {} in the batch did not verify", i),
+			PunchCardError::InvalidCard => write!(f, "card is not valid for this number of punches"),
+			PunchCardError::DoubleRedemption => write!(f, "card was already redeemed"),
+			PunchCardError::CountMismatch => write!(f, "card counts misaligned"),
+			PunchCardError::WrongCardCount { expected, got } =>
+				write!(f, "expected {} cards for redemption, got {}", expected, got),
+			PunchCardError::InvalidPunchCount(k) => write!(f, "can't punch a card {} times at once", k),
+			PunchCardError::UnknownReward(n) => write!(f, "no reward for {} punches", n),
+			PunchCardError::DuplicateReward(n) => write!(f, "more than one reward for {} punches", n),
+			PunchCardError::NotEnoughPunches { needed, got } =>
+				write!(f, "reward needs {} punches, card has {}", needed, got),
+			PunchCardError::UnknownEpoch(e) => write!(f, "epoch {} is not accepted", e),
+			PunchCardError::StaleEpoch(e) => write!(f, "epoch {} is not after the current epoch", e),
+			PunchCardError::Expired(e) => write!(f, "card expired after epoch {}", e),
+			PunchCardError::ExpiryTooFar(e) => write!(f, "card expiry epoch {} is too far ahead", e),
+			PunchCardError::NoExpiry => write!(f, "card has no expiry"),
+			PunchCardError::UnknownMerchant(m) => write!(f, "unknown merchant {}", m),
+			PunchCardError::DuplicateMerchant(m) => write!(f, "merchant {} is already registered", m),
+			PunchCardError::BadThreshold { threshold, signers } =>
+				write!(f, "can't share a secret {} of {} ways", threshold, signers),
+			PunchCardError::NotEnoughSigners { needed, got } =>
+				write!(f, "needs {} signers, got {}", needed, got),
+			PunchCardError::UnknownSigner(i) => write!(f, "unknown signer {}", i),
+			PunchCardError::DuplicateSigner(i) => write!(f, "signer {} took part twice", i),
+			PunchCardError::DishonestRedemption => write!(f, "redemption outcome doesn't match the proven card"),
+			PunchCardError::NotInvertible => write!(f, "mask is not invertible"),
+			PunchCardError::ExpandTooLong(len) => write!(f, "can't expand a message to {} bytes", len),
+			PunchCardError::BadLength { expected, got } =>
+				write!(f, "message should be {} bytes, got {}", expected, got),
+			PunchCardError::UnsupportedVersion(v) => write!(f, "unsupported wire format version {}", v),
+			PunchCardError::WrongScheme(tag) => write!(f, "message is for another scheme (tag {})", tag),
+			PunchCardError::WrongMessageType(t) => write!(f, "unexpected message type {}", t),
+			PunchCardError::Io(kind) => write!(f, "i/o error: {:?}", kind),
+			PunchCardError::CorruptStore => write!(f, "stored data is corrupt"),
+		}
+	}
+}
+
+impl std::error::Error for PunchCardError {}
+
+//an audited redemption the server turned down (see ServerData::server_verify_audited)
+//error is the decision and receipt the evidence for it, None for failures that leave nothing to audit
+//(an expired card, bytes that don't decode)
+//it converts into its error, so a caller that uses ? treats it like any other failed redemption
+#[derive(Debug)]
+pub struct AuditedRejection<R> {
+	pub error: PunchCardError,
+	pub receipt: Option<Box<R>>,
+}
+
+impl<R> fmt::Display for AuditedRejection<R> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.receipt {
+			Some(_) => write!(f, "{} (with receipt)", self.error),
+			None => write!(f, "{}", self.error),
+		}
+	}
+}
+
+impl<R: fmt::Debug> std::error::Error for AuditedRejection<R> {}
+
+impl<R> From<PunchCardError> for AuditedRejection<R> {
+	fn from(error: PunchCardError) -> AuditedRejection<R> {
+		AuditedRejection {
+			error,
+			receipt: None,
+		}
+	}
+}
+
+impl<R> From<AuditedRejection<R>> for PunchCardError {
+	fn from(rejection: AuditedRejection<R>) -> PunchCardError {
+		rejection.error
+	}
+}
+
+impl From<io::Error> for PunchCardError {
+	fn from(err: io::Error) -> PunchCardError {
+		PunchCardError::Io(err.kind())
+	}
+}
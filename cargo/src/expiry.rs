@@ -0,0 +1,213 @@
+//Cards that expire, so the redeemed card db doesn't grow forever
+//a card's expiry epoch is written into the last 4 bytes (little endian) of its secret when the card is made,
+//after an 8 byte marker saying the secret carries one (the first 20 bytes are random),
+//so it's bound to the card by the same hash that binds the secret, and it can be read back from the nullifiers the server stores
+//secrets without the marker (plain card_setup, cheat_setup_db) don't expire, whatever their last bytes are
+//
+//a server given an ExpiryPolicy (PunchCardScheme::set_expiry_policy) checks it everywhere it takes a card secret:
+//redemptions, merges and audited redemptions refuse cards that have expired, cards that claim to expire further ahead
+//than a card can live, and (unless require_expiry is turned off) cards made without an expiry,
+//since the client picks its own card secret and could otherwise leave the marker out to stay in the db for good
+//once a card's epoch has passed the server can never take it again, so its nullifier can be pruned,
+//and so can the nullifiers without the marker when the policy requires one
+
+use rand_core::{RngCore, OsRng};
+use super::error::PunchCardError;
+use super::scheme::PunchCardScheme;
+use super::nullifier::NullifierStore;
+
+const EXPIRY_MAGIC: &[u8; 8] = b"PCEXPIRY";
+const MAGIC_OFFSET: usize = 20;
+const EXPIRY_OFFSET: usize = MAGIC_OFFSET + EXPIRY_MAGIC.len();
+
+//a random card secret that expires after epoch expiry
+pub fn secret_until(expiry: u32) -> [u8; 32] {
+	let mut card_secret = [0u8; 32];
+	OsRng.fill_bytes(&mut card_secret[..MAGIC_OFFSET]);
+	card_secret[MAGIC_OFFSET..EXPIRY_OFFSET].copy_from_slice(EXPIRY_MAGIC);
+	card_secret[EXPIRY_OFFSET..].copy_from_slice(&expiry.to_le_bytes());
+	card_secret
+}
+
+//the last epoch a card secret (or nullifier) can be redeemed in, None if it was made without an expiry
+pub fn expiry_of(card_secret: &[u8; 32]) -> Option<u32> {
+	if &card_secret[MAGIC_OFFSET..EXPIRY_OFFSET] != EXPIRY_MAGIC {
+		return None;
+	}
+	let mut expiry = [0u8; 4];
+	expiry.copy_from_slice(&card_secret[EXPIRY_OFFSET..]);
+	Some(u32::from_le_bytes(expiry))
+}
+
+//which cards the server accepts right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiryPolicy {
+	pub current_epoch: u32,
+	//cards may expire at most this many epochs after the current one
+	pub max_lifetime: u32,
+	//refuse cards made without an expiry, on for policies made with new
+	//with it off those cards are taken and their nullifiers are never pruned
+	pub require_expiry: bool,
+}
+
+impl ExpiryPolicy {
+
+	//a policy that only takes cards with an expiry
+	pub fn new(current_epoch: u32, max_lifetime: u32) -> ExpiryPolicy {
+		ExpiryPolicy {
+			current_epoch,
+			max_lifetime,
+			require_expiry: true,
+		}
+	}
+
+	//Expired if the card's epoch has passed, ExpiryTooFar if it lives longer than the policy allows,
+	//NoExpiry if it has none and the policy requires one
+	pub fn check(&self, card_secret: &[u8; 32]) -> Result<(), PunchCardError> {
+		let expiry = match expiry_of(card_secret) {
+			Some(expiry) => expiry,
+			None if self.require_expiry => return Err(PunchCardError::NoExpiry),
+			None => return Ok(()),
+		};
+		if expiry < self.current_epoch {
+			return Err(PunchCardError::Expired(expiry));
+		}
+		if expiry - self.current_epoch > self.max_lifetime {
+			return Err(PunchCardError::ExpiryTooFar(expiry));
+		}
+		Ok(())
+	}
+
+	//whether a nullifier still has to be kept, i.e. the card it came from could still pass check
+	pub fn keeps(&self, nullifier: &[u8; 32]) -> bool {
+		match expiry_of(nullifier) {
+			Some(expiry) => expiry >= self.current_epoch,
+			None => !self.require_expiry,
+		}
+	}
+}
+
+//check card secrets a server is about to take against its policy, if it has one
+pub(crate) fn check_expiry(policy: Option<ExpiryPolicy>, card_secrets: &[[u8; 32]]) -> Result<(), PunchCardError> {
+	match policy {
+		Some(policy) => card_secrets.iter().try_for_each(|card_secret| policy.check(card_secret)),
+		None => Ok(()),
+	}
+}
+
+//drop the nullifiers of cards the server's expiry policy can no longer take, see ExpiryPolicy::keeps
+//the server checks the policy before taking any card, so those cards can't be double spent
+//a server without a policy takes any card, so nothing is dropped
+//returns how many were dropped
+pub fn prune_expired<S: PunchCardScheme>(server: &mut S::Server) -> Result<usize, PunchCardError> {
+	let policy = match S::expiry_policy(server) {
+		Some(policy) => policy,
+		None => return Ok(0),
+	};
+	S::store_mut(server).retain(&mut |nullifier| policy.keeps(nullifier))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::scheme::testing::{both_schemes, punched_cards};
+
+	//CARDS_PER_REDEMPTION cards expiring after expiry, punched once each
+	fn expiring_cards<S: PunchCardScheme>(server: &S::Server, pub_key: &S::PublicKey, expiry: u32) -> Vec<S::Client> {
+		let merchant = S::merchant(server);
+		punched_cards::<S>(S::CARDS_PER_REDEMPTION, pub_key, || S::card_setup_until(merchant, expiry), |card| S::server_punch(server, card))
+	}
+
+	//redeem cards expiring after expiry
+	fn redeem<S: PunchCardScheme>(server: &mut S::Server, pub_key: &S::PublicKey, expiry: u32) -> Result<(), PunchCardError> {
+		let token = S::unmask_redeem(expiring_cards::<S>(server, pub_key, expiry)).unwrap();
+		S::server_verify(server, token, S::CARDS_PER_REDEMPTION)
+	}
+
+	fn expiry<S: PunchCardScheme>() where S::Store: Default {
+		//expiring cards work for any merchant
+		let (pub_key, mut server) = S::server_setup_for(7, S::Store::default()).unwrap();
+		let n = S::CARDS_PER_REDEMPTION;
+		S::set_expiry_policy(&mut server, Some(ExpiryPolicy::new(5, 2)));
+
+		redeem::<S>(&mut server, &pub_key, 5).unwrap();
+		redeem::<S>(&mut server, &pub_key, 7).unwrap();
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 4), Err(PunchCardError::Expired(4)));
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 8), Err(PunchCardError::ExpiryTooFar(8)));
+		assert_eq!(S::count_cards(&server), 2 * n as usize);
+
+		//a card without an expiry is refused by default
+		let plain = punched_cards::<S>(n, &pub_key, || S::card_setup_for(7), |card| S::server_punch(&server, card));
+		assert_eq!(S::server_verify(&mut server, S::unmask_redeem(plain).unwrap(), n), Err(PunchCardError::NoExpiry));
+
+		//moving to epoch 6 prunes the cards that expired in epoch 5
+		S::set_expiry_policy(&mut server, Some(ExpiryPolicy::new(6, 2)));
+		assert_eq!(prune_expired::<S>(&mut server).unwrap(), n as usize);
+		assert_eq!(S::count_cards(&server), n as usize);
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 5), Err(PunchCardError::Expired(5)));
+	}
+
+	//a card whose nullifier was pruned can't be taken a second time through plain server_verify
+	fn pruned_stays_spent<S: PunchCardScheme>() where S::Store: Default {
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		S::set_expiry_policy(&mut server, Some(ExpiryPolicy::new(5, 2)));
+		let clients = expiring_cards::<S>(&server, &pub_key, 5);
+		let saved: Vec<Vec<u8>> = clients.iter().map(|client| S::save_client(client).unwrap()).collect();
+		S::server_verify(&mut server, S::unmask_redeem(clients).unwrap(), S::CARDS_PER_REDEMPTION).unwrap();
+
+		S::set_expiry_policy(&mut server, Some(ExpiryPolicy::new(6, 2)));
+		assert_eq!(prune_expired::<S>(&mut server).unwrap(), S::CARDS_PER_REDEMPTION as usize);
+		assert!(S::store_mut(&mut server).is_empty());
+		let copies = saved.iter().map(|state| S::restore_client(state).unwrap()).collect();
+		assert_eq!(S::server_verify(&mut server, S::unmask_redeem(copies).unwrap(), S::CARDS_PER_REDEMPTION), Err(PunchCardError::Expired(5)));
+	}
+
+	//cards without an expiry, and the filler nullifiers, survive pruning and stay spent if the policy takes them,
+	//and are pruned if it doesn't
+	fn plain_cards<S: PunchCardScheme>() where S::Store: Default {
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		S::set_expiry_policy(&mut server, Some(ExpiryPolicy { require_expiry: false, ..ExpiryPolicy::new(0, 0) }));
+		S::cheat_setup_db(&mut server, 10).unwrap();
+		let clients = punched_cards::<S>(S::CARDS_PER_REDEMPTION, &pub_key, S::card_setup, |card| S::server_punch(&server, card));
+		let token = S::unmask_redeem(clients).unwrap();
+		let nullifiers = S::nullifiers(&token);
+		for nullifier in &nullifiers {
+			assert_eq!(expiry_of(nullifier), None);
+		}
+		S::server_verify(&mut server, token, S::CARDS_PER_REDEMPTION).unwrap();
+		let stored = S::count_cards(&server);
+
+		S::set_expiry_policy(&mut server, Some(ExpiryPolicy { require_expiry: false, ..ExpiryPolicy::new(u32::MAX, 0) }));
+		assert_eq!(prune_expired::<S>(&mut server).unwrap(), 0);
+		assert_eq!(S::count_cards(&server), stored);
+		for nullifier in &nullifiers {
+			assert!(S::store_mut(&mut server).contains(nullifier));
+		}
+
+		//a server without a policy takes any card, so it keeps everything
+		S::set_expiry_policy(&mut server, None);
+		assert_eq!(prune_expired::<S>(&mut server).unwrap(), 0);
+
+		S::set_expiry_policy(&mut server, Some(ExpiryPolicy::new(0, 0)));
+		assert_eq!(prune_expired::<S>(&mut server).unwrap(), stored);
+		assert!(S::store_mut(&mut server).is_empty());
+	}
+
+	#[test]
+	fn expiry_marker() {
+		let card_secret = secret_until(9);
+		assert_eq!(expiry_of(&card_secret), Some(9));
+		//the last bytes alone don't make an expiry
+		let mut plain = [0u8; 32];
+		plain[EXPIRY_OFFSET..].copy_from_slice(&1u32.to_le_bytes());
+		assert_eq!(expiry_of(&plain), None);
+		let policy = ExpiryPolicy::new(5, 0);
+		assert_eq!(policy.check(&plain), Err(PunchCardError::NoExpiry));
+		assert_eq!(ExpiryPolicy { require_expiry: false, ..policy }.check(&plain), Ok(()));
+		assert_eq!(check_expiry(None, &[plain]), Ok(()));
+	}
+
+	both_schemes!(plain_cards, group_plain_cards, pairing_plain_cards);
+	both_schemes!(expiry, group_expiry, pairing_expiry);
+	both_schemes!(pruned_stays_spent, group_pruned_stays_spent, pairing_pruned_stays_spent);
+}
@@ -0,0 +1,422 @@
+//Hashing to the groups as RFC 9380 does it, with the domain separation tag always given explicitly
+//Ristretto255 uses hash_to_ristretto255 (RFC 9380 appendix B): expand_message_xmd with SHA-512 to 64 bytes,
+//then the Ristretto one-way map
+//BLS12-381 uses the BLS12381G1_XMD:SHA-256_SSWU_RO_ and BLS12381G2_XMD:SHA-256_SSWU_RO_ suites
+//pairing-plus implements an earlier draft of these, which differs from the RFC in two places:
+//expand_message_xmd puts the DST length before the DST instead of after it, and the SWU map picks the sign of y
+//with a different sgn0 (sign of the centered lift instead of parity)
+//so the expander here is our own, each field element is mapped on its own through pairing-plus's encode_to_curve
+//(SWU map, isogeny and cofactor clearing), and the result is negated when the RFC would have picked the other y
+//this works because the isogeny and cofactor clearing are homomorphisms, so negating before or after is the same
+//a DST longer than 255 bytes is hashed down first (RFC 9380 section 5.3.3), so any DST can be used
+//
+//cards are hashed with the DSTs below unless the server and its cards are set up with others
+//(PunchCardScheme::server_setup_with_dst and card_setup_with_dst), a deployment that picks its own can't take
+//anyone else's cards; the tests have vectors for the defaults so other implementations can check theirs
+
+use sha2::{Digest, Sha256, Sha512};
+use sha2::digest::BlockInput;
+use sha2::digest::generic_array::typenum::Unsigned;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use ff_zeroize::{Field, PrimeField, PrimeFieldRepr, SqrtField};
+use pairing_plus::CurveProjective;
+use pairing_plus::bls12_381::{Fq, Fq2, Fr, G1, G2};
+use pairing_plus::hash_to_curve::HashToCurve;
+use pairing_plus::hash_to_field::{hash_to_field, ExpandMsg, FromRO};
+use pairing_plus::signum::Signum0;
+use super::error::PunchCardError;
+
+pub const RISTRETTO255_DST: &[u8] = b"PunchCard-V01-CS01-with-ristretto255_XMD:SHA-512_R255MAP_RO_";
+pub const BLS12381G1_DST: &[u8] = b"PunchCard-V01-CS01-with-BLS12381G1_XMD:SHA-256_SSWU_RO_";
+pub const BLS12381G2_DST: &[u8] = b"PunchCard-V01-CS01-with-BLS12381G2_XMD:SHA-256_SSWU_RO_";
+
+//expand_message_xmd from RFC 9380 section 5.3.1
+//it makes at most 255 hash outputs and 65535 bytes, asking for more is an error
+pub fn expand_message_xmd<H: Digest + BlockInput>(msg: &[u8], dst: &[u8], len_in_bytes: usize) -> Result<Vec<u8>, PunchCardError> {
+	if !xmd_can_expand::<H>(len_in_bytes) {
+		return Err(PunchCardError::ExpandTooLong(len_in_bytes));
+	}
+	Ok(xmd::<H>(msg, dst, len_in_bytes))
+}
+
+//whether expand_message_xmd with H can produce len_in_bytes bytes, i.e. in at most 255 hash outputs
+fn xmd_can_expand<H: Digest>(len_in_bytes: usize) -> bool {
+	len_in_bytes <= 65535 && len_in_bytes <= 255 * H::OutputSize::to_usize()
+}
+
+//expand_message_xmd to a length fixed when compiling
+//the lengths used are all well within what xmd can make, the check is only there to catch a bad one in development
+fn xmd_fixed<H: Digest + BlockInput, const LEN: usize>(msg: &[u8], dst: &[u8]) -> [u8; LEN] {
+	debug_assert!(xmd_can_expand::<H>(LEN));
+	let mut uniform_bytes = [0u8; LEN];
+	uniform_bytes.copy_from_slice(&xmd::<H>(msg, dst, LEN));
+	uniform_bytes
+}
+
+//expand_message_xmd without the length check, len_in_bytes has to pass xmd_can_expand
+fn xmd<H: Digest + BlockInput>(msg: &[u8], dst: &[u8], len_in_bytes: usize) -> Vec<u8> {
+	let dst = reduce_dst::<H>(dst);
+	let b_in_bytes = H::OutputSize::to_usize();
+	//ceil(len_in_bytes / b_in_bytes), written out since usize::div_ceil needs Rust 1.73
+	#[allow(clippy::manual_div_ceil)]
+	let ell = (len_in_bytes + b_in_bytes - 1) / b_in_bytes;
+
+	//DST_prime = DST || I2OSP(len(DST), 1)
+	let mut dst_prime = dst.clone();
+	dst_prime.push(dst.len() as u8);
+
+	let b_0 = H::new()
+		.chain(vec![0u8; H::BlockSize::to_usize()])
+		.chain(msg)
+		.chain((len_in_bytes as u16).to_be_bytes())
+		.chain([0u8])
+		.chain(&dst_prime)
+		.result();
+
+	let mut uniform_bytes = Vec::with_capacity(ell * b_in_bytes);
+	let mut b_i = H::new().chain(&b_0).chain([1u8]).chain(&dst_prime).result();
+	uniform_bytes.extend_from_slice(&b_i);
+	for i in 2..=ell {
+		let xored: Vec<u8> = b_0.iter().zip(b_i.iter()).map(|(x, y)| x ^ y).collect();
+		b_i = H::new().chain(xored).chain([i as u8]).chain(&dst_prime).result();
+		uniform_bytes.extend_from_slice(&b_i);
+	}
+	uniform_bytes.truncate(len_in_bytes);
+	uniform_bytes
+}
+
+//dst, or H("H2C-OVERSIZE-DST-" || dst) if it's too long for expand_message_xmd
+fn reduce_dst<H: Digest>(dst: &[u8]) -> Vec<u8> {
+	if dst.len() <= 255 {
+		return dst.to_vec();
+	}
+	H::new().chain(b"H2C-OVERSIZE-DST-").chain(dst).result().to_vec()
+}
+
+pub fn hash_to_ristretto255(msg: &[u8], dst: &[u8]) -> RistrettoPoint {
+	RistrettoPoint::from_uniform_bytes(&xmd_fixed::<Sha512, 64>(msg, dst))
+}
+
+//hash_to_field(msg, 1) for the BLS12-381 scalar field with SHA-256, used for Fiat-Shamir challenges
+pub fn hash_to_fr(msg: &[u8], dst: &[u8]) -> Fr {
+	hash_to_field::<Fr, PassThrough>(&xmd_fixed::<Sha256, 48>(msg, dst), &[], 1)[0]
+}
+
+//hands already expanded bytes to pairing-plus's hash_to_field unchanged, so our expand_message_xmd is the one used
+//it's only ever given exactly the bytes hash_to_field asks for, which ExpandMsg has no way to report an error about
+pub struct PassThrough;
+
+impl ExpandMsg for PassThrough {
+	fn expand_message(msg: &[u8], _dst: &[u8], len_in_bytes: usize) -> Vec<u8> {
+		debug_assert_eq!(msg.len(), len_in_bytes);
+		msg.to_vec()
+	}
+}
+
+//a BLS12-381 group with the constants of its SWU map (on the isogenous curve y^2 = x^3 + A'x + B')
+pub trait HashToBls12381: CurveProjective + HashToCurve<PassThrough> {
+	//the base field, Fq or Fq2
+	type Field: SqrtField + Signum0 + FromRO;
+	//A', B' and Z
+	fn swu_constants() -> (Self::Field, Self::Field, Self::Field);
+	//sgn0 from RFC 9380 section 4.1
+	fn sgn0(element: &Self::Field) -> bool;
+}
+
+impl HashToBls12381 for G1 {
+	type Field = Fq;
+
+	fn swu_constants() -> (Fq, Fq, Fq) {
+		(Fq::from_str("12190336318893619529228877361869031420615612348429846051986726275283378313155663745811710833465465981901188123677").unwrap(),
+		 Fq::from_str("2906670324641927570491258158026293881577086121416628140204402091718288198173574630967936031029026176254968826637280").unwrap(),
+		 Fq::from_str("11").unwrap())
+	}
+
+	fn sgn0(element: &Fq) -> bool {
+		element.into_repr().is_odd()
+	}
+}
+
+impl HashToBls12381 for G2 {
+	type Field = Fq2;
+
+	fn swu_constants() -> (Fq2, Fq2, Fq2) {
+		let a = Fq2 { c0: Fq::zero(), c1: Fq::from_str("240").unwrap() };
+		let b = Fq2 { c0: Fq::from_str("1012").unwrap(), c1: Fq::from_str("1012").unwrap() };
+		let mut z = Fq2 { c0: Fq::from_str("2").unwrap(), c1: Fq::one() };
+		z.negate();
+		(a, b, z)
+	}
+
+	fn sgn0(element: &Fq2) -> bool {
+		<G1 as HashToBls12381>::sgn0(&element.c0) || (element.c0.is_zero() && <G1 as HashToBls12381>::sgn0(&element.c1))
+	}
+}
+
+//whether the RFC's y for the SWU map of u is the negation of the one pairing-plus picks
+//x comes from RFC 9380 section 6.6.2, and both pick a root of the same x^3 + A'x + B'
+fn swu_sign_differs<T: HashToBls12381>(u: &T::Field) -> bool {
+	let (a, b, z) = T::swu_constants();
+	let g = |x: &T::Field| {
+		let mut gx = *x;
+		gx.square();
+		gx.add_assign(&a);
+		gx.mul_assign(x);
+		gx.add_assign(&b);
+		gx
+	};
+
+	let mut z_u2 = *u;
+	z_u2.square();
+	z_u2.mul_assign(&z);
+	let mut denominator = z_u2;
+	denominator.square();
+	denominator.add_assign(&z_u2);
+
+	let mut x1 = match denominator.inverse() {
+		Some(tv1) => {
+			//-B/A * (1 + tv1)
+			let mut x1 = tv1;
+			x1.add_assign(&T::Field::one());
+			x1.mul_assign(&b);
+			x1.mul_assign(&a.inverse().unwrap());
+			x1.negate();
+			x1
+		},
+		None => {
+			//B/(Z*A)
+			let mut za = z;
+			za.mul_assign(&a);
+			let mut x1 = b;
+			x1.mul_assign(&za.inverse().unwrap());
+			x1
+		},
+	};
+	let y = match g(&x1).sqrt() {
+		Some(y) => y,
+		None => {
+			x1.mul_assign(&z_u2);
+			g(&x1).sqrt().unwrap()
+		},
+	};
+
+	let rfc_keeps = T::sgn0(&y) == T::sgn0(u);
+	let pairing_plus_keeps = y.sgn0() == u.sgn0();
+	rfc_keeps != pairing_plus_keeps
+}
+
+//hash_to_curve: u0, u1 = hash_to_field(msg, 2), then clear_cofactor(iso_map(map_to_curve(u0) + map_to_curve(u1)))
+pub fn hash_to_bls12_381<T: HashToBls12381>(msg: &[u8], dst: &[u8]) -> T {
+	let element_len = <T::Field as FromRO>::Length::to_usize();
+	//64 bytes for G1 and 128 for G2
+	debug_assert!(xmd_can_expand::<Sha256>(2 * element_len));
+	let uniform_bytes = xmd::<Sha256>(msg, dst, 2 * element_len);
+
+	let mut point = T::zero();
+	for okm in uniform_bytes.chunks(element_len) {
+		let u = hash_to_field::<T::Field, PassThrough>(okm, &[], 1)[0];
+		let mut mapped = <T as HashToCurve<PassThrough>>::encode_to_curve(okm, []);
+		if swu_sign_differs::<T>(&u) {
+			mapped.negate();
+		}
+		point.add_assign(&mapped);
+	}
+	point
+}
+
+pub fn hash_to_g1(msg: &[u8], dst: &[u8]) -> G1 {
+	hash_to_bls12_381::<G1>(msg, dst)
+}
+
+pub fn hash_to_g2(msg: &[u8], dst: &[u8]) -> G2 {
+	hash_to_bls12_381::<G2>(msg, dst)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use pairing_plus::serdes::SerDes;
+	use super::super::merchant::card_message;
+
+	fn hex(s: &str) -> Vec<u8> {
+		(0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+	}
+
+	fn uncompressed<T: SerDes>(point: &T) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		point.serialize(&mut bytes, false).unwrap();
+		bytes
+	}
+
+	//the messages RFC 9380's test vectors hash, in order
+	fn rfc_messages() -> Vec<Vec<u8>> {
+		let mut q128 = b"q128_".to_vec();
+		q128.extend([b'q'; 128]);
+		let mut a512 = b"a512_".to_vec();
+		a512.extend([b'a'; 512]);
+		vec![b"".to_vec(), b"abc".to_vec(), b"abcdef0123456789".to_vec(), q128, a512]
+	}
+
+	//RFC 9380 appendix K.1, expand_message_xmd with SHA-256
+	//(message index in rfc_messages, len_in_bytes, uniform_bytes)
+	const XMD_SHA256: &[(usize, usize, &str)] = &[
+		(0, 0x20, "68a985b87eb6b46952128911f2a4412bbc302a9d759667f87f7a21d803f07235"),
+		(1, 0x20, "d8ccab23b5985ccea865c6c97b6e5b8350e794e603b4b97902f53a8a0d605615"),
+		(2, 0x20, "eff31487c770a893cfb36f912fbfcbff40d5661771ca4b2cb4eafe524333f5c1"),
+		(3, 0x20, "b23a1d2b4d97b2ef7785562a7e8bac7eed54ed6e97e29aa51bfe3f12ddad1ff9"),
+		(4, 0x20, "4623227bcc01293b8c130bf771da8c298dede7383243dc0993d2d94823958c4c"),
+		(0, 0x80, "af84c27ccfd45d41914fdff5df25293e221afc53d8ad2ac06d5e3e29485dadbee0d121587713a3e0dd4d5e69e93eb7cd4f5df4cd103e188cf60cb02edc3edf18eda8576c412b18ffb658e3dd6ec849469b979d444cf7b26911a08e63cf31f9dcc541708d3491184472c2c29bb749d4286b004ceb5ee6b9a7fa5b646c993f0ced"),
+		(1, 0x80, "abba86a6129e366fc877aab32fc4ffc70120d8996c88aee2fe4b32d6c7b6437a647e6c3163d40b76a73cf6a5674ef1d890f95b664ee0afa5359a5c4e07985635bbecbac65d747d3d2da7ec2b8221b17b0ca9dc8a1ac1c07ea6a1e60583e2cb00058e77b7b72a298425cd1b941ad4ec65e8afc50303a22c0f99b0509b4c895f40"),
+		(2, 0x80, "ef904a29bffc4cf9ee82832451c946ac3c8f8058ae97d8d629831a74c6572bd9ebd0df635cd1f208e2038e760c4994984ce73f0d55ea9f22af83ba4734569d4bc95e18350f740c07eef653cbb9f87910d833751825f0ebefa1abe5420bb52be14cf489b37fe1a72f7de2d10be453b2c9d9eb20c7e3f6edc5a60629178d9478df"),
+		(3, 0x80, "80be107d0884f0d881bb460322f0443d38bd222db8bd0b0a5312a6fedb49c1bbd88fd75d8b9a09486c60123dfa1d73c1cc3169761b17476d3c6b7cbbd727acd0e2c942f4dd96ae3da5de368d26b32286e32de7e5a8cb2949f866a0b80c58116b29fa7fabb3ea7d520ee603e0c25bcaf0b9a5e92ec6a1fe4e0391d1cdbce8c68a"),
+		(4, 0x80, "546aff5444b5b79aa6148bd81728704c32decb73a3ba76e9e75885cad9def1d06d6792f8a7d12794e90efed817d96920d728896a4510864370c207f99bd4a608ea121700ef01ed879745ee3e4ceef777eda6d9e5e38b90c86ea6fb0b36504ba4a45d22e86f6db5dd43d98a294bebb9125d5b794e9d2a81181066eb954966a487"),
+	];
+
+	//RFC 9380 appendix K.2, SHA-256 with a DST over 255 bytes (so it gets hashed down)
+	//(message index in rfc_messages, len_in_bytes, uniform_bytes)
+	const XMD_SHA256_LONG_DST: &[(usize, usize, &str)] = &[
+		(0, 0x20, "e8dc0c8b686b7ef2074086fbdd2f30e3f8bfbd3bdf177f73f04b97ce618a3ed3"),
+		(1, 0x20, "52dbf4f36cf560fca57dedec2ad924ee9c266341d8f3d6afe5171733b16bbb12"),
+		(2, 0x20, "35387dcf22618f3728e6c686490f8b431f76550b0b2c61cbc1ce7001536f4521"),
+		(3, 0x20, "01b637612bb18e840028be900a833a74414140dde0c4754c198532c3a0ba42bc"),
+		(4, 0x20, "20cce7033cabc5460743180be6fa8aac5a103f56d481cf369a8accc0c374431b"),
+		(0, 0x80, "14604d85432c68b757e485c8894db3117992fc57e0e136f71ad987f789a0abc287c47876978e2388a02af86b1e8d1342e5ce4f7aaa07a87321e691f6fba7e0072eecc1218aebb89fb14a0662322d5edbd873f0eb35260145cd4e64f748c5dfe60567e126604bcab1a3ee2dc0778102ae8a5cfd1429ebc0fa6bf1a53c36f55dfc"),
+		(1, 0x80, "1a30a5e36fbdb87077552b9d18b9f0aee16e80181d5b951d0471d55b66684914aef87dbb3626eaabf5ded8cd0686567e503853e5c84c259ba0efc37f71c839da2129fe81afdaec7fbdc0ccd4c794727a17c0d20ff0ea55e1389d6982d1241cb8d165762dbc39fb0cee4474d2cbbd468a835ae5b2f20e4f959f56ab24cd6fe267"),
+		(2, 0x80, "d2ecef3635d2397f34a9f86438d772db19ffe9924e28a1caf6f1c8f15603d4028f40891044e5c7e39ebb9b31339979ff33a4249206f67d4a1e7c765410bcd249ad78d407e303675918f20f26ce6d7027ed3774512ef5b00d816e51bfcc96c3539601fa48ef1c07e494bdc37054ba96ecb9dbd666417e3de289d4f424f502a982"),
+		(3, 0x80, "ed6e8c036df90111410431431a232d41a32c86e296c05d426e5f44e75b9a50d335b2412bc6c91e0a6dc131de09c43110d9180d0a70f0d6289cb4e43b05f7ee5e9b3f42a1fad0f31bac6a625b3b5c50e3a83316783b649e5ecc9d3b1d9471cb5024b7ccf40d41d1751a04ca0356548bc6e703fca02ab521b505e8e45600508d32"),
+		(4, 0x80, "78b53f2413f3c688f07732c10e5ced29a17c6a16f717179ffbe38d92d6c9ec296502eb9889af83a1928cd162e845b0d3c5424e83280fed3d10cffb2f8431f14e7a23f4c68819d40617589e4c41169d0b56e0e3535be1fd71fbb08bb70c5b5ffed953d6c14bf7618b35fc1f4c4b30538236b4b08c9fbf90462447a8ada60be495"),
+	];
+
+	//RFC 9380 appendix K.3, expand_message_xmd with SHA-512
+	//(message index in rfc_messages, len_in_bytes, uniform_bytes)
+	const XMD_SHA512: &[(usize, usize, &str)] = &[
+		(0, 0x20, "6b9a7312411d92f921c6f68ca0b6380730a1a4d982c507211a90964c394179ba"),
+		(1, 0x20, "0da749f12fbe5483eb066a5f595055679b976e93abe9be6f0f6318bce7aca8dc"),
+		(2, 0x20, "087e45a86e2939ee8b91100af1583c4938e0f5fc6c9db4b107b83346bc967f58"),
+		(3, 0x20, "7336234ee9983902440f6bc35b348352013becd88938d2afec44311caf8356b3"),
+		(4, 0x20, "57b5f7e766d5be68a6bfe1768e3c2b7f1228b3e4b3134956dd73a59b954c66f4"),
+		(0, 0x80, "41b037d1734a5f8df225dd8c7de38f851efdb45c372887be655212d07251b921b052b62eaed99b46f72f2ef4cc96bfaf254ebbbec091e1a3b9e4fb5e5b619d2e0c5414800a1d882b62bb5cd1778f098b8eb6cb399d5d9d18f5d5842cf5d13d7eb00a7cff859b605da678b318bd0e65ebff70bec88c753b159a805d2c89c55961"),
+		(1, 0x80, "7f1dddd13c08b543f2e2037b14cefb255b44c83cc397c1786d975653e36a6b11bdd7732d8b38adb4a0edc26a0cef4bb45217135456e58fbca1703cd6032cb1347ee720b87972d63fbf232587043ed2901bce7f22610c0419751c065922b488431851041310ad659e4b23520e1772ab29dcdeb2002222a363f0c2b1c972b3efe1"),
+		(2, 0x80, "3f721f208e6199fe903545abc26c837ce59ac6fa45733f1baaf0222f8b7acb0424814fcb5eecf6c1d38f06e9d0a6ccfbf85ae612ab8735dfdf9ce84c372a77c8f9e1c1e952c3a61b7567dd0693016af51d2745822663d0c2367e3f4f0bed827feecc2aaf98c949b5ed0d35c3f1023d64ad1407924288d366ea159f46287e61ac"),
+		(3, 0x80, "b799b045a58c8d2b4334cf54b78260b45eec544f9f2fb5bd12fb603eaee70db7317bf807c406e26373922b7b8920fa29142703dd52bdf280084fb7ef69da78afdf80b3586395b433dc66cde048a258e476a561e9deba7060af40adf30c64249ca7ddea79806ee5beb9a1422949471d267b21bc88e688e4014087a0b592b695ed"),
+		(4, 0x80, "05b0bfef265dcee87654372777b7c44177e2ae4c13a27f103340d9cd11c86cb2426ffcad5bd964080c2aee97f03be1ca18e30a1f14e27bc11ebbd650f305269cc9fb1db08bf90bfc79b42a952b46daf810359e7bc36452684784a64952c343c52e5124cd1f71d474d5197fefc571a92929c9084ffe1112cf5eea5192ebff330b"),
+	];
+
+	//RFC 9380 appendix J.9.1, BLS12381G1_XMD:SHA-256_SSWU_RO_, one per message in rfc_messages
+	//uncompressed, x || y
+	const G1_RO: &[[&str; 2]] = &[
+		["052926add2207b76ca4fa57a8734416c8dc95e24501772c814278700eed6d1e4e8cf62d9c09db0fac349612b759e79a1", "08ba738453bfed09cb546dbb0783dbb3a5f1f566ed67bb6be0e8c67e2e81a4cc68ee29813bb7994998f3eae0c9c6a265"],
+		["03567bc5ef9c690c2ab2ecdf6a96ef1c139cc0b2f284dca0a9a7943388a49a3aee664ba5379a7655d3c68900be2f6903", "0b9c15f3fe6e5cf4211f346271d7b01c8f3b28be689c8429c85b67af215533311f0b8dfaaa154fa6b88176c229f2885d"],
+		["11e0b079dea29a68f0383ee94fed1b940995272407e3bb916bbf268c263ddd57a6a27200a784cbc248e84f357ce82d98", "03a87ae2caf14e8ee52e51fa2ed8eefe80f02457004ba4d486d6aa1f517c0889501dc7413753f9599b099ebcbbd2d709"],
+		["15f68eaa693b95ccb85215dc65fa81038d69629f70aeee0d0f677cf22285e7bf58d7cb86eefe8f2e9bc3f8cb84fac488", "1807a1d50c29f430b8cafc4f8638dfeeadf51211e1602a5f184443076715f91bb90a48ba1e370edce6ae1062f5e6dd38"],
+		["082aabae8b7dedb0e78aeb619ad3bfd9277a2f77ba7fad20ef6aabdc6c31d19ba5a6d12283553294c1825c4b3ca2dcfe", "05b84ae5a942248eea39e1d91030458c40153f3b654ab7872d779ad1e942856a20c438e8d99bc8abfbf74729ce1f7ac8"],
+	];
+
+	//RFC 9380 appendix J.10.1, BLS12381G2_XMD:SHA-256_SSWU_RO_, one per message in rfc_messages
+	//uncompressed, x.c1 || x.c0 || y.c1 || y.c0
+	const G2_RO: &[[&str; 4]] = &[
+		["05cb8437535e20ecffaef7752baddf98034139c38452458baeefab379ba13dff5bf5dd71b72418717047f5b0f37da03d", "0141ebfbdca40eb85b87142e130ab689c673cf60f1a3e98d69335266f30d9b8d4ac44c1038e9dcdd5393faf5c41fb78a", "12424ac32561493f3fe3c260708a12b7c620e7be00099a974e259ddc7d1f6395c3c811cdd19f1e8dbf3e9ecfdcbab8d6", "0503921d7f6a12805e72940b963c0cf3471c7b2a524950ca195d11062ee75ec076daf2d4bc358c4b190c0c98064fdd92"],
+		["139cddbccdc5e91b9623efd38c49f81a6f83f175e80b06fc374de9eb4b41dfe4ca3a230ed250fbe3a2acf73a41177fd8", "02c2d18e033b960562aae3cab37a27ce00d80ccd5ba4b7fe0e7a210245129dbec7780ccc7954725f4168aff2787776e6", "00aa65dae3c8d732d10ecd2c50f8a1baf3001578f71c694e03866e9f3d49ac1e1ce70dd94a733534f106d4cec0eddd16", "1787327b68159716a37440985269cf584bcb1e621d3a7202be6ea05c4cfe244aeb197642555a0645fb87bf7466b2ba48"],
+		["190d119345b94fbd15497bcba94ecf7db2cbfd1e1fe7da034d26cbba169fb3968288b3fafb265f9ebd380512a71c3f2c", "121982811d2491fde9ba7ed31ef9ca474f0e1501297f68c298e9f4c0028add35aea8bb83d53c08cfc007c1e005723cd0", "0bb5e7572275c567462d91807de765611490205a941a5a6af3b1691bfe596c31225d3aabdf15faff860cb4ef17c7c3be", "05571a0f8d3c08d094576981f4a3b8eda0a8e771fcdcc8ecceaf1356a6acf17574518acb506e435b639353c2e14827c8"],
+		["0934aba516a52d8ae479939a91998299c76d39cc0c035cd18813bec433f587e2d7a4fef038260eef0cef4d02aae3eb91", "19a84dd7248a1066f737cc34502ee5555bd3c19f2ecdb3c7d9e24dc65d4e25e50d83f0f77105e955d78f4762d33c17da", "09bcccfa036b4847c9950780733633f13619994394c23ff0b32fa6b795844f4a0673e20282d07bc69641cee04f5e5662", "14f81cd421617428bc3b9fe25afbb751d934a00493524bc4e065635b0555084dd54679df1536101b2c979c0152d09192"],
+		["11fca2ff525572795a801eed17eb12785887c7b63fb77a42be46ce4a34131d71f7a73e95fee3f812aea3de78b4d01569", "01a6ba2f9a11fa5598b2d8ace0fbe0a0eacb65deceb476fbbcb64fd24557c2f4b18ecfc5663e54ae16a84f5ab7f62534", "03a47f8e6d1763ba0cad63d6114c0accbef65707825a511b251a660a9b3994249ae4e63fac38b23da0c398689ee2ab52", "0b6798718c8aed24bc19cb27f866f1c9effcdbf92397ad6448b5c9db90d2b9da6cbabf48adc1adf59a1a28344e79d57e"],
+	];
+
+	//RFC 9496 appendix A.3, the ristretto255 one-way map from 64 uniform bytes
+	//(uniform_bytes, compressed point)
+	const RISTRETTO255_MAP: &[(&str, &str)] = &[
+		("5d1be09e3d0c82fc538112490e35701979d99e06ca3e2b5b54bffe8b4dc772c14d98b696a1bbfb5ca32c436cc61c16563790306c79eaca7705668b47dffe5bb6", "3066f82a1a747d45120d1740f14358531a8f04bbffe6a819f86dfe50f44a0a46"),
+		("f116b34b8f17ceb56e8732a60d913dd10cce47a6d53bee9204be8b44f6678b270102a56902e2488c46120e9276cfe54638286b9e4b3cdb470b542d46c2068d38", "f26e5b6f7d362d2d2a94c5d0e7602cb4773c95a2e5c31a64f133189fa76ed61b"),
+		("8422e1bbdaab52938b81fd602effb6f89110e1e57208ad12d9ad767e2e25510c27140775f9337088b982d83d7fcf0b2fa1edffe51952cbe7365e95c86eaf325c", "006ccd2a9e6867e6a2c5cea83d3302cc9de128dd2a9a57dd8ee7b9d7ffe02826"),
+		("ac22415129b61427bf464e17baee8db65940c233b98afce8d17c57beeb7876c2150d15af1cb1fb824bbd14955f2b57d08d388aab431a391cfc33d5bafb5dbbaf", "f8f0c87cf237953c5890aec3998169005dae3eca1fbb04548c635953c817f92a"),
+		("165d697a1ef3d5cf3c38565beefcf88c0f282b8e7dbd28544c483432f1cec7675debea8ebb4e5fe7d6f6e5db15f15587ac4d4d4a1de7191e0c1ca6664abcc413", "ae81e7dedf20a497e10c304a765c1767a42d6e06029758d2d7e8ef7cc4c41179"),
+		("a836e6c9a9ca9f1e8d486273ad56a78c70cf18f0ce10abb1c7172ddd605d7fd2979854f47ae1ccf204a33102095b4200e5befc0465accc263175485f0e17ea5c", "e2705652ff9f5e44d3e841bf1c251cf7dddb77d140870d1ab2ed64f1a9ce8628"),
+		("2cdc11eaeb95daf01189417cdddbf95952993aa9cb9c640eb5058d09702c74622c9965a697a3b345ec24ee56335b556e677b30e6f90ac77d781064f866a3c982", "80bd07262511cdde4863f8a7434cef696750681cb9510eea557088f76d9e5065"),
+	];
+
+	#[test]
+	fn rfc_expand_message_xmd() {
+		let messages = rfc_messages();
+		let long_dst = [b"QUUX-V01-CS02-with-expander-SHA256-128-long-DST-".as_slice(), &[b'1'; 208]].concat();
+		for &(msg, len, expected) in XMD_SHA256 {
+			assert_eq!(expand_message_xmd::<Sha256>(&messages[msg], b"QUUX-V01-CS02-with-expander-SHA256-128", len).unwrap(), hex(expected));
+		}
+		for &(msg, len, expected) in XMD_SHA256_LONG_DST {
+			assert_eq!(expand_message_xmd::<Sha256>(&messages[msg], &long_dst, len).unwrap(), hex(expected));
+		}
+		for &(msg, len, expected) in XMD_SHA512 {
+			assert_eq!(expand_message_xmd::<Sha512>(&messages[msg], b"QUUX-V01-CS02-with-expander-SHA512-256", len).unwrap(), hex(expected));
+		}
+	}
+
+	#[test]
+	fn expand_too_long() {
+		//255 hash outputs is the most expand_message_xmd can make
+		assert_eq!(expand_message_xmd::<Sha256>(b"", b"dst", 255 * 32).unwrap().len(), 255 * 32);
+		assert_eq!(expand_message_xmd::<Sha256>(b"", b"dst", 255 * 32 + 1), Err(PunchCardError::ExpandTooLong(255 * 32 + 1)));
+		assert_eq!(expand_message_xmd::<Sha512>(b"", b"dst", 255 * 64).unwrap().len(), 255 * 64);
+		assert_eq!(expand_message_xmd::<Sha512>(b"", b"dst", 255 * 64 + 1), Err(PunchCardError::ExpandTooLong(255 * 64 + 1)));
+		assert_eq!(expand_message_xmd::<Sha512>(b"", b"dst", 65536), Err(PunchCardError::ExpandTooLong(65536)));
+	}
+
+	#[test]
+	fn rfc_bls12_381_g1() {
+		for (msg, expected) in rfc_messages().iter().zip(G1_RO) {
+			let point = hash_to_g1(msg, b"QUUX-V01-CS02-with-BLS12381G1_XMD:SHA-256_SSWU_RO_");
+			assert_eq!(uncompressed(&point), hex(&expected.concat()));
+		}
+	}
+
+	#[test]
+	fn rfc_bls12_381_g2() {
+		for (msg, expected) in rfc_messages().iter().zip(G2_RO) {
+			let point = hash_to_g2(msg, b"QUUX-V01-CS02-with-BLS12381G2_XMD:SHA-256_SSWU_RO_");
+			assert_eq!(uncompressed(&point), hex(&expected.concat()));
+		}
+	}
+
+	//RFC 9380 has no test vectors for ristretto255_XMD:SHA-512_R255MAP_RO_ (appendix B only defines the suite)
+	//so its two halves are checked on their own: expand_message_xmd with SHA-512 above, the one-way map here
+	#[test]
+	fn rfc_ristretto255() {
+		for &(uniform_bytes, expected) in RISTRETTO255_MAP {
+			let mut bytes = [0u8; 64];
+			bytes.copy_from_slice(&hex(uniform_bytes));
+			assert_eq!(RistrettoPoint::from_uniform_bytes(&bytes).compress().to_bytes().to_vec(), hex(expected));
+		}
+
+		let dst = b"QUUX-V01-CS02-with-ristretto255_XMD:SHA-512_R255MAP_RO_";
+		for msg in rfc_messages() {
+			let mut bytes = [0u8; 64];
+			bytes.copy_from_slice(&expand_message_xmd::<Sha512>(&msg, dst, 64).unwrap());
+			assert_eq!(hash_to_ristretto255(&msg, dst), RistrettoPoint::from_uniform_bytes(&bytes));
+		}
+	}
+
+	#[test]
+	fn oversize_dst() {
+		let long = [b'D'; 300];
+		let reduced = reduce_dst::<Sha512>(&long);
+		assert_eq!(hash_to_ristretto255(b"abc", &long), hash_to_ristretto255(b"abc", &reduced));
+		assert_ne!(hash_to_ristretto255(b"abc", &long), hash_to_ristretto255(b"abc", &long[..255]));
+	}
+
+	//the unpunched card for merchant 7 and the card secret 0, 1, ..., 31 in each group
+	#[test]
+	fn card_vectors() {
+		let mut card_secret = [0u8; 32];
+		for (i, byte) in card_secret.iter_mut().enumerate() {
+			*byte = i as u8;
+		}
+		let msg = card_message(7, &card_secret);
+
+		assert_eq!(hash_to_ristretto255(&msg, RISTRETTO255_DST).compress().to_bytes().to_vec(),
+				   hex("46644a12cd8e1630080f625193c9f3518b9eed86db7eb33b9055c426543c4b37"));
+		let mut compressed = Vec::new();
+		hash_to_g1(&msg, BLS12381G1_DST).serialize(&mut compressed, true).unwrap();
+		assert_eq!(compressed, hex("86981fd997243de099b656d6a91b58c127a10b7826b57961532fa3f50ddf572babd9216a459eb5df887963d24b1871c8"));
+		let mut compressed = Vec::new();
+		hash_to_g2(&msg, BLS12381G2_DST).serialize(&mut compressed, true).unwrap();
+		assert_eq!(compressed, hex("b2517338d2a20271b53d623bb2fbc2c23f9cf8810fb92ecbe66b42726d332843934d51af71dac89e052a28e93e01826e0a22769290413d5d5bfb85632532b1b45d0568ecd133662c9f8a600456589539073b3a9892e24ba6be4545868fc2c278"));
+	}
+}
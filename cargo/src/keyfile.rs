@@ -0,0 +1,153 @@
+//Saving and loading server secrets so outstanding cards survive a restart
+//key format: KEY_MAGIC || scheme tag (same tags as the wire format) || version || scheme specific body
+//each scheme's body holds the secret followed by the public key(s) derived from it and the DSTs cards are hashed with,
+//and the public keys are recomputed and checked when a key is imported
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use super::error::PunchCardError;
+use super::nullifier::sync_parent_dir;
+use super::scheme::PunchCardScheme;
+
+pub const KEY_MAGIC: &[u8; 4] = b"PCKY";
+pub const KEY_VERSION: u8 = 1;
+const HEADER_LEN: usize = 6;
+
+//add a magic || tag || version header to a scheme specific body
+//used for server keys here and for saved client cards in wallet.rs
+pub(crate) fn encode_tagged(magic: &[u8; 4], tag: u8, version: u8, body: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(HEADER_LEN + body.len());
+	out.extend_from_slice(magic);
+	out.push(tag);
+	out.push(version);
+	out.extend_from_slice(body);
+	out
+}
+
+//check the header and length, return the body split into its first body_len bytes and the rest
+pub(crate) fn decode_tagged<'a>(magic: &[u8; 4], tag: u8, version: u8, bytes: &'a [u8], body_len: usize) -> Result<(&'a [u8], &'a [u8]), PunchCardError> {
+	if bytes.len() < HEADER_LEN || &bytes[..magic.len()] != magic {
+		return Err(PunchCardError::CorruptStore);
+	}
+	if bytes[4] != tag {
+		return Err(PunchCardError::WrongScheme(bytes[4]));
+	}
+	if bytes[5] != version {
+		return Err(PunchCardError::UnsupportedVersion(bytes[5]));
+	}
+	if bytes.len() < HEADER_LEN + body_len {
+		return Err(PunchCardError::BadLength { expected: HEADER_LEN + body_len, got: bytes.len() });
+	}
+	Ok(bytes[HEADER_LEN..].split_at(body_len))
+}
+
+//the DSTs a server or card hashes with go after the fixed part of its body, each as length (u32 little endian) || DST
+pub(crate) fn write_dst(dst: &[u8], out: &mut Vec<u8>) {
+	out.extend_from_slice(&(dst.len() as u32).to_le_bytes());
+	out.extend_from_slice(dst);
+}
+
+//read count DSTs written by write_dst, which have to fill all of rest
+pub(crate) fn read_dsts(mut rest: &[u8], count: usize) -> Result<Vec<Vec<u8>>, PunchCardError> {
+	let mut dsts = Vec::with_capacity(count);
+	for _ in 0..count {
+		if rest.len() < 4 {
+			return Err(PunchCardError::BadLength { expected: 4, got: rest.len() });
+		}
+		let (len, tail) = rest.split_at(4);
+		let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+		if tail.len() < len {
+			return Err(PunchCardError::BadLength { expected: len, got: tail.len() });
+		}
+		let (dst, tail) = tail.split_at(len);
+		dsts.push(dst.to_vec());
+		rest = tail;
+	}
+	if !rest.is_empty() {
+		return Err(PunchCardError::BadLength { expected: 0, got: rest.len() });
+	}
+	Ok(dsts)
+}
+
+pub(crate) fn encode_key(tag: u8, body: &[u8]) -> Vec<u8> {
+	encode_tagged(KEY_MAGIC, tag, KEY_VERSION, body)
+}
+
+pub(crate) fn decode_key(tag: u8, bytes: &[u8], body_len: usize) -> Result<(&[u8], &[u8]), PunchCardError> {
+	decode_tagged(KEY_MAGIC, tag, KEY_VERSION, bytes, body_len)
+}
+
+//write the key (or any other secret state) so that only the owner can read it
+//it goes to a temporary file first and is renamed into place, so a crash never leaves half a key,
+//and the directory is synced after the rename so a saved key isn't lost to a crash either
+pub fn write_key_file<P: AsRef<Path>>(path: P, key: &[u8]) -> Result<(), PunchCardError> {
+	let path = path.as_ref();
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+
+	let mut options = OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	let mut file = options.open(&tmp_path)?;
+	file.write_all(key)?;
+	file.sync_all()?;
+	fs::rename(&tmp_path, path)?;
+	sync_parent_dir(path)?;
+	Ok(())
+}
+
+pub fn read_key_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, PunchCardError> {
+	Ok(fs::read(path)?)
+}
+
+//export the server's key to path
+pub fn save_server_key<S: PunchCardScheme, P: AsRef<Path>>(server: &S::Server, path: P) -> Result<(), PunchCardError> {
+	write_key_file(path, &S::export_key(server)?)
+}
+
+//reload a server from a key saved with save_server_key
+//redeemed cards go in store, which should be the store the server used before
+pub fn load_server_key<S: PunchCardScheme, P: AsRef<Path>>(path: P, store: S::Store) -> Result<(S::PublicKey, S::Server), PunchCardError> {
+	S::import_key(&read_key_file(path)?, store)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::crypto::GroupScheme;
+	use super::super::crypto_pairing::PairingScheme;
+	use super::super::nullifier::MemoryNullifierStore;
+	use super::super::scheme::testing::{both_schemes, token};
+
+	//a card punched by the original server must still redeem at the reloaded one
+	fn reload<S: PunchCardScheme<Store = MemoryNullifierStore>>() {
+		let path = std::env::temp_dir().join(format!("punchcard-key-{}-{}", S::NAME.replace(' ', "-"), std::process::id()));
+		let (pub_key, server) = S::server_setup().unwrap();
+		save_server_key::<S, _>(&server, &path).unwrap();
+		let token = token::<S>(&server, &pub_key, S::CARDS_PER_REDEMPTION);
+		drop(server);
+
+		let (_, mut reloaded) = load_server_key::<S, _>(&path, MemoryNullifierStore::new()).unwrap();
+		S::server_verify(&mut reloaded, token, S::CARDS_PER_REDEMPTION).unwrap();
+		fs::remove_file(&path).unwrap();
+	}
+
+	both_schemes!(reload, group_key_reload, pairing_key_reload);
+
+	#[test]
+	fn key_tags_are_checked() {
+		let (_, server) = <GroupScheme>::server_setup().unwrap();
+		let mut key = GroupScheme::export_key(&server).unwrap();
+		assert!(matches!(PairingScheme::import_key(&key, MemoryNullifierStore::new()), Err(PunchCardError::WrongScheme(_))));
+
+		//public key no longer matches the secret
+		let last = HEADER_LEN + 72 - 1;
+		key[last] ^= 1;
+		assert_eq!(GroupScheme::import_key(&key, MemoryNullifierStore::new()).err(), Some(PunchCardError::CorruptStore));
+	}
+}
@@ -1,140 +1,132 @@
-mod crypto;
-mod crypto_pairing;
+pub mod error;
+pub mod scheme;
+pub mod crypto;
+pub mod crypto_pairing;
+pub mod wire;
+pub mod nullifier;
+pub mod keyfile;
+pub mod wallet;
+pub mod stats;
+pub mod compare;
+pub mod reward;
+pub mod epoch;
+pub mod expiry;
+pub mod merchant;
+pub mod threshold;
+pub mod transcript;
+pub mod hash_to_curve;
 
 use std::os::raw::{c_char};
 use std::ffi::{CString};
 use crypto::ServerData;
 use crypto::PunchCard;
-use crypto_pairing::PairServerData;
+use crypto::GroupScheme;
 use crypto_pairing::PairPunchCard;
+use crypto_pairing::PairingScheme;
+use crypto_pairing::PairCheck;
+use scheme::PunchCardScheme;
 use std::time::Instant;
 use curve25519_dalek::scalar::Scalar;
 use rand::Rng;
+use stats::{Samples, Summary};
 
 
-enum Tests {
+//which experiment to run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tests {
     Group,
     Lookup,
     Pairing,
 }
 
-struct Times {
-	num_iterations: u32,
-	num_punches: u32,
-	setup_rows: u32,
-	test_type: Tests,
-	server_setup: u128,
-	client_setup: u128,
-	server_punch: u128,
-	client_punch: u128,
-	client_redeem: u128,
-	server_redeem: u128,
+//how benchmark results are written out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+pub struct Times {
+	pub num_iterations: u32,
+	pub num_punches: u32,
+	pub setup_rows: u32,
+	pub test_type: Tests,
+	//how the pairing test's server checks redeemed cards
+	pub pair_check: PairCheck,
+	pub server_setup: Samples,
+	pub client_setup: Samples,
+	pub server_punch: Samples,
+	pub client_punch: Samples,
+	pub client_redeem: Samples,
+	pub server_redeem: Samples,
+}
+
+impl Times {
+    
+    //a fresh configuration with all timings at zero
+    pub fn new(test_type: Tests, num_iterations: u32, num_punches: u32, setup_rows: u32) -> Times {
+        Times {
+            num_iterations,
+            num_punches,
+            setup_rows,
+            test_type,
+            pair_check: PairCheck::default(),
+            server_setup: Samples::default(),
+            client_setup: Samples::default(),
+            server_punch: Samples::default(),
+            client_punch: Samples::default(),
+            client_redeem: Samples::default(),
+            server_redeem: Samples::default(),
+        }
+    }
+    
+    //reject configurations the experiments can't run with
+    pub fn check(&self) -> Result<(), String> {
+        if self.num_iterations == 0 {
+            return Err("iterations must be at least 1".to_owned());
+        }
+        match self.test_type {
+            Tests::Group => Ok(()),
+            //each lookup picks a random row of the pre-filled database
+            Tests::Lookup if self.setup_rows == 0 => Err("the lookup test needs setup rows to look up".to_owned()),
+            Tests::Lookup => Ok(()),
+            Tests::Pairing => Ok(()),
+        }
+    }
 }
 
 #[no_mangle]
-pub extern fn benchmarkCode() -> *mut c_char {
+pub extern "C" fn benchmarkCode() -> *mut c_char {
     //call and time crypto code here
     //write performance numbers to the string that gets returned
     
+    let mut times = Times::new(
+    	Tests::Group,
+    	1000, //how many iterations to average over
+    	10, //how many punches before a card is redeemed
+    	0, //change to larger number to test with used cards in db, also make this larger for the lookup test (or else it will crash)
+    );
 
-    
-    let mut times = Times {
-    	num_iterations: 1000, //how many iterations to average over
-    	num_punches: 10, //how many punches before a card is redeemed, must be even for the pairing version test code
-    	setup_rows: 0, //change to larger number to test with used cards in db, also make this larger for the lookup test (or else it will crash)
-    	test_type: Tests::Group,
-    	server_setup: 0,
- 		client_setup: 0,
-		server_punch: 0,
-		client_punch: 0,
-		client_redeem: 0,
-		server_redeem: 0,
-    };
+    let name = run_benchmark(&mut times);
+    let perf_string = format_results(&times, &name, OutputFormat::Text);
 
-    let perf_string: &str;
-    
-    match times.test_type {
-        Tests::Group => {
-            for _ in 0..times.num_iterations {
-                //if j % 10 == 0 {println!("10 more done!\n");}
-            
-                //set up server
-                let now = Instant::now();
-                let (pub_secret, mut server) = ServerData::server_setup();
-                let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in server setup: {}", elapsed);
-                times.server_setup += elapsed;
-                
-                //fill up database of used cards
-                server.cheat_setup_db(times.setup_rows);
-                
-                //println!("number of used punchcards: {}", server.count_cards());
-                
-                //create new punchcard
-                let now = Instant::now();
-                let (mut current_card, mut client) = PunchCard::card_setup();
-                let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in punchcard setup: {}", elapsed);
-                times.client_setup += elapsed;
-                
-                
-                //punch the card
-                for i in 0..times.num_punches {
-                    
-                    //server punches
-                    let now = Instant::now();
-                    let (new_card, proof) = server.server_punch(current_card);
-                    let elapsed = now.elapsed().as_micros();
-                    //println!("time elapsed in server punch: {}", elapsed);
-                    times.server_punch += elapsed;
-                
-                    //client verifies punch, prepares for next punch	
-                    let now = Instant::now();
-                    let res = client.verify_remask(new_card, pub_secret, proof);
-                    current_card = res.0;
-                    let punch_success = res.1;
-                    let elapsed = now.elapsed().as_micros();
-                    if !punch_success {panic!("punch failed");}
-                    //println!("time elapsed in client punch: {}", elapsed);
-                    times.client_punch += elapsed;
-                
-                    //println!("punch succeeded? {}", punch_success);
-                    //println!("punch count: {}", client.get_count());	
-                    if client.get_count() != i+1 {panic!("punch count wrong");}
-                }
+    CString::new(perf_string).unwrap().into_raw()
+}
 
-                
-                //client redeems card
-                let now = Instant::now();
-                let (card_secret, final_card) = client.unmask_redeem();
-                let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in redemption (client): {}", elapsed);
-                times.client_redeem += elapsed;
-                
-                //server verifies card
-                let now = Instant::now();
-                let redeem_success = server.server_verify(final_card, card_secret, times.num_punches);
-                if !redeem_success {panic!("redemption failed");}
-                let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in redemption (server): {}", elapsed);
-                times.server_redeem += elapsed;
-                
-                //println!("card redemption succeeded? {}", redeem_success);
-                //println!("number of used punchcards: {}", server.count_cards());
-                if server.count_cards() != (times.setup_rows + 1) as usize {panic!("wrong number of rows in card database");}
-                
-            }
-            
-            perf_string = "Performance Results for 25519 group\n";
-        },
+//run the experiment selected in times, accumulating timings into it
+//returns the name of what was measured
+pub fn run_benchmark(times: &mut Times) -> String {
+    match times.test_type {
+        Tests::Group => run_scheme::<GroupScheme>(times, |_| {}),
         Tests::Lookup => {
             //mostly using this as scratch space for miscellaneous experiments
             let (_, mut server) = ServerData::server_setup();
-            server.cheat_setup_db(times.setup_rows);
+            server.cheat_setup_db(times.setup_rows).expect("couldn't fill card db");
             let (_, client) = PunchCard::card_setup();
             let mut rng = rand::thread_rng();
             
-            let (_, _, mut pairing_client) = PairPunchCard::card_setup();
+            let (_, _, mut pairing_client) = PairPunchCard::card_setup().expect("pairing card setup failed");
             
             for _ in 0..times.num_iterations {
                 let x:u32 = rng.gen_range(0, times.setup_rows);
@@ -145,168 +137,196 @@ pub extern fn benchmarkCode() -> *mut c_char {
                 let there = server.lookup_test(val);
                 let elapsed = now.elapsed().as_nanos();
                 //println!("time elapsed in redemption (server): {}", elapsed);
-                times.server_setup += elapsed;
+                times.server_setup.record(elapsed);
                 if !there {panic!("wasn't there!");}
                 
                 
                 let now = Instant::now();
                 let _res = client.exp_test();
                 let elapsed = now.elapsed().as_micros();
-                times.client_setup += elapsed;
+                times.client_setup.record(elapsed);
                 
                 
                 
                 let now = Instant::now();
                 let _res = pairing_client.exp_test_g1();
                 let elapsed = now.elapsed().as_micros();
-                times.client_redeem += elapsed;
+                times.client_redeem.record(elapsed);
                 
                 let now = Instant::now();
                 let _res = pairing_client.exp_test_g2();
                 let elapsed = now.elapsed().as_micros();
-                times.server_redeem += elapsed;
+                times.server_redeem.record(elapsed);
                 
                 let now = Instant::now();
                 let _res = pairing_client.pair_test();
                 let elapsed = now.elapsed().as_micros();
-                times.server_punch += elapsed;
+                times.server_punch.record(elapsed);
             }
             
-                perf_string = "Performance Results for misc experiment\n";
+            "misc experiment".to_owned()
         },
-        Tests::Pairing => { 
-            //similar to the group code above, but for the pairing version
-            //we will create 2 cards and punch each 5 times, then merge them to redeem
-                for j in 0..times.num_iterations {
-                if j % 10 == 0 {println!("10 more done!\n");}
-            
-                //set up server
-                let now = Instant::now();
-                let mut server = PairServerData::pair_server_setup();
-                let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in server setup: {}", elapsed);
-                times.server_setup += elapsed;
-                
-                //fill up database of used cards
-                //double because each card is actually 2 cards
-                server.pair_cheat_setup_db(2*times.setup_rows);
-                
-                //println!("number of used punchcards: {}", server.count_cards());
-                
-                //create new punchcard
-                let now = Instant::now();
-                let (mut current_card_g1, mut current_card_g2, mut client) = PairPunchCard::card_setup();
-                let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in punchcard setup: {}", elapsed);
-                times.client_setup += elapsed;
-                
-                //create a second card that's going to be merged with the first
-                let (mut second_current_card_g1, mut second_current_card_g2, mut second_client) = PairPunchCard::card_setup();
-                
-                
-                //punch the first card
-                for i in 0..times.num_punches/2 {
-                    
-                    //server punches
-                    let now = Instant::now();
-                    let (new_card_g1, new_card_g2, proof_g1, proof_g2) = server.pair_server_punch(&mut current_card_g1, &mut current_card_g2);
-                    let elapsed = now.elapsed().as_micros();
-                    //println!("time elapsed in server punch: {}", elapsed);
-                    times.server_punch += elapsed;
-                
-                    //client verifies punch, prepares for next punch	
-                    let now = Instant::now();
-                    let res = client.verify_remask(new_card_g1, new_card_g2, &server.pub_secret_g1, &server.pub_secret_g2, proof_g1, proof_g2);
-                    current_card_g1 = res.0;
-                    current_card_g2 = res.1;
-                    let punch_success = res.2;
-                    let elapsed = now.elapsed().as_micros();
-                    if !punch_success {panic!("punch failed");}
-                    //println!("time elapsed in client punch: {}", elapsed);
-                    times.client_punch += elapsed;
-                
-                    //println!("punch succeeded? {}", punch_success);
-                    //println!("punch count: {}", client.get_count());	
-                    if client.pair_get_count() != i+1 {panic!("first punch count wrong");}
+        //we will create 2 cards and split num_punches between them, then merge them to redeem
+        Tests::Pairing => {
+            let check = times.pair_check;
+            let name = run_scheme::<PairingScheme>(times, |server| server.pair_set_check(check));
+            match check {
+                PairCheck::Single => name,
+                PairCheck::Both => name + " (both pairings checked)",
+                PairCheck::Product => name + " (product of both pairings checked)",
+            }
+        },
+    }
+}
+
+pub const MICROSECONDS: &str = "microseconds";
+pub const NANOSECONDS: &str = "nanoseconds";
+
+//field names, labels, units and samples for each measured operation, in report order
+//everything is timed in microseconds except the lookup test's hash set lookup, which takes well under one
+pub(crate) fn operations(times: &Times) -> [(&'static str, &'static str, &'static str, &Samples); 6] {
+    let lookup_unit = if times.test_type == Tests::Lookup {NANOSECONDS} else {MICROSECONDS};
+    [
+        ("server_setup", "Server setup", lookup_unit, &times.server_setup),
+        ("client_setup", "Client setup", MICROSECONDS, &times.client_setup),
+        ("server_punch", "Server punch", MICROSECONDS, &times.server_punch),
+        ("client_punch", "Client punch", MICROSECONDS, &times.client_punch),
+        ("client_redeem", "Client redeem", MICROSECONDS, &times.client_redeem),
+        ("server_redeem", "Server redeem", MICROSECONDS, &times.server_redeem),
+    ]
+}
+
+fn summary_json(summary: &Summary, unit: &str) -> String {
+    format!("{{\"unit\": \"{}\", \"count\": {}, \"total\": {}, \"mean\": {:.3}, \"median\": {:.3}, \"p95\": {:.3}, \"p99\": {:.3}, \"min\": {}, \"max\": {}, \"std_dev\": {:.3}}}",
+        unit, summary.count, summary.total, summary.mean, summary.median, summary.p95, summary.p99, summary.min, summary.max, summary.std_dev)
+}
+
+//write out the timings in the requested format
+//text keeps the cumulative numbers the paper's data.txt was written from and adds the distribution of each operation
+//json and csv carry the same statistics for scripts, with the unit of every operation
+//text names each unit only when they aren't all microseconds
+pub fn format_results(times: &Times, name: &str, format: OutputFormat) -> String {
+    let ops = operations(times);
+    let all_micros = ops.iter().all(|(_, _, unit, _)| *unit == MICROSECONDS);
+    let unit_suffix = |unit: &str| if all_micros {String::new()} else {format!(" {}", unit)};
+    
+    match format {
+        OutputFormat::Text => {
+            let mut out = "Performance Results for ".to_owned() + name + "\n" +
+                "Each operation is repeated for "
+                + &times.num_iterations.to_string() +
+                " iterations, except punches, which are done "
+                + &(times.num_iterations*times.num_punches).to_string() + 
+                " times (" 
+                + &times.num_punches.to_string() + 
+                " punches per iteration). \nThe server database starts with "
+                + &times.setup_rows.to_string() + 
+                " used punchcards in each iteration." +
+                if all_micros {" \nNumbers are cumulative over all runs, in microseconds.\n"}
+                else {" \nNumbers are cumulative over all runs, in the unit given after each one.\n"};
+            for (_, label, unit, samples) in ops.iter() {
+                out += &format!("{}: {}{}\n", label, samples.total(), unit_suffix(unit));
+            }
+            out += if all_micros {"\nPer-operation statistics, in microseconds:\n"} else {"\nPer-operation statistics:\n"};
+            for (_, label, unit, samples) in ops.iter() {
+                if let Some(sum) = samples.summary() {
+                    out += &format!("{}: n={} mean={:.3} median={:.3} p95={:.3} p99={:.3} min={} max={} std_dev={:.3}{}\n",
+                        label, sum.count, sum.mean, sum.median, sum.p95, sum.p99, sum.min, sum.max, sum.std_dev, unit_suffix(unit));
                 }
-                //punch the second card
-                for i in 0..times.num_punches/2 {
-                    
-                    //server punches
-                    let now = Instant::now();
-                    let (new_card_g1, new_card_g2, proof_g1, proof_g2) = server.pair_server_punch(&mut second_current_card_g1, &mut second_current_card_g2);
-                    let elapsed = now.elapsed().as_micros();
-                    //println!("time elapsed in server punch: {}", elapsed);
-                    times.server_punch += elapsed;
-                
-                    //client verifies punch, prepares for next punch	
-                    let now = Instant::now();
-                    let res = second_client.verify_remask(new_card_g1, new_card_g2, &server.pub_secret_g1, &server.pub_secret_g2, proof_g1, proof_g2);
-                    second_current_card_g1 = res.0;
-                    second_current_card_g2 = res.1;
-                    let punch_success = res.2;
-                    let elapsed = now.elapsed().as_micros();
-                    if !punch_success {panic!("punch failed");}
-                    //println!("time elapsed in client punch: {}", elapsed);
-                    times.client_punch += elapsed;
-                
-                    //println!("punch succeeded? {}", punch_success);
-                    //println!("punch count: {}", client.get_count());	
-                    if second_client.pair_get_count() != i+1 {panic!("second punch count wrong");}
+            }
+            out
+        },
+        OutputFormat::Json => {
+            let stats: Vec<String> = ops.iter()
+                .filter_map(|(field, _, unit, samples)| samples.summary().map(|sum| format!("\"{}\": {}", field, summary_json(&sum, unit))))
+                .collect();
+            format!("{{\"scheme\": \"{}\", \"iterations\": {}, \"punches\": {}, \"setup_rows\": {}, \"operations\": {{{}}}}}\n",
+                name, times.num_iterations, times.num_punches, times.setup_rows, stats.join(", "))
+        },
+        OutputFormat::Csv => {
+            //one row per operation
+            let mut out = "scheme,iterations,punches,setup_rows,operation,unit,count,total,mean,median,p95,p99,min,max,std_dev\n".to_owned();
+            for (field, _, unit, samples) in ops.iter() {
+                if let Some(sum) = samples.summary() {
+                    out += &format!("{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{},{},{:.3}\n",
+                        name, times.num_iterations, times.num_punches, times.setup_rows, field, unit,
+                        sum.count, sum.total, sum.mean, sum.median, sum.p95, sum.p99, sum.min, sum.max, sum.std_dev);
                 }
+            }
+            out
+        },
+    }
+}
 
+//run the full protocol for any scheme, accumulating timings into times
+//each redemption merges S::CARDS_PER_REDEMPTION cards, so num_punches is split between them
+//as evenly as it goes, with the first cards taking the extra punches (e.g. 7 is split 4 and 3)
+//configure is applied to each new server before it's used
+fn run_scheme<S: PunchCardScheme>(times: &mut Times, configure: impl Fn(&mut S::Server)) -> String
+    where S::Store: Default
+{
+    let (punches_per_card, extra_punches) = (times.num_punches / S::CARDS_PER_REDEMPTION, times.num_punches % S::CARDS_PER_REDEMPTION);
+    let punches_on = |card_num: u32| punches_per_card + u32::from(card_num < extra_punches);
+    
+    for _ in 0..times.num_iterations {
+    
+        //set up server
+        let now = Instant::now();
+        let (pub_key, mut server) = S::server_setup().expect("server setup failed");
+        let elapsed = now.elapsed().as_micros();
+        times.server_setup.record(elapsed);
+        configure(&mut server);
+        
+        //fill up database of used cards
+        //each redemption uses up CARDS_PER_REDEMPTION cards
+        S::cheat_setup_db(&mut server, S::CARDS_PER_REDEMPTION*times.setup_rows).expect("couldn't fill card db");
+        
+        let mut clients = Vec::new();
+        for card_num in 0..S::CARDS_PER_REDEMPTION {
+        
+            //create new punchcard
+            //only the first card's setup is timed, as in the original experiments
+            let now = Instant::now();
+            let (mut current_card, mut client) = S::card_setup().expect("card setup failed");
+            let elapsed = now.elapsed().as_micros();
+            if card_num == 0 {times.client_setup.record(elapsed);}
+            
+            //punch the card
+            for i in 0..punches_on(card_num) {
                 
-                //client redeems card
+                //server punches
                 let now = Instant::now();
-                let (card_secret, second_card_secret, mut final_card) = client.pair_unmask_redeem(second_client);
+                let (new_card, proof) = S::server_punch(&server, &current_card).expect("server punch failed");
                 let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in redemption (client): {}", elapsed);
-                times.client_redeem += elapsed;
-                
-                //server verifies card
+                times.server_punch.record(elapsed);
+            
+                //client verifies punch, prepares for next punch	
                 let now = Instant::now();
-                let redeem_success = server.pair_server_verify(&mut final_card, card_secret, second_card_secret, times.num_punches);
-                if !redeem_success {panic!("redemption failed");}
+                current_card = S::verify_remask(&mut client, new_card, &pub_key, proof).expect("punch failed");
                 let elapsed = now.elapsed().as_micros();
-                //println!("time elapsed in redemption (server): {}", elapsed);
-                times.server_redeem += elapsed;
-                
-                //println!("card redemption succeeded? {}", redeem_success);
-                //println!("number of used punchcards: {}", server.count_cards());
-                if server.pair_count_cards() != (2*times.setup_rows + 2) as usize {panic!("wrong number of rows in card database");}
-                
-            }
+                times.client_punch.record(elapsed);
             
-            perf_string = "Performance Results for BLS12_381 group with merging\n";
-            
-            
-        },
-    }
+                if S::get_count(&client).expect("punch count misaligned") != i+1 {panic!("punch count wrong");}
+            }
+            clients.push(client);
+        }
 
+        //client redeems card
+        let now = Instant::now();
+        let token = S::unmask_redeem(clients).expect("client redemption failed");
+        let elapsed = now.elapsed().as_micros();
+        times.client_redeem.record(elapsed);
+        
+        //server verifies card
+        let now = Instant::now();
+        S::server_verify(&mut server, token, times.num_punches).expect("redemption failed");
+        let elapsed = now.elapsed().as_micros();
+        times.server_redeem.record(elapsed);
+        
+        if S::count_cards(&server) != (S::CARDS_PER_REDEMPTION*(times.setup_rows + 1)) as usize {panic!("wrong number of rows in card database");}
+    }
     
-    
-    
-    
-	let perf_string = perf_string.to_owned() + 
-                        &"Each operation is repeated for ".to_owned()
-						+ &times.num_iterations.to_string().to_owned() +
-						&" iterations, except punches, which are done ".to_owned()
-						+ &(times.num_iterations*times.num_punches).to_string().to_owned() + 
-						&" times (".to_owned() 
-						+ &times.num_punches.to_string().to_owned() + 
-						&" punches per iteration). \nThe server database starts with ".to_owned()
-						+ &times.setup_rows.to_string().to_owned() + 
-						&" used punchcards in each iteration.".to_owned() +
-						&" \nNumbers are cumulative over all runs, in microseconds.\n".to_owned() +
-						&"Server setup: ".to_owned() + &times.server_setup.to_string().to_owned() + 
-						&"\nClient setup: ".to_owned() + &times.client_setup.to_string().to_owned() + 
-						&"\nServer punch: ".to_owned() + &times.server_punch.to_string().to_owned() + 
-						&"\nClient punch: ".to_owned() + &times.client_punch.to_string().to_owned() + 
-						&"\nClient redeem: ".to_owned() + &times.client_redeem.to_string().to_owned() + 
-						&"\nServer redeem: ".to_owned() + &times.server_redeem.to_string().to_owned() + 
-						&"\n".to_owned();
-
-    CString::new(perf_string.to_owned()).unwrap().into_raw()
+    S::NAME.to_owned()
 }
 
 /// Expose the JNI interface for android below
@@ -321,7 +341,7 @@ pub mod android {
     use self::jni::sys::{jstring};
 
     #[no_mangle]
-    pub unsafe extern fn Java_com_example_punchcard_RustPunchCard_benchmarkCode(env: JNIEnv, _: JClass) -> jstring {
+    pub unsafe extern "C" fn Java_com_example_punchcard_RustPunchCard_benchmarkCode(env: JNIEnv, _: JClass) -> jstring {
         // Our Java companion code might pass-in "world" as a string, hence the name.
         let world = benchmarkCode();
         // Retake pointer so that we can use it below and allow memory to be freed when it goes out of scope.
@@ -332,3 +352,42 @@ pub mod android {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(test_type: Tests) -> Times {
+        let mut times = Times::new(test_type, 1, 1, 1);
+        for samples in [&mut times.server_setup, &mut times.client_setup, &mut times.server_punch,
+                        &mut times.client_punch, &mut times.client_redeem, &mut times.server_redeem] {
+            samples.record(250);
+        }
+        times
+    }
+
+    #[test]
+    fn lookup_units() {
+        let times = recorded(Tests::Lookup);
+        let json = format_results(&times, "lookup", OutputFormat::Json);
+        assert!(json.contains("\"server_setup\": {\"unit\": \"nanoseconds\", \"count\": 1, \"total\": 250"));
+        assert!(json.contains("\"client_setup\": {\"unit\": \"microseconds\""));
+
+        let csv = format_results(&times, "lookup", OutputFormat::Csv);
+        assert!(csv.lines().any(|line| line.starts_with("lookup,1,1,1,server_setup,nanoseconds,1,250,")));
+        assert!(csv.lines().any(|line| line.starts_with("lookup,1,1,1,client_setup,microseconds,1,250,")));
+
+        let text = format_results(&times, "lookup", OutputFormat::Text);
+        assert!(text.contains("Server setup: 250 nanoseconds\n"));
+        assert!(text.contains("Client setup: 250 microseconds\n"));
+    }
+
+    #[test]
+    fn protocol_units() {
+        //the protocol runs keep the plain text the published results were written in
+        let times = recorded(Tests::Group);
+        let text = format_results(&times, "group", OutputFormat::Text);
+        assert!(text.contains("Numbers are cumulative over all runs, in microseconds.\nServer setup: 250\n"));
+        assert!(!format_results(&times, "group", OutputFormat::Json).contains(NANOSECONDS));
+    }
+}
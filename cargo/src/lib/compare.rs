@@ -0,0 +1 @@
+../compare.rs
\ No newline at end of file
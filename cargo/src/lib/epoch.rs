@@ -0,0 +1 @@
+../epoch.rs
\ No newline at end of file
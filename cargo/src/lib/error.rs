@@ -0,0 +1 @@
+../error.rs
\ No newline at end of file
@@ -0,0 +1 @@
+../expiry.rs
\ No newline at end of file
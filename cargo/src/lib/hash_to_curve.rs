@@ -0,0 +1 @@
+../hash_to_curve.rs
\ No newline at end of file
@@ -0,0 +1 @@
+../keyfile.rs
\ No newline at end of file
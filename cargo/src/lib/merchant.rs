@@ -0,0 +1 @@
+../merchant.rs
\ No newline at end of file
@@ -0,0 +1 @@
+../nullifier.rs
\ No newline at end of file
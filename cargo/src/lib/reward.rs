@@ -0,0 +1 @@
+../reward.rs
\ No newline at end of file
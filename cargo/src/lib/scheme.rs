@@ -0,0 +1 @@
+../scheme.rs
\ No newline at end of file
@@ -0,0 +1 @@
+../stats.rs
\ No newline at end of file
@@ -0,0 +1 @@
+../threshold.rs
\ No newline at end of file
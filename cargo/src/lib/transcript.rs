@@ -0,0 +1 @@
+../transcript.rs
\ No newline at end of file
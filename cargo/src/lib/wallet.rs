@@ -0,0 +1 @@
+../wallet.rs
\ No newline at end of file
@@ -0,0 +1 @@
+../wire.rs
\ No newline at end of file
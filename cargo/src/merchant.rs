@@ -0,0 +1,139 @@
+//Hosting many shops on one platform
+//every merchant has its own secret, public key(s) and store of redeemed cards, and its ID is hashed into
+//each of its cards (see card_message) and into the challenge of every punch proof it makes,
+//so a card punched at one shop is never accepted at another, even by a shop that ended up with the same secret
+//servers and cards made without a merchant ID belong to DEFAULT_MERCHANT
+
+use std::collections::BTreeMap;
+use super::error::PunchCardError;
+use super::scheme::{PunchCardScheme, Punched};
+
+pub const DEFAULT_MERCHANT: u32 = 0;
+
+//what a card secret is hashed to the curve as: merchant (u32 little endian) || card_secret
+pub(crate) fn card_message(merchant: u32, card_secret: &[u8; 32]) -> [u8; 36] {
+	let mut message = [0u8; 36];
+	message[..4].copy_from_slice(&merchant.to_le_bytes());
+	message[4..].copy_from_slice(card_secret);
+	message
+}
+
+//everything the platform keeps for one merchant
+struct Merchant<S: PunchCardScheme> {
+	pub_key: S::PublicKey,
+	server: S::Server,
+}
+
+pub struct MerchantRegistry<S: PunchCardScheme> {
+	merchants: BTreeMap<u32, Merchant<S>>,
+}
+
+impl<S: PunchCardScheme> Default for MerchantRegistry<S> {
+	fn default() -> Self {
+		MerchantRegistry::new()
+	}
+}
+
+impl<S: PunchCardScheme> MerchantRegistry<S> {
+
+	pub fn new() -> MerchantRegistry<S> {
+		MerchantRegistry {
+			merchants: BTreeMap::new(),
+		}
+	}
+
+	//give merchant a fresh secret that records its redeemed cards in store
+	//returns the public key its customers check punches against
+	pub fn register(&mut self, merchant: u32, store: S::Store) -> Result<&S::PublicKey, PunchCardError> {
+		if self.merchants.contains_key(&merchant) {
+			return Err(PunchCardError::DuplicateMerchant(merchant));
+		}
+		let (pub_key, server) = S::server_setup_for(merchant, store)?;
+		Ok(&self.merchants.entry(merchant).or_insert(Merchant { pub_key, server }).pub_key)
+	}
+
+	//add a merchant whose key was saved before (see keyfile.rs), the merchant ID comes from the key
+	pub fn import(&mut self, key: &[u8], store: S::Store) -> Result<u32, PunchCardError> {
+		let (pub_key, server) = S::import_key(key, store)?;
+		let merchant = S::merchant(&server);
+		if self.merchants.contains_key(&merchant) {
+			return Err(PunchCardError::DuplicateMerchant(merchant));
+		}
+		self.merchants.insert(merchant, Merchant { pub_key, server });
+		Ok(merchant)
+	}
+
+	//stop serving merchant, handing back its server (e.g. to export its key)
+	pub fn remove(&mut self, merchant: u32) -> Result<S::Server, PunchCardError> {
+		self.merchants.remove(&merchant).map(|m| m.server).ok_or(PunchCardError::UnknownMerchant(merchant))
+	}
+
+	//registered merchant IDs, in order
+	pub fn merchants(&self) -> Vec<u32> {
+		self.merchants.keys().copied().collect()
+	}
+
+	fn merchant(&self, merchant: u32) -> Result<&Merchant<S>, PunchCardError> {
+		self.merchants.get(&merchant).ok_or(PunchCardError::UnknownMerchant(merchant))
+	}
+
+	pub fn public_key(&self, merchant: u32) -> Result<&S::PublicKey, PunchCardError> {
+		Ok(&self.merchant(merchant)?.pub_key)
+	}
+
+	pub fn server(&self, merchant: u32) -> Result<&S::Server, PunchCardError> {
+		Ok(&self.merchant(merchant)?.server)
+	}
+
+	pub fn server_mut(&mut self, merchant: u32) -> Result<&mut S::Server, PunchCardError> {
+		Ok(&mut self.merchants.get_mut(&merchant).ok_or(PunchCardError::UnknownMerchant(merchant))?.server)
+	}
+
+	pub fn server_punch(&self, merchant: u32, card: &S::Card) -> Result<Punched<S>, PunchCardError> {
+		S::server_punch(self.server(merchant)?, card)
+	}
+
+	pub fn server_verify(&mut self, merchant: u32, token: S::RedeemToken, num_punches: u32) -> Result<(), PunchCardError> {
+		S::server_verify(self.server_mut(merchant)?, token, num_punches)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::scheme::testing::{both_schemes, punched_cards};
+
+	//CARDS_PER_REDEMPTION cards for merchant, punched there `punches` times in total
+	fn merchant_cards<S: PunchCardScheme>(registry: &MerchantRegistry<S>, merchant: u32, punches: u32) -> Vec<S::Client> {
+		punched_cards::<S>(punches, registry.public_key(merchant).unwrap(), || S::card_setup_for(merchant),
+						   |card| registry.server_punch(merchant, card))
+	}
+
+	fn shops<S: PunchCardScheme>() where S::Store: Default {
+		let mut registry = MerchantRegistry::<S>::new();
+		registry.register(7, S::Store::default()).unwrap();
+		registry.register(9, S::Store::default()).unwrap();
+		assert_eq!(registry.register(7, S::Store::default()).err(), Some(PunchCardError::DuplicateMerchant(7)));
+		assert_eq!(registry.merchants(), vec![7, 9]);
+		let n = S::CARDS_PER_REDEMPTION;
+
+		let token = S::unmask_redeem(merchant_cards(&registry, 7, 2 * n)).unwrap();
+		registry.server_verify(7, token, 2 * n).unwrap();
+
+		//a card from shop 7 can't be redeemed at shop 9
+		let token = S::unmask_redeem(merchant_cards(&registry, 7, 2 * n)).unwrap();
+		assert_eq!(registry.server_verify(9, token, 2 * n), Err(PunchCardError::InvalidCard));
+
+		//and a punch from shop 9 doesn't verify on a shop 7 card, even against shop 9's key
+		let (card, mut client) = S::card_setup_for(7).unwrap();
+		let (new_card, proof) = registry.server_punch(9, &card).unwrap();
+		assert_eq!(S::verify_remask(&mut client, new_card, registry.public_key(9).unwrap(), proof).err(), Some(PunchCardError::BadProof));
+
+		//a merchant can move to another registry with its key
+		let key = S::export_key(&registry.remove(9).unwrap()).unwrap();
+		assert_eq!(registry.public_key(9).err(), Some(PunchCardError::UnknownMerchant(9)));
+		assert_eq!(registry.import(&key, S::Store::default()), Ok(9));
+	}
+
+	both_schemes!(shops, group_merchants, pairing_merchants);
+}
@@ -0,0 +1,411 @@
+//Storage for the card secrets (nullifiers) of redeemed cards
+//the servers only ever ask whether a card was used and record new ones,
+//so anything that can do that durably can back a server
+
+use sha2::{Sha512, Digest};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use super::error::PunchCardError;
+
+pub trait NullifierStore {
+	fn contains(&self, nullifier: &[u8; 32]) -> bool;
+
+	//record all of the nullifiers together, or none of them if any was already used
+	//(or if the same one appears twice); returns false in that case
+	fn insert_all(&mut self, nullifiers: &[[u8; 32]]) -> Result<bool, PunchCardError>;
+
+	fn len(&self) -> usize;
+
+	//drop every nullifier keep returns false for, e.g. ones from cards that have expired
+	//returns how many were dropped
+	fn retain(&mut self, keep: &mut dyn FnMut(&[u8; 32]) -> bool) -> Result<usize, PunchCardError>;
+
+	//returns false if this was already in the store
+	fn insert(&mut self, nullifier: [u8; 32]) -> Result<bool, PunchCardError> {
+		self.insert_all(&[nullifier])
+	}
+
+	fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+//true if none of the nullifiers are used yet and there are no repeats among them
+fn all_fresh<S: NullifierStore + ?Sized>(store: &S, nullifiers: &[[u8; 32]]) -> bool {
+	let mut seen = HashSet::new();
+	nullifiers.iter().all(|n| !store.contains(n) && seen.insert(*n))
+}
+
+//the original behavior: forgets everything when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryNullifierStore {
+	used_cards: HashSet<[u8; 32]>,
+}
+
+impl MemoryNullifierStore {
+	pub fn new() -> MemoryNullifierStore {
+		MemoryNullifierStore::default()
+	}
+}
+
+impl NullifierStore for MemoryNullifierStore {
+	fn contains(&self, nullifier: &[u8; 32]) -> bool {
+		self.used_cards.contains(nullifier)
+	}
+
+	fn insert_all(&mut self, nullifiers: &[[u8; 32]]) -> Result<bool, PunchCardError> {
+		if !all_fresh(self, nullifiers) {
+			return Ok(false);
+		}
+		self.used_cards.extend(nullifiers.iter().copied());
+		Ok(true)
+	}
+
+	fn len(&self) -> usize {
+		self.used_cards.len()
+	}
+
+	fn retain(&mut self, keep: &mut dyn FnMut(&[u8; 32]) -> bool) -> Result<usize, PunchCardError> {
+		let before = self.used_cards.len();
+		self.used_cards.retain(|n| keep(n));
+		Ok(before - self.used_cards.len())
+	}
+}
+
+//file layout: MAGIC, then one record per insert_all call
+//record: count (u16 little endian) || count*32 bytes of nullifiers || first 8 bytes of Sha512(count || nullifiers)
+//a record is only trusted once its checksum matches, so a crash in the middle of an append
+//leaves a torn record at the very end, which is cut off when the file is reopened
+//anything else that doesn't check out is damage, and opening the file fails rather than drop the records after it
+const MAGIC: &[u8; 8] = b"PCNULL\x00\x01";
+const COUNT_LEN: usize = 2;
+const CHECKSUM_LEN: usize = 8;
+//the most a single append can write
+const MAX_RECORD_LEN: usize = COUNT_LEN + 32*(u16::MAX as usize) + CHECKSUM_LEN;
+
+//durable store: an append-only log that is fsynced before a redemption is accepted
+//all nullifiers are also kept in memory for lookups
+#[derive(Debug)]
+pub struct FileNullifierStore {
+	path: PathBuf,
+	file: File,
+	//length of the log up to the end of the last complete record
+	len: u64,
+	used_cards: HashSet<[u8; 32]>,
+}
+
+fn record_checksum(body: &[u8]) -> [u8; CHECKSUM_LEN] {
+	let digest = Sha512::digest(body);
+	let mut checksum = [0u8; CHECKSUM_LEN];
+	checksum.copy_from_slice(&digest[..CHECKSUM_LEN]);
+	checksum
+}
+
+//one log record holding nullifiers, which must number at most u16::MAX
+fn encode_record(nullifiers: &[[u8; 32]]) -> Vec<u8> {
+	let mut record = Vec::with_capacity(COUNT_LEN + 32*nullifiers.len() + CHECKSUM_LEN);
+	record.extend_from_slice(&(nullifiers.len() as u16).to_le_bytes());
+	for nullifier in nullifiers {
+		record.extend_from_slice(nullifier);
+	}
+	let checksum = record_checksum(&record);
+	record.extend_from_slice(&checksum);
+	record
+}
+
+//length of the record at the start of bytes if it's all there and its checksum matches
+fn complete_record(bytes: &[u8]) -> Option<usize> {
+	if bytes.len() < COUNT_LEN {
+		return None;
+	}
+	let count = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+	let len = COUNT_LEN + 32*count + CHECKSUM_LEN;
+	if bytes.len() < len || record_checksum(&bytes[..len - CHECKSUM_LEN])[..] != bytes[len - CHECKSUM_LEN..len] {
+		return None;
+	}
+	Some(len)
+}
+
+//whether rest, which doesn't start with a complete record, is what's left of an append a crash cut short
+//that's a prefix of a single record: no longer than its count says (or than any record can be),
+//with no complete record anywhere after it
+//a damaged count can claim a record runs past the end of the file, so the records that follow are looked for
+//byte by byte rather than trusting it, and finding one means the file was damaged, not torn
+fn torn_tail(rest: &[u8]) -> bool {
+	if rest.len() > MAX_RECORD_LEN {
+		return false;
+	}
+	if rest.len() >= COUNT_LEN {
+		let count = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+		if rest.len() > COUNT_LEN + 32*count + CHECKSUM_LEN {
+			return false;
+		}
+	}
+	(1..rest.len()).all(|i| complete_record(&rest[i..]).is_none())
+}
+
+//make a rename or a newly created file in path's directory durable
+//syncing the file itself only covers its contents, the directory entry pointing at it is synced separately
+pub(crate) fn sync_parent_dir(path: &Path) -> Result<(), PunchCardError> {
+	#[cfg(unix)]
+	{
+		let parent = match path.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => parent,
+			_ => Path::new("."),
+		};
+		File::open(parent)?.sync_all()?;
+	}
+	#[cfg(not(unix))]
+	let _ = path;
+	Ok(())
+}
+
+impl FileNullifierStore {
+
+	//open the log at path, creating it if needed
+	//a torn record left at the end by a crash is truncated away (see torn_tail)
+	//a bad record anywhere else means the file was damaged and is reported as CorruptStore
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<FileNullifierStore, PunchCardError> {
+		let path = path.as_ref().to_path_buf();
+		let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+
+		let mut contents = Vec::new();
+		file.read_to_end(&mut contents)?;
+
+		if contents.is_empty() {
+			file.write_all(MAGIC)?;
+			file.sync_all()?;
+			sync_parent_dir(&path)?;
+			return Ok(FileNullifierStore {
+				path,
+				file,
+				len: MAGIC.len() as u64,
+				used_cards: HashSet::new(),
+			});
+		}
+		if contents.len() < MAGIC.len() || &contents[..MAGIC.len()] != MAGIC {
+			return Err(PunchCardError::CorruptStore);
+		}
+
+		let mut used_cards = HashSet::new();
+		let mut pos = MAGIC.len();
+		while pos < contents.len() {
+			let rest = &contents[pos..];
+			let len = match complete_record(rest) {
+				Some(len) => len,
+				None => {
+					if !torn_tail(rest) {
+						return Err(PunchCardError::CorruptStore);
+					}
+					file.set_len(pos as u64)?;
+					file.sync_all()?;
+					break;
+				},
+			};
+
+			for nullifier in rest[COUNT_LEN..len - CHECKSUM_LEN].chunks(32) {
+				let mut entry = [0u8; 32];
+				entry.copy_from_slice(nullifier);
+				used_cards.insert(entry);
+			}
+			pos += len;
+		}
+		file.seek(SeekFrom::End(0))?;
+
+		Ok(FileNullifierStore {
+			path,
+			file,
+			len: pos as u64,
+			used_cards,
+		})
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+}
+
+impl NullifierStore for FileNullifierStore {
+	fn contains(&self, nullifier: &[u8; 32]) -> bool {
+		self.used_cards.contains(nullifier)
+	}
+
+	//the nullifiers are written as one record and synced before they count as used
+	fn insert_all(&mut self, nullifiers: &[[u8; 32]]) -> Result<bool, PunchCardError> {
+		if !all_fresh(self, nullifiers) {
+			return Ok(false);
+		}
+		if nullifiers.len() > u16::MAX as usize {
+			return Err(PunchCardError::BadLength { expected: u16::MAX as usize, got: nullifiers.len() });
+		}
+
+		let record = encode_record(nullifiers);
+
+		//if the append fails part way, cut it off again so later records don't follow a torn one
+		if let Err(err) = self.file.write_all(&record).and_then(|_| self.file.sync_data()) {
+			let _ = self.file.set_len(self.len);
+			let _ = self.file.seek(SeekFrom::Start(self.len));
+			return Err(err.into());
+		}
+		self.len += record.len() as u64;
+
+		self.used_cards.extend(nullifiers.iter().copied());
+		Ok(true)
+	}
+
+	fn len(&self) -> usize {
+		self.used_cards.len()
+	}
+
+	//the log is rewritten with only the kept nullifiers into a temporary file, which is synced
+	//and renamed over the old one, then the directory is synced so the rename itself survives a crash
+	//a crash leaves either the old log or the new one, and once this returns it's the new one
+	fn retain(&mut self, keep: &mut dyn FnMut(&[u8; 32]) -> bool) -> Result<usize, PunchCardError> {
+		let kept: Vec<[u8; 32]> = self.used_cards.iter().filter(|n| keep(n)).copied().collect();
+		let dropped = self.used_cards.len() - kept.len();
+		if dropped == 0 {
+			return Ok(0);
+		}
+
+		let mut contents = MAGIC.to_vec();
+		for chunk in kept.chunks(u16::MAX as usize) {
+			contents.extend_from_slice(&encode_record(chunk));
+		}
+		let mut tmp_path = self.path.as_os_str().to_owned();
+		tmp_path.push(".tmp");
+		let mut tmp = File::create(&tmp_path)?;
+		tmp.write_all(&contents)?;
+		tmp.sync_all()?;
+		drop(tmp);
+		fs::rename(&tmp_path, &self.path)?;
+		sync_parent_dir(&self.path)?;
+
+		let mut file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+		file.seek(SeekFrom::End(0))?;
+		self.file = file;
+		self.len = contents.len() as u64;
+		self.used_cards = kept.into_iter().collect();
+		Ok(dropped)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+
+	fn temp_path(name: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("punchcard-{}-{}", name, std::process::id()));
+		let _ = fs::remove_file(&path);
+		path
+	}
+
+	#[test]
+	fn memory_store_rejects_reuse() {
+		let mut store = MemoryNullifierStore::new();
+		assert!(store.insert([1u8; 32]).unwrap());
+		assert!(!store.insert([1u8; 32]).unwrap());
+		assert!(!store.insert_all(&[[2u8; 32], [2u8; 32]]).unwrap());
+		assert!(!store.insert_all(&[[2u8; 32], [1u8; 32]]).unwrap());
+		assert!(!store.contains(&[2u8; 32]));
+		assert_eq!(store.len(), 1);
+	}
+
+	#[test]
+	fn file_store_survives_reopen() {
+		let path = temp_path("reopen");
+		{
+			let mut store = FileNullifierStore::open(&path).unwrap();
+			assert!(store.insert([1u8; 32]).unwrap());
+			assert!(store.insert_all(&[[2u8; 32], [3u8; 32]]).unwrap());
+		}
+		let mut store = FileNullifierStore::open(&path).unwrap();
+		assert_eq!(store.len(), 3);
+		assert!(!store.insert([3u8; 32]).unwrap());
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn file_store_recovers_torn_tail() {
+		let path = temp_path("torn");
+		{
+			let mut store = FileNullifierStore::open(&path).unwrap();
+			store.insert([1u8; 32]).unwrap();
+			store.insert_all(&[[2u8; 32], [3u8; 32]]).unwrap();
+		}
+		//chop the last record in half as if we crashed while writing it
+		let full_len = fs::metadata(&path).unwrap().len();
+		let torn_len = full_len - 40;
+		OpenOptions::new().write(true).open(&path).unwrap().set_len(torn_len).unwrap();
+
+		let mut store = FileNullifierStore::open(&path).unwrap();
+		assert_eq!(store.len(), 1);
+		assert!(store.contains(&[1u8; 32]));
+		assert!(store.insert([2u8; 32]).unwrap());
+		drop(store);
+
+		let store = FileNullifierStore::open(&path).unwrap();
+		assert_eq!(store.len(), 2);
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn file_store_prunes() {
+		let path = temp_path("prune");
+		{
+			let mut store = FileNullifierStore::open(&path).unwrap();
+			store.insert_all(&[[1u8; 32], [2u8; 32], [3u8; 32]]).unwrap();
+			assert_eq!(store.retain(&mut |n| n[0] != 2).unwrap(), 1);
+			assert!(store.insert([4u8; 32]).unwrap());
+		}
+		let mut store = FileNullifierStore::open(&path).unwrap();
+		assert_eq!(store.len(), 3);
+		assert!(!store.contains(&[2u8; 32]));
+		assert!(!store.insert([3u8; 32]).unwrap());
+		//the rewrite left no temporary file behind
+		let mut tmp_path = path.as_os_str().to_owned();
+		tmp_path.push(".tmp");
+		assert!(!Path::new(&tmp_path).exists());
+		fs::remove_file(&path).unwrap();
+
+		//a bare file name syncs the current directory
+		sync_parent_dir(Path::new("nullifiers.log")).unwrap();
+	}
+
+	#[test]
+	fn file_store_detects_corruption() {
+		let path = temp_path("corrupt");
+		{
+			let mut store = FileNullifierStore::open(&path).unwrap();
+			store.insert([1u8; 32]).unwrap();
+			store.insert([2u8; 32]).unwrap();
+		}
+		//flip a byte in the first record, which is followed by another record
+		let mut contents = fs::read(&path).unwrap();
+		contents[MAGIC.len() + COUNT_LEN] ^= 1;
+		fs::write(&path, &contents).unwrap();
+
+		assert_eq!(FileNullifierStore::open(&path).err(), Some(PunchCardError::CorruptStore));
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn file_store_detects_bad_count() {
+		let path = temp_path("bad-count");
+		{
+			let mut store = FileNullifierStore::open(&path).unwrap();
+			store.insert([1u8; 32]).unwrap();
+			store.insert([2u8; 32]).unwrap();
+		}
+		//the first record now claims to run past the end of the file, which must not truncate the second one away
+		let mut contents = fs::read(&path).unwrap();
+		contents[MAGIC.len() + 1] ^= 1;
+		fs::write(&path, &contents).unwrap();
+
+		assert_eq!(FileNullifierStore::open(&path).err(), Some(PunchCardError::CorruptStore));
+		assert_eq!(fs::read(&path).unwrap(), contents);
+		fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,204 @@
+//Reward tiers and redeeming a card for one of them
+//the server still checks the card against its exact punch count, the tier only has to be
+//at or below that count, so a card punched 12 times can be redeemed for a 10 punch reward
+//(the extra punches are given up, unless they're carried over with redeem_with_carry_over)
+
+use super::error::PunchCardError;
+use super::scheme::{PunchCardScheme, Punched};
+
+//one reward and how many punches it costs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewardTier {
+	pub punches: u32,
+	pub reward: String,
+}
+
+//the rewards a server offers, ordered by punches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewardPolicy {
+	tiers: Vec<RewardTier>,
+}
+
+//what the client claims when redeeming: how many punches the card holds and which tier it wants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewardClaim {
+	pub punches: u32,
+	pub threshold: u32,
+}
+
+impl RewardPolicy {
+
+	//tiers are (punches, reward) pairs, e.g. [(5, "small coffee"), (10, "large coffee")]
+	//fails if a tier costs no punches or two tiers cost the same
+	pub fn new<R: Into<String>>(tiers: Vec<(u32, R)>) -> Result<RewardPolicy, PunchCardError> {
+		let mut tiers: Vec<RewardTier> = tiers.into_iter()
+			.map(|(punches, reward)| RewardTier { punches, reward: reward.into() })
+			.collect();
+		tiers.sort_by_key(|tier| tier.punches);
+		if let Some(tier) = tiers.first().filter(|tier| tier.punches == 0) {
+			return Err(PunchCardError::InvalidPunchCount(tier.punches));
+		}
+		if let Some(pair) = tiers.windows(2).find(|pair| pair[0].punches == pair[1].punches) {
+			return Err(PunchCardError::DuplicateReward(pair[0].punches));
+		}
+		Ok(RewardPolicy { tiers })
+	}
+
+	pub fn tiers(&self) -> &[RewardTier] {
+		&self.tiers
+	}
+
+	//the tier redeemed at exactly threshold punches
+	pub fn tier(&self, threshold: u32) -> Option<&RewardTier> {
+		self.tiers.iter().find(|tier| tier.punches == threshold)
+	}
+
+	//the largest tier a card with this many punches can get
+	pub fn best_tier(&self, punches: u32) -> Option<&RewardTier> {
+		self.tiers.iter().rev().find(|tier| tier.punches <= punches)
+	}
+
+	//the tiers' punch counts
+	//cards are checked at the punches they hold (claim.punches), not at the tier, so passing these to
+	//PunchCardScheme::precompute_thresholds only speeds up claims for exactly a tier's punches,
+	//a card punched 12 times for a 10 punch reward is checked without the cache
+	pub fn thresholds(&self) -> Vec<u32> {
+		self.tiers.iter().map(|tier| tier.punches).collect()
+	}
+}
+
+//redeem a token for the tier in claim
+//checks that the tier exists, that the card holds enough punches for it,
+//and (through server_verify) that the card really has claim.punches punches and wasn't redeemed before
+//returns the reward granted
+pub fn redeem_reward<'a, S: PunchCardScheme>(server: &mut S::Server, policy: &'a RewardPolicy, token: S::RedeemToken,
+											 claim: RewardClaim) -> Result<&'a RewardTier, PunchCardError> {
+	let tier = policy.tier(claim.threshold).ok_or(PunchCardError::UnknownReward(claim.threshold))?;
+	if claim.punches < tier.punches {
+		return Err(PunchCardError::NotEnoughPunches { needed: tier.punches, got: claim.punches });
+	}
+	S::server_verify(server, token, claim.punches)?;
+	Ok(tier)
+}
+
+//result of redeem_with_carry_over
+pub struct Redemption<'a, S: PunchCardScheme> {
+	pub tier: &'a RewardTier,
+	//punches left over after paying for the tier
+	pub carried: u32,
+	//the client's fresh card punched carried times, and the proof for it
+	//None if nothing was left over
+	pub carry_over: Option<Punched<S>>,
+}
+
+//redeem a token like redeem_reward, and punch the client's fresh card with the punches left over
+//the fresh card is punched by claim.punches - tier in one go (see PunchCardScheme::server_punch_by),
+//so the client checks it with verify_remask_by against the server's published powers
+//the fresh card is punched before the old one is marked used, so a bad fresh card doesn't cost the client the old one
+pub fn redeem_with_carry_over<'a, S: PunchCardScheme>(server: &mut S::Server, policy: &'a RewardPolicy, token: S::RedeemToken,
+													  claim: RewardClaim, fresh_card: &S::Card) -> Result<Redemption<'a, S>, PunchCardError> {
+	let tier = policy.tier(claim.threshold).ok_or(PunchCardError::UnknownReward(claim.threshold))?;
+	if claim.punches < tier.punches {
+		return Err(PunchCardError::NotEnoughPunches { needed: tier.punches, got: claim.punches });
+	}
+	let carried = claim.punches - tier.punches;
+	let carry_over = if carried > 0 {
+		Some(S::server_punch_by(server, fresh_card, carried)?)
+	} else {
+		None
+	};
+	S::server_verify(server, token, claim.punches)?;
+	Ok(Redemption {
+		tier,
+		carried,
+		carry_over,
+	})
+}
+
+//client side of a carry over: check the punched fresh card and take its punches
+//fresh is the client made with card_setup whose card was sent along with the redemption
+pub fn accept_carry_over<S: PunchCardScheme>(fresh: &mut S::Client, redemption: Redemption<S>, powers: &S::PowerKey) -> Result<(), PunchCardError> {
+	if let Some((card, proof)) = redemption.carry_over {
+		S::verify_remask_by(fresh, card, powers, redemption.carried, proof)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::scheme::testing::{both_schemes, punch_more, token};
+
+	fn coffee() -> RewardPolicy {
+		RewardPolicy::new(vec![(10, "large coffee"), (5, "small coffee")]).unwrap()
+	}
+
+	//punch a card (or cards) punches times and redeem it with claim, returning the reward's name
+	fn redeem<S: PunchCardScheme>(server: &mut S::Server, pub_key: &S::PublicKey, punches: u32, claim: RewardClaim) -> Result<String, PunchCardError> {
+		let token = token::<S>(server, pub_key, punches);
+		redeem_reward::<S>(server, &coffee(), token, claim).map(|tier| tier.reward.clone())
+	}
+
+	fn tiers<S: PunchCardScheme>() where S::Store: Default {
+		let (pub_key, mut server) = S::server_setup().unwrap();
+
+		let claim = RewardClaim { punches: 12, threshold: 10 };
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 12, claim), Ok("large coffee".to_owned()));
+		//an odd count is split unevenly between merged cards
+		let claim = RewardClaim { punches: 11, threshold: 10 };
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 11, claim), Ok("large coffee".to_owned()));
+
+		let claim = RewardClaim { punches: 6, threshold: 10 };
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 6, claim), Err(PunchCardError::NotEnoughPunches { needed: 10, got: 6 }));
+
+		let claim = RewardClaim { punches: 6, threshold: 6 };
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 6, claim), Err(PunchCardError::UnknownReward(6)));
+
+		//claiming more punches than the card has
+		let claim = RewardClaim { punches: 10, threshold: 10 };
+		assert_eq!(redeem::<S>(&mut server, &pub_key, 6, claim), Err(PunchCardError::InvalidCard));
+	}
+
+	//12 punches redeemed for 10 leaves 2 on a fresh card, which can be redeemed later
+	fn carry_over<S: PunchCardScheme>() where S::Store: Default {
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		let powers = S::publish_powers(&server, 10).unwrap();
+		S::verify_powers(&pub_key, &powers).unwrap();
+		let policy = coffee();
+
+		let token = token::<S>(&server, &pub_key, 12);
+		let (fresh_card, mut fresh) = S::card_setup().unwrap();
+		let claim = RewardClaim { punches: 12, threshold: 10 };
+		let redemption = redeem_with_carry_over::<S>(&mut server, &policy, token, claim, &fresh_card).unwrap();
+		assert_eq!(redemption.tier.reward, "large coffee");
+		assert_eq!(redemption.carried, 2);
+		accept_carry_over::<S>(&mut fresh, redemption, &powers).unwrap();
+		assert_eq!(S::get_count(&fresh).unwrap(), 2);
+
+		//keep punching the fresh card (plus any other cards the scheme merges) up to 5 and redeem again
+		punch_more::<S>(&mut fresh, 3, &pub_key, &mut |card| S::server_punch(&server, card));
+		let card = S::get_card(&fresh).unwrap();
+		let mut clients = vec![fresh];
+		for _ in 1..S::CARDS_PER_REDEMPTION {
+			clients.push(S::card_setup().unwrap().1);
+		}
+		let claim = RewardClaim { punches: 5, threshold: 5 };
+		let redemption = redeem_with_carry_over::<S>(&mut server, &policy, S::unmask_redeem(clients).unwrap(), claim, &card).unwrap();
+		assert_eq!(redemption.tier.reward, "small coffee");
+		assert!(redemption.carry_over.is_none());
+	}
+
+	#[test]
+	fn policy_tiers() {
+		let policy = coffee();
+		assert_eq!(policy.thresholds(), vec![5, 10]);
+		assert_eq!(policy.best_tier(12).unwrap().reward, "large coffee");
+		assert_eq!(policy.best_tier(7).unwrap().reward, "small coffee");
+		assert_eq!(policy.best_tier(4), None);
+		assert_eq!(RewardPolicy::new(vec![(5, "a"), (5, "b")]), Err(PunchCardError::DuplicateReward(5)));
+		assert_eq!(RewardPolicy::new(vec![(0, "free")]), Err(PunchCardError::InvalidPunchCount(0)));
+	}
+
+	both_schemes!(tiers, group_reward_tiers, pairing_reward_tiers);
+	both_schemes!(carry_over, group_carry_over, pairing_carry_over);
+}
@@ -0,0 +1,425 @@
+//Common interface over the two punch card backends
+//crypto.rs implements it with Ristretto (GroupScheme)
+//crypto_pairing.rs implements it with BLS12-381 (PairingScheme)
+//so that service code and the benchmark can be written once for either
+//anything that depends on input from the other party returns a PunchCardError on failure
+
+use super::error::{PunchCardError, AuditedRejection};
+use super::nullifier::NullifierStore;
+use super::merchant::DEFAULT_MERCHANT;
+use super::expiry::ExpiryPolicy;
+
+//a punched card and the proof for it, as returned by the server
+pub type Punched<S> = (<S as PunchCardScheme>::Card, <S as PunchCardScheme>::Proof);
+
+pub trait PunchCardScheme {
+	//where the server records redeemed card secrets
+	type Store: NullifierStore;
+	//server state: secret and redeemed card db
+	type Server;
+	//what the server publishes so clients can check punches
+	type PublicKey;
+	//domain separation tag(s) cards are hashed to the curve with (see hash_to_curve.rs)
+	type Dst;
+	//client state for one card
+	type Client;
+	//masked card sent to the server to be punched
+	type Card;
+	//proof that a punch was done honestly
+	type Proof;
+	//what the client hands over at redemption
+	type RedeemToken;
+	//published powers g^(secret^k) that let clients check multi-punches
+	type PowerKey;
+
+	//human readable name used in benchmark output
+	const NAME: &'static str;
+
+	//how many cards are combined in one redemption
+	//1 for the plain scheme, 2 for the mergeable pairing scheme
+	const CARDS_PER_REDEMPTION: u32;
+
+	//the DSTs servers and cards use unless they're set up with others
+	fn default_dst() -> Self::Dst;
+
+	//set up the secret for merchant, hashing cards with dst instead of the default
+	//it only takes cards set up with the same dst, and keeps it through export_key and import_key
+	fn server_setup_with_dst(merchant: u32, store: Self::Store, dst: &Self::Dst) -> Result<(Self::PublicKey, Self::Server), PunchCardError>;
+
+	//set up the secret for merchant (see merchant.rs), recording redeemed cards in store
+	fn server_setup_for(merchant: u32, store: Self::Store) -> Result<(Self::PublicKey, Self::Server), PunchCardError> {
+		Self::server_setup_with_dst(merchant, store, &Self::default_dst())
+	}
+
+	//set up the server secret, recording redeemed cards in store
+	fn server_setup_with_store(store: Self::Store) -> Result<(Self::PublicKey, Self::Server), PunchCardError> {
+		Self::server_setup_for(DEFAULT_MERCHANT, store)
+	}
+
+	//set up the server secret and an empty redeemed card db
+	fn server_setup() -> Result<(Self::PublicKey, Self::Server), PunchCardError>
+		where Self::Store: Default
+	{
+		Self::server_setup_with_store(Self::Store::default())
+	}
+
+	//serialize the server secret and public key(s), tagged with the scheme (see keyfile.rs)
+	fn export_key(server: &Self::Server) -> Result<Vec<u8>, PunchCardError>;
+
+	//reload a server from an exported key, recording redeemed cards in store
+	fn import_key(key: &[u8], store: Self::Store) -> Result<(Self::PublicKey, Self::Server), PunchCardError>;
+
+	//the merchant whose cards server punches and redeems
+	fn merchant(server: &Self::Server) -> u32;
+
+	//bind server's proofs from now on to epoch (see epoch.rs), they start out in DEFAULT_EPOCH
+	fn set_epoch(server: &mut Self::Server, epoch: u32);
+
+	//from now on refuse card secrets policy doesn't take at every redemption and merge (see expiry.rs)
+	//servers start out with None, which takes any card
+	fn set_expiry_policy(server: &mut Self::Server, policy: Option<ExpiryPolicy>);
+
+	fn expiry_policy(server: &Self::Server) -> Option<ExpiryPolicy>;
+
+	//check punches on client's card as made in epoch, it has to match the epoch of the server punching it
+	fn set_card_epoch(client: &mut Self::Client, epoch: u32);
+
+	//create a new punchcard for merchant that's hashed with dst and expires after epoch expiry if there is one
+	//the card keeps dst through save_client and restore_client
+	fn card_setup_with_dst(merchant: u32, expiry: Option<u32>, dst: &Self::Dst) -> Result<(Self::Card, Self::Client), PunchCardError>;
+
+	//create a new punchcard for merchant, already masked
+	fn card_setup_for(merchant: u32) -> Result<(Self::Card, Self::Client), PunchCardError> {
+		Self::card_setup_with_dst(merchant, None, &Self::default_dst())
+	}
+
+	//create a new punchcard, already masked
+	fn card_setup() -> Result<(Self::Card, Self::Client), PunchCardError> {
+		Self::card_setup_for(DEFAULT_MERCHANT)
+	}
+
+	//create a new punchcard for merchant that expires after epoch expiry, see expiry.rs
+	fn card_setup_until(merchant: u32, expiry: u32) -> Result<(Self::Card, Self::Client), PunchCardError> {
+		Self::card_setup_with_dst(merchant, Some(expiry), &Self::default_dst())
+	}
+
+	//punch card and prove that this was done honestly
+	fn server_punch(server: &Self::Server, card: &Self::Card) -> Result<(Self::Card, Self::Proof), PunchCardError>;
+
+	//verify proof from the server
+	//if accepted, unmask punchcard, remask with new mask, increment count
+	//otherwise return BadProof and keep the old punchcard, same count
+	fn verify_remask(client: &mut Self::Client, card: Self::Card, pub_key: &Self::PublicKey,
+					 proof: Self::Proof) -> Result<Self::Card, PunchCardError>;
+
+	//precompute secret^n (and g^(secret^n)) for the punch counts cards are usually redeemed or punched at,
+	//so those redemptions and punches by n skip the exponentiation
+	fn precompute_thresholds(server: &mut Self::Server, thresholds: &[u32]) -> Result<(), PunchCardError>;
+
+	//publish g^(secret^k) for k = 1 to max_k, with proofs chaining each one to the public key
+	fn publish_powers(server: &Self::Server, max_k: u32) -> Result<Self::PowerKey, PunchCardError>;
+
+	//check published powers against the public key, clients should do this once before trusting them
+	fn verify_powers(pub_key: &Self::PublicKey, powers: &Self::PowerKey) -> Result<(), PunchCardError>;
+
+	//punch the card k times in one go (raise it to secret^k), with a single proof against g^(secret^k)
+	fn server_punch_by(server: &Self::Server, card: &Self::Card, k: u32) -> Result<(Self::Card, Self::Proof), PunchCardError>;
+
+	//verify_remask for a punch by k, the count goes up by k
+	//fails with InvalidPunchCount if k is not covered by powers
+	fn verify_remask_by(client: &mut Self::Client, card: Self::Card, powers: &Self::PowerKey, k: u32,
+						proof: Self::Proof) -> Result<Self::Card, PunchCardError>;
+
+	//check many punches against the same public key at once, e.g. punches a client got while offline
+	//or a server's log of punches being audited
+	//each entry is (card sent to the server, punched card returned, proof), all made by merchant in epoch
+	//fails with BadProofAt(i), where i is the first punch whose proof doesn't verify
+	fn batch_verify(pub_key: &Self::PublicKey, merchant: u32, epoch: u32, punches: &[(&Self::Card, &Self::Card, &Self::Proof)]) -> Result<(), PunchCardError>;
+
+	fn get_count(client: &Self::Client) -> Result<u32, PunchCardError>;
+
+	//the current masked card, to send for the next punch
+	fn get_card(client: &Self::Client) -> Result<Self::Card, PunchCardError>;
+
+	//serialize the full client state so a card can outlive the process (see wallet.rs)
+	fn save_client(client: &Self::Client) -> Result<Vec<u8>, PunchCardError>;
+
+	//restore a saved card, checking that the saved state is consistent
+	fn restore_client(state: &[u8]) -> Result<Self::Client, PunchCardError>;
+
+	//unmask the punch cards (CARDS_PER_REDEMPTION of them) and merge them into a token
+	fn unmask_redeem(clients: Vec<Self::Client>) -> Result<Self::RedeemToken, PunchCardError>;
+
+	//check that the token is valid with num_punches
+	//check that the card secrets are new and taken by the server's expiry policy
+	//fails with InvalidCard, DoubleRedemption or the policy's error otherwise
+	fn server_verify(server: &mut Self::Server, token: Self::RedeemToken, num_punches: u32) -> Result<(), PunchCardError>;
+
+	//the card secrets a token will use up when it's redeemed
+	fn nullifiers(token: &Self::RedeemToken) -> Vec<[u8; 32]>;
+
+	//the server's redeemed card db, for maintenance like pruning expired cards
+	fn store_mut(server: &mut Self::Server) -> &mut Self::Store;
+
+	fn count_cards(server: &Self::Server) -> usize;
+
+	//preload the database of used cards with num entries
+	fn cheat_setup_db(server: &mut Self::Server, num: u32) -> Result<(), PunchCardError>;
+}
+
+//redemptions where the server proves how it decided, for the client or an auditor
+//GroupScheme and PairingScheme both implement it
+pub trait AuditedScheme: PunchCardScheme {
+	//the server's evidence for one redemption decision, holding the token, the count and the outcome
+	type Receipt: std::fmt::Debug;
+
+	//server_verify that also proves how it decided
+	//Ok only if the token was redeemed, a rejected or double redeemed token is an AuditedRejection carrying its receipt,
+	//without one for failures that leave nothing to audit (e.g. the expiry policy's)
+	//like server_verify only a valid token uses up its card secrets, a rejected one gets a proof that it doesn't
+	//hold num_punches that doesn't give out a card that would, and can be sent again
+	fn server_verify_audited(server: &mut Self::Server, token: Self::RedeemToken, num_punches: u32) -> Result<Self::Receipt, AuditedRejection<Self::Receipt>>;
+
+	//check the receipt's evidence and that its outcome follows from it
+	//powers must reach the receipt's count and should have been checked with verify_powers
+	//BadProof if the evidence doesn't verify, DishonestRedemption if the outcome doesn't match it
+	fn audit(receipt: &Self::Receipt, powers: &Self::PowerKey) -> Result<(), PunchCardError>;
+}
+
+//whether a receipt's outcome follows from whether its evidence shows a valid card
+//whether a card was already redeemed can't be proven, a DoubleRedemption only needs the card itself to be valid
+pub(crate) fn check_outcome(valid: bool, outcome: &Result<(), PunchCardError>) -> Result<(), PunchCardError> {
+	let honest = match outcome {
+		Ok(()) | Err(PunchCardError::DoubleRedemption) => valid,
+		Err(PunchCardError::InvalidCard) => !valid,
+		Err(_) => false,
+	};
+	if !honest {
+		return Err(PunchCardError::DishonestRedemption);
+	}
+	Ok(())
+}
+
+//helpers shared by the tests of the modules built on PunchCardScheme
+#[cfg(test)]
+pub(crate) mod testing {
+	use super::*;
+
+	//punch client k more times with punch, checking every punch against pub_key
+	pub(crate) fn punch_more<S: PunchCardScheme>(client: &mut S::Client, k: u32, pub_key: &S::PublicKey,
+												 punch: &mut impl FnMut(&S::Card) -> Result<Punched<S>, PunchCardError>) {
+		let mut card = S::get_card(client).unwrap();
+		for _ in 0..k {
+			let (new_card, proof) = punch(&card).unwrap();
+			card = S::verify_remask(client, new_card, pub_key, proof).unwrap();
+		}
+	}
+
+	//CARDS_PER_REDEMPTION cards made by setup, ready to redeem for `punches` in total
+	//the punches are split between the cards as evenly as they go, with the first cards taking the extra ones
+	pub(crate) fn punched_cards<S: PunchCardScheme>(punches: u32, pub_key: &S::PublicKey,
+													mut setup: impl FnMut() -> Result<(S::Card, S::Client), PunchCardError>,
+													mut punch: impl FnMut(&S::Card) -> Result<Punched<S>, PunchCardError>) -> Vec<S::Client> {
+		let n = S::CARDS_PER_REDEMPTION;
+		(0..n).map(|i| {
+			let (_, mut client) = setup().unwrap();
+			punch_more::<S>(&mut client, punches / n + u32::from(i < punches % n), pub_key, &mut punch);
+			client
+		}).collect()
+	}
+
+	//punched_cards from a plain server, unmasked into a token
+	pub(crate) fn token<S: PunchCardScheme>(server: &S::Server, pub_key: &S::PublicKey, punches: u32) -> S::RedeemToken {
+		S::unmask_redeem(punched_cards::<S>(punches, pub_key, S::card_setup, |card| S::server_punch(server, card))).unwrap()
+	}
+
+	//a #[test] running $test for each backend, named $group and $pairing
+	//for use in the tests module of a top level module (the library is also compiled into the binary as lib,
+	//so $crate doesn't name the same place in both)
+	macro_rules! both_schemes {
+		($test:ident, $group:ident, $pairing:ident) => {
+			#[test]
+			fn $group() {
+				$test::<super::super::crypto::GroupScheme>();
+			}
+
+			#[test]
+			fn $pairing() {
+				$test::<super::super::crypto_pairing::PairingScheme>();
+			}
+		};
+	}
+	pub(crate) use both_schemes;
+
+	//a DST other than the scheme's default_dst
+	pub(crate) trait OtherDst {
+		fn other_dst() -> Self;
+	}
+
+	impl OtherDst for Vec<u8> {
+		fn other_dst() -> Self {
+			b"PunchCard-test-ristretto255_XMD:SHA-512_R255MAP_RO_".to_vec()
+		}
+	}
+
+	impl OtherDst for (Vec<u8>, Vec<u8>) {
+		fn other_dst() -> Self {
+			(b"PunchCard-test-BLS12381G1_XMD:SHA-256_SSWU_RO_".to_vec(), b"PunchCard-test-BLS12381G2_XMD:SHA-256_SSWU_RO_".to_vec())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::epoch::DEFAULT_EPOCH;
+	use super::testing::{both_schemes, punched_cards, OtherDst};
+
+	type PunchLog<S> = Vec<(<S as PunchCardScheme>::Card, <S as PunchCardScheme>::Card, <S as PunchCardScheme>::Proof)>;
+
+	//k punches, each on a fresh card, as the server's log would record them
+	fn punch_log<S: PunchCardScheme>(k: usize) -> (S::PublicKey, PunchLog<S>)
+		where S::Store: Default
+	{
+		let (pub_key, server) = S::server_setup().unwrap();
+		let (mut card, _) = S::card_setup().unwrap();
+		let mut log = Vec::new();
+		for _ in 0..k {
+			let (new_card, proof) = S::server_punch(&server, &card).unwrap();
+			let (next_card, _) = S::card_setup().unwrap();
+			log.push((card, new_card, proof));
+			card = next_card;
+		}
+		(pub_key, log)
+	}
+
+	fn batch<S: PunchCardScheme>() where S::Store: Default {
+		let (pub_key, mut log) = punch_log::<S>(5);
+		let refs: Vec<_> = log.iter().map(|(c, n, p)| (c, n, p)).collect();
+		assert_eq!(S::batch_verify(&pub_key, DEFAULT_MERCHANT, DEFAULT_EPOCH, &refs), Ok(()));
+		assert_eq!(S::batch_verify(&pub_key, DEFAULT_MERCHANT, DEFAULT_EPOCH, &[]), Ok(()));
+		//the merchant is part of every proof's transcript
+		assert_eq!(S::batch_verify(&pub_key, DEFAULT_MERCHANT + 1, DEFAULT_EPOCH, &refs), Err(PunchCardError::BadProofAt(0)));
+		//and so is the epoch
+		assert_eq!(S::batch_verify(&pub_key, DEFAULT_MERCHANT, DEFAULT_EPOCH + 1, &refs), Err(PunchCardError::BadProofAt(0)));
+
+		//a proof for a different punch doesn't verify
+		let (a, b) = log.split_at_mut(3);
+		std::mem::swap(&mut a[2].2, &mut b[0].2);
+		let refs: Vec<_> = log.iter().map(|(c, n, p)| (c, n, p)).collect();
+		assert_eq!(S::batch_verify(&pub_key, DEFAULT_MERCHANT, DEFAULT_EPOCH, &refs), Err(PunchCardError::BadProofAt(2)));
+
+		//neither does one from another server
+		let (other_key, _) = S::server_setup().unwrap();
+		let (_, log) = punch_log::<S>(2);
+		let refs: Vec<_> = log.iter().map(|(c, n, p)| (c, n, p)).collect();
+		assert_eq!(S::batch_verify(&other_key, DEFAULT_MERCHANT, DEFAULT_EPOCH, &refs), Err(PunchCardError::BadProofAt(0)));
+	}
+
+	//a punch by 3 and a plain punch add up to 4 on every card
+	fn multi_punch<S: PunchCardScheme>() where S::Store: Default {
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		let powers = S::publish_powers(&server, 3).unwrap();
+		S::verify_powers(&pub_key, &powers).unwrap();
+		let (other_key, _) = S::server_setup().unwrap();
+		assert!(S::verify_powers(&other_key, &powers).is_err());
+
+		let mut clients = Vec::new();
+		for _ in 0..S::CARDS_PER_REDEMPTION {
+			let (card, mut client) = S::card_setup().unwrap();
+
+			//checking against the wrong power fails and leaves the card alone
+			let (new_card, proof) = S::server_punch_by(&server, &card, 3).unwrap();
+			assert_eq!(S::verify_remask_by(&mut client, new_card, &powers, 2, proof).err(), Some(PunchCardError::BadProof));
+			assert_eq!(S::get_count(&client).unwrap(), 0);
+
+			let (new_card, proof) = S::server_punch_by(&server, &card, 3).unwrap();
+			let card = S::verify_remask_by(&mut client, new_card, &powers, 3, proof).unwrap();
+			let (new_card, proof) = S::server_punch(&server, &card).unwrap();
+			S::verify_remask(&mut client, new_card, &pub_key, proof).unwrap();
+			assert_eq!(S::get_count(&client).unwrap(), 4);
+
+			let card = S::get_card(&client).unwrap();
+			let (new_card, proof) = S::server_punch_by(&server, &card, 4).unwrap();
+			assert_eq!(S::verify_remask_by(&mut client, new_card, &powers, 4, proof).err(), Some(PunchCardError::InvalidPunchCount(4)));
+			clients.push(client);
+		}
+
+		let token = S::unmask_redeem(clients).unwrap();
+		S::server_verify(&mut server, token, 4 * S::CARDS_PER_REDEMPTION).unwrap();
+	}
+
+	//a card redeems at a precomputed threshold, and counts that weren't precomputed are still checked
+	fn thresholds<S: PunchCardScheme>() where S::Store: Default {
+		let (_, mut server) = S::server_setup().unwrap();
+		S::precompute_thresholds(&mut server, &[2, 2 * S::CARDS_PER_REDEMPTION]).unwrap();
+		let powers = S::publish_powers(&server, 2).unwrap();
+
+		let redeem = |server: &mut S::Server, n: u32| {
+			let mut clients = Vec::new();
+			for _ in 0..S::CARDS_PER_REDEMPTION {
+				let (card, mut client) = S::card_setup().unwrap();
+				let (new_card, proof) = S::server_punch_by(server, &card, 2).unwrap();
+				S::verify_remask_by(&mut client, new_card, &powers, 2, proof).unwrap();
+				clients.push(client);
+			}
+			S::server_verify(server, S::unmask_redeem(clients).unwrap(), n)
+		};
+		assert_eq!(redeem(&mut server, 2 * S::CARDS_PER_REDEMPTION), Ok(()));
+		assert_eq!(redeem(&mut server, 3 * S::CARDS_PER_REDEMPTION), Err(PunchCardError::InvalidCard));
+	}
+
+	//an audited redemption only uses up a token it accepts, and the receipt for each decision audits
+	fn audited<S: AuditedScheme>() where S::Store: Default {
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		let powers = S::publish_powers(&server, 4).unwrap();
+		S::verify_powers(&pub_key, &powers).unwrap();
+		//the same token again, from the saved cards
+		let saved: Vec<_> = punched_cards::<S>(4, &pub_key, S::card_setup, |card| S::server_punch(&server, card))
+			.iter().map(|client| S::save_client(client).unwrap()).collect();
+		let token = || S::unmask_redeem(saved.iter().map(|state| S::restore_client(state).unwrap()).collect()).unwrap();
+		let rejected = |result: Result<S::Receipt, AuditedRejection<S::Receipt>>, error| {
+			let rejection = result.err().unwrap();
+			assert_eq!(rejection.error, error);
+			S::audit(&rejection.receipt.unwrap(), &powers).unwrap();
+		};
+
+		rejected(S::server_verify_audited(&mut server, token(), 3), PunchCardError::InvalidCard);
+		assert_eq!(S::count_cards(&server), 0);
+		let receipt = S::server_verify_audited(&mut server, token(), 4).unwrap();
+		S::audit(&receipt, &powers).unwrap();
+		assert_eq!(S::count_cards(&server), S::CARDS_PER_REDEMPTION as usize);
+		rejected(S::server_verify_audited(&mut server, token(), 4), PunchCardError::DoubleRedemption);
+	}
+
+	//a server only takes cards hashed with its own DST, and keeps it through export_key and import_key
+	//like its cards keep theirs through save_client and restore_client
+	fn dsts<S: PunchCardScheme>() where S::Store: Default, S::Dst: OtherDst {
+		let dst = S::Dst::other_dst();
+		let (pub_key, server) = S::server_setup_with_dst(DEFAULT_MERCHANT, S::Store::default(), &dst).unwrap();
+		let cards = |setup: &dyn Fn() -> Result<(S::Card, S::Client), PunchCardError>| -> Vec<Vec<u8>> {
+			punched_cards::<S>(2 * S::CARDS_PER_REDEMPTION, &pub_key, setup, |card| S::server_punch(&server, card))
+				.iter().map(|client| S::save_client(client).unwrap()).collect()
+		};
+		let default_cards = cards(&|| S::card_setup());
+		let other_cards = cards(&|| S::card_setup_with_dst(DEFAULT_MERCHANT, None, &dst));
+		let token = |saved: &[Vec<u8>]| S::unmask_redeem(saved.iter().map(|state| S::restore_client(state).unwrap()).collect()).unwrap();
+
+		let (_, mut reloaded) = S::import_key(&S::export_key(&server).unwrap(), S::Store::default()).unwrap();
+		drop(server);
+		assert_eq!(S::server_verify(&mut reloaded, token(&default_cards), 2 * S::CARDS_PER_REDEMPTION), Err(PunchCardError::InvalidCard));
+		assert_eq!(S::server_verify(&mut reloaded, token(&other_cards), 2 * S::CARDS_PER_REDEMPTION), Ok(()));
+
+		//and a server on the default DST doesn't take cards hashed with another
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		let clients = punched_cards::<S>(2 * S::CARDS_PER_REDEMPTION, &pub_key, || S::card_setup_with_dst(DEFAULT_MERCHANT, None, &dst),
+										 |card| S::server_punch(&server, card));
+		assert_eq!(S::server_verify(&mut server, S::unmask_redeem(clients).unwrap(), 2 * S::CARDS_PER_REDEMPTION), Err(PunchCardError::InvalidCard));
+	}
+
+	both_schemes!(audited, group_audited, pairing_audited);
+	both_schemes!(dsts, group_dsts, pairing_dsts);
+	both_schemes!(thresholds, group_thresholds, pairing_thresholds);
+	both_schemes!(multi_punch, group_multi_punch, pairing_multi_punch);
+	both_schemes!(batch, group_batch_verify, pairing_batch_verify);
+}
@@ -0,0 +1,103 @@
+//Per-operation timing samples for the benchmark and the statistics reported over them
+
+//every timing taken for one operation, in that operation's unit (see operations in lib.rs)
+#[derive(Debug, Default, Clone)]
+pub struct Samples {
+	values: Vec<u128>,
+}
+
+//summary of a set of samples
+//percentiles interpolate linearly between the closest ranks, std_dev is the sample standard deviation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+	pub count: usize,
+	pub total: u128,
+	pub mean: f64,
+	pub median: f64,
+	pub p95: f64,
+	pub p99: f64,
+	pub min: u128,
+	pub max: u128,
+	pub std_dev: f64,
+}
+
+//value at fraction q (0 to 1) of the way through sorted, which must not be empty
+fn percentile(sorted: &[u128], q: f64) -> f64 {
+	let rank = q * (sorted.len() - 1) as f64;
+	let low = rank.floor() as usize;
+	let high = rank.ceil() as usize;
+	let weight = rank - low as f64;
+	sorted[low] as f64 * (1.0 - weight) + sorted[high] as f64 * weight
+}
+
+impl Samples {
+	pub fn record(&mut self, value: u128) {
+		self.values.push(value);
+	}
+
+	pub fn total(&self) -> u128 {
+		self.values.iter().sum()
+	}
+
+	pub fn len(&self) -> usize {
+		self.values.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.values.is_empty()
+	}
+
+	//None if nothing was recorded
+	pub fn summary(&self) -> Option<Summary> {
+		if self.values.is_empty() {
+			return None;
+		}
+		let mut sorted = self.values.clone();
+		sorted.sort_unstable();
+
+		let count = sorted.len();
+		let total = self.total();
+		let mean = total as f64 / count as f64;
+		let variance = if count > 1 {
+			sorted.iter().map(|v| (*v as f64 - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+		} else {
+			0.0
+		};
+
+		Some(Summary {
+			count,
+			total,
+			mean,
+			median: percentile(&sorted, 0.5),
+			p95: percentile(&sorted, 0.95),
+			p99: percentile(&sorted, 0.99),
+			min: sorted[0],
+			max: sorted[count - 1],
+			std_dev: variance.sqrt(),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn summary_of_known_samples() {
+		let mut samples = Samples::default();
+		assert_eq!(samples.summary(), None);
+		for v in &[5u128, 1, 4, 2, 3] {
+			samples.record(*v);
+		}
+		let summary = samples.summary().unwrap();
+		assert_eq!(summary.count, 5);
+		assert_eq!(summary.total, 15);
+		assert_eq!(summary.mean, 3.0);
+		assert_eq!(summary.median, 3.0);
+		assert_eq!(summary.min, 1);
+		assert_eq!(summary.max, 5);
+		//rank 0.95 * 4 = 3.8 -> 4 * 0.2 + 5 * 0.8
+		assert!((summary.p95 - 4.8).abs() < 1e-9);
+		assert!((summary.std_dev - 2.5f64.sqrt()).abs() < 1e-9);
+	}
+}
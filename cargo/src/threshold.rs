@@ -0,0 +1,455 @@
+//Threshold punching for the Ristretto scheme, so no single process holds the server secret
+//the secret x is Shamir shared among n signers: signer i holds f(i) for a random polynomial f of degree t-1
+//with f(0) = x, and publishes its verification key g^f(i). any t signers can punch, fewer learn nothing about x
+//
+//a punch takes two rounds between the combiner and at least t signers
+//1. every signer sends card^f(i) with its own Chaum-Pedersen proof against its verification key,
+//   and commitments g^r_i, card^r_i for a fresh nonce r_i
+//2. the combiner checks the proofs and interpolates the punched card card^x and the commitments in the exponent,
+//   hashes them into the challenge c like a normal punch, and every signer answers r_i + f(i)*c
+//the interpolated answers make an ordinary punch proof against the group public key g^x,
+//so a client checks a threshold punch with verify_remask exactly like a punch from a ServerData
+//
+//redemption needs H(card_secret)^(x^n), which the combiner gets with round 1 alone, once per punch
+//(see ThresholdCombiner::server_verify)
+
+use rand_core::OsRng;
+use curve25519_dalek::constants;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::VartimeMultiscalarMul;
+use std::collections::BTreeMap;
+use super::crypto::{Proof, card_hash, dleq_challenge, dleq_punch, dleq_verify, punch_transcript};
+use super::error::PunchCardError;
+use super::nullifier::{NullifierStore, MemoryNullifierStore};
+use super::epoch::DEFAULT_EPOCH;
+use super::expiry::{check_expiry, ExpiryPolicy};
+use super::hash_to_curve::RISTRETTO255_DST;
+
+//one signer process, holding a single share of the secret
+#[derive(Debug)]
+pub struct ThresholdSigner {
+	index: u32,
+	merchant: u32,
+	epoch: u32,
+	share: Scalar,
+	verification_key: CompressedRistretto,
+}
+
+//everything public about a shared secret, plus the redeemed card db
+//it talks to the signers but can't punch on its own
+#[derive(Debug)]
+pub struct ThresholdCombiner<N: NullifierStore = MemoryNullifierStore> {
+	threshold: u32,
+	merchant: u32,
+	epoch: u32,
+	pub_secret: CompressedRistretto,
+	//g^f(i) for each signer index i
+	verification_keys: BTreeMap<u32, CompressedRistretto>,
+	used_cards: N,
+	//which card secrets are taken, None takes any (see expiry.rs)
+	expiry: Option<ExpiryPolicy>,
+	//DST cards are hashed with (see hash_to_curve.rs)
+	dst: Vec<u8>,
+}
+
+//card^f(index) and a proof against signer index's verification key
+#[derive(Debug)]
+pub struct PartialCard {
+	pub index: u32,
+	pub card: CompressedRistretto,
+	pub proof: Proof,
+}
+
+//a signer's first round of a punch: its partial card and commitments to its nonce
+#[derive(Debug)]
+pub struct PartialPunch {
+	pub partial: PartialCard,
+	pub v_t: CompressedRistretto,
+	pub w_t: CompressedRistretto,
+}
+
+//the nonce behind a PartialPunch, kept by the signer for the second round
+//answering two challenges with one nonce would give away the share, so respond uses it up
+#[derive(Debug)]
+pub struct SignerNonce {
+	index: u32,
+	beta_t: Scalar,
+}
+
+//a signer's second round of a punch
+#[derive(Debug)]
+pub struct PartialResponse {
+	pub index: u32,
+	pub beta_z: Scalar,
+}
+
+//what the combiner remembers about one signer between the rounds
+#[derive(Debug)]
+struct SessionSigner {
+	index: u32,
+	lagrange: Scalar,
+	verification_key: RistrettoPoint,
+	partial: RistrettoPoint,
+	v_t: RistrettoPoint,
+	w_t: RistrettoPoint,
+}
+
+//a punch between the two rounds
+#[derive(Debug)]
+pub struct PunchSession {
+	card: RistrettoPoint,
+	new_card: CompressedRistretto,
+	v_t: CompressedRistretto,
+	w_t: CompressedRistretto,
+	chal: Scalar,
+	signers: Vec<SessionSigner>,
+}
+
+//split a fresh secret for merchant among signers signers, any threshold of which can punch
+pub fn deal<N: NullifierStore>(merchant: u32, used_cards: N, threshold: u32,
+							   signers: u32) -> Result<(ThresholdCombiner<N>, Vec<ThresholdSigner>), PunchCardError> {
+	deal_secret(Scalar::random(&mut OsRng), merchant, DEFAULT_EPOCH, used_cards, threshold, signers, RISTRETTO255_DST.to_vec())
+}
+
+//Shamir sharing of secret, signers get the indices 1 to signers
+//proofs are made for merchant in epoch and cards are hashed with dst, like the ServerData the secret came from
+pub(crate) fn deal_secret<N: NullifierStore>(secret: Scalar, merchant: u32, epoch: u32, used_cards: N, threshold: u32,
+											 signers: u32, dst: Vec<u8>) -> Result<(ThresholdCombiner<N>, Vec<ThresholdSigner>), PunchCardError> {
+	if threshold == 0 || threshold > signers {
+		return Err(PunchCardError::BadThreshold { threshold, signers });
+	}
+
+	let mut coefficients = vec![secret];
+	coefficients.extend((1..threshold).map(|_| Scalar::random(&mut OsRng)));
+
+	let shares: Vec<ThresholdSigner> = (1..=signers).map(|index| {
+		//Horner's rule for f(index)
+		let x = Scalar::from(index);
+		let share = coefficients.iter().rev().fold(Scalar::zero(), |acc, a| acc * x + a);
+		ThresholdSigner {
+			index,
+			merchant,
+			epoch,
+			share,
+			verification_key: (&share * &constants::RISTRETTO_BASEPOINT_TABLE).compress(),
+		}
+	}).collect();
+
+	let combiner = ThresholdCombiner {
+		threshold,
+		merchant,
+		epoch,
+		pub_secret: (&secret * &constants::RISTRETTO_BASEPOINT_TABLE).compress(),
+		verification_keys: shares.iter().map(|s| (s.index, s.verification_key)).collect(),
+		used_cards,
+		expiry: None,
+		dst,
+	};
+	Ok((combiner, shares))
+}
+
+//l_i for each index i, so that f(0) = sum l_i*f(i) for any f of degree below indices.len()
+fn lagrange_at_zero(indices: &[u32]) -> Vec<Scalar> {
+	indices.iter().map(|&i| {
+		let mut num = Scalar::one();
+		let mut den = Scalar::one();
+		for &j in indices.iter().filter(|&&j| j != i) {
+			num *= Scalar::from(j);
+			den *= Scalar::from(j) - Scalar::from(i);
+		}
+		num * den.invert()
+	}).collect()
+}
+
+fn decompress(point: &CompressedRistretto) -> Result<RistrettoPoint, PunchCardError> {
+	point.decompress().ok_or(PunchCardError::MalformedPoint)
+}
+
+impl ThresholdSigner {
+
+	pub fn index(&self) -> u32 {
+		self.index
+	}
+
+	pub fn verification_key(&self) -> CompressedRistretto {
+		self.verification_key
+	}
+
+	//raise card to this signer's share, with a proof
+	pub fn partial_card(&self, card: CompressedRistretto) -> Result<PartialCard, PunchCardError> {
+		let (new_card, proof) = dleq_punch(&punch_transcript(self.merchant, self.epoch), card, self.share, self.verification_key)?;
+		Ok(PartialCard {
+			index: self.index,
+			card: new_card,
+			proof,
+		})
+	}
+
+	//first round of a punch
+	pub fn partial_punch(&self, card: CompressedRistretto) -> Result<(PartialPunch, SignerNonce), PunchCardError> {
+		let partial = self.partial_card(card)?;
+		let beta_t = Scalar::random(&mut OsRng);
+		let punch = PartialPunch {
+			partial,
+			v_t: (&beta_t * &constants::RISTRETTO_BASEPOINT_TABLE).compress(),
+			w_t: (decompress(&card)? * beta_t).compress(),
+		};
+		Ok((punch, SignerNonce { index: self.index, beta_t }))
+	}
+
+	//second round of a punch, chal comes from PunchSession::challenge
+	pub fn respond(&self, nonce: SignerNonce, chal: Scalar) -> Result<PartialResponse, PunchCardError> {
+		if nonce.index != self.index {
+			return Err(PunchCardError::UnknownSigner(nonce.index));
+		}
+		Ok(PartialResponse {
+			index: self.index,
+			beta_z: nonce.beta_t + self.share * chal,
+		})
+	}
+}
+
+impl<N: NullifierStore> ThresholdCombiner<N> {
+
+	//the group public key, clients check punches against it
+	pub fn pub_secret(&self) -> CompressedRistretto {
+		self.pub_secret
+	}
+
+	pub fn threshold(&self) -> u32 {
+		self.threshold
+	}
+
+	pub fn merchant(&self) -> u32 {
+		self.merchant
+	}
+
+	pub fn verification_key(&self, index: u32) -> Option<CompressedRistretto> {
+		self.verification_keys.get(&index).copied()
+	}
+
+	//check the partial cards for card and get their Lagrange coefficients
+	//BadProofAt gives the position of a partial whose proof fails, so the caller can ask another signer
+	fn check_partials(&self, card: &CompressedRistretto, partials: &[&PartialCard]) -> Result<Vec<Scalar>, PunchCardError> {
+		if partials.len() < self.threshold as usize {
+			return Err(PunchCardError::NotEnoughSigners { needed: self.threshold, got: partials.len() as u32 });
+		}
+		let base = punch_transcript(self.merchant, self.epoch);
+		let mut indices = Vec::with_capacity(partials.len());
+		for (i, partial) in partials.iter().enumerate() {
+			let key = self.verification_key(partial.index).ok_or(PunchCardError::UnknownSigner(partial.index))?;
+			if indices.contains(&partial.index) {
+				return Err(PunchCardError::DuplicateSigner(partial.index));
+			}
+			dleq_verify(&base, &key, card, &partial.card, &partial.proof).map_err(|_| PunchCardError::BadProofAt(i))?;
+			indices.push(partial.index);
+		}
+		Ok(lagrange_at_zero(&indices))
+	}
+
+	//card^x from at least threshold partial cards
+	pub fn combine(&self, card: CompressedRistretto, partials: &[PartialCard]) -> Result<CompressedRistretto, PunchCardError> {
+		let refs: Vec<&PartialCard> = partials.iter().collect();
+		let lagrange = self.check_partials(&card, &refs)?;
+		let points = partials.iter().map(|p| decompress(&p.card)).collect::<Result<Vec<_>, _>>()?;
+		Ok(RistrettoPoint::vartime_multiscalar_mul(&lagrange, &points).compress())
+	}
+
+	//after the first round of a punch: combine the punched card and work out the challenge
+	//every signer that took part has to answer it
+	pub fn start_punch(&self, card: CompressedRistretto, punches: &[PartialPunch]) -> Result<PunchSession, PunchCardError> {
+		let refs: Vec<&PartialCard> = punches.iter().map(|p| &p.partial).collect();
+		let lagrange = self.check_partials(&card, &refs)?;
+
+		let mut signers = Vec::with_capacity(punches.len());
+		for (punch, lagrange) in punches.iter().zip(lagrange) {
+			let index = punch.partial.index;
+			signers.push(SessionSigner {
+				index,
+				lagrange,
+				//checked by check_partials
+				verification_key: decompress(&self.verification_keys[&index])?,
+				partial: decompress(&punch.partial.card)?,
+				v_t: decompress(&punch.v_t)?,
+				w_t: decompress(&punch.w_t)?,
+			});
+		}
+		let lagrange: Vec<Scalar> = signers.iter().map(|s| s.lagrange).collect();
+		let interpolate = |points: Vec<RistrettoPoint>| RistrettoPoint::vartime_multiscalar_mul(&lagrange, &points).compress();
+		let new_card = interpolate(signers.iter().map(|s| s.partial).collect());
+		let v_t = interpolate(signers.iter().map(|s| s.v_t).collect());
+		let w_t = interpolate(signers.iter().map(|s| s.w_t).collect());
+
+		Ok(PunchSession {
+			card: decompress(&card)?,
+			new_card,
+			v_t,
+			w_t,
+			chal: dleq_challenge(&punch_transcript(self.merchant, self.epoch), &self.pub_secret, &card, &new_card, &v_t, &w_t),
+			signers,
+		})
+	}
+
+	//check every card secret taken from now on against policy (see expiry.rs), None takes any
+	pub fn set_expiry_policy(&mut self, policy: Option<ExpiryPolicy>) {
+		self.expiry = policy;
+	}
+
+	//check that card is H(card_secret)^(x^num_punches) and that the card secret is new and taken by the expiry policy
+	//exponentiate asks the signers for partial cards of the card it's given (e.g. with ThresholdSigner::partial_card),
+	//it is called once per punch
+	pub fn server_verify<F>(&mut self, card: CompressedRistretto, card_secret: [u8; 32], num_punches: u32,
+							mut exponentiate: F) -> Result<(), PunchCardError>
+		where F: FnMut(CompressedRistretto) -> Result<Vec<PartialCard>, PunchCardError> {
+
+		check_expiry(self.expiry, &[card_secret])?;
+		let mut expected_card = card_hash(self.merchant, &card_secret, &self.dst).compress();
+		for _ in 0..num_punches {
+			let partials = exponentiate(expected_card)?;
+			expected_card = self.combine(expected_card, &partials)?;
+		}
+		if card != expected_card {
+			return Err(PunchCardError::InvalidCard);
+		}
+		//insert returns false if this was already in the store
+		if !self.used_cards.insert(card_secret)? {
+			return Err(PunchCardError::DoubleRedemption);
+		}
+		Ok(())
+	}
+
+	pub fn count_cards(&self) -> usize {
+		self.used_cards.len()
+	}
+}
+
+impl PunchSession {
+
+	//what the signers answer in the second round
+	pub fn challenge(&self) -> Scalar {
+		self.chal
+	}
+
+	//the signers that have to answer, in the order their partial punches came
+	pub fn signers(&self) -> Vec<u32> {
+		self.signers.iter().map(|s| s.index).collect()
+	}
+
+	//check every signer's answer and put together the punched card and its proof
+	//BadProofAt gives the position in responses of an answer that doesn't match its commitments
+	pub fn finish(self, responses: &[PartialResponse]) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		let mut answers = BTreeMap::new();
+		for (i, response) in responses.iter().enumerate() {
+			if !self.signers.iter().any(|s| s.index == response.index) {
+				return Err(PunchCardError::UnknownSigner(response.index));
+			}
+			if answers.insert(response.index, (i, response.beta_z)).is_some() {
+				return Err(PunchCardError::DuplicateSigner(response.index));
+			}
+		}
+
+		let mut beta_z = Scalar::zero();
+		for signer in &self.signers {
+			let (i, z) = *answers.get(&signer.index)
+				.ok_or(PunchCardError::NotEnoughSigners { needed: self.signers.len() as u32, got: answers.len() as u32 })?;
+			//the same check as a whole proof, against the signer's share
+			let gbz: RistrettoPoint = &z * &constants::RISTRETTO_BASEPOINT_TABLE;
+			if gbz != signer.v_t + signer.verification_key * self.chal || self.card * z != signer.w_t + signer.partial * self.chal {
+				return Err(PunchCardError::BadProofAt(i));
+			}
+			beta_z += signer.lagrange * z;
+		}
+
+		let proof = Proof {
+			v_t: self.v_t,
+			w_t: self.w_t,
+			beta_z: beta_z.to_bytes(),
+		};
+		Ok((self.new_card, proof))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::crypto::{PunchCard, ServerData};
+	use super::super::merchant::DEFAULT_MERCHANT;
+
+	//both rounds of a punch of card with signers
+	fn punch(combiner: &ThresholdCombiner, signers: &[&ThresholdSigner], card: CompressedRistretto) -> Result<(CompressedRistretto, Proof), PunchCardError> {
+		let (punches, nonces): (Vec<_>, Vec<_>) = signers.iter().map(|s| s.partial_punch(card).unwrap()).unzip();
+		let session = combiner.start_punch(card, &punches)?;
+		let responses: Vec<_> = signers.iter().zip(nonces).map(|(s, nonce)| s.respond(nonce, session.challenge()).unwrap()).collect();
+		session.finish(&responses)
+	}
+
+	fn partial_cards(signers: &[&ThresholdSigner], card: CompressedRistretto) -> Result<Vec<PartialCard>, PunchCardError> {
+		signers.iter().map(|s| s.partial_card(card)).collect()
+	}
+
+	#[test]
+	fn any_threshold_of_signers() {
+		let (mut combiner, signers) = deal(DEFAULT_MERCHANT, MemoryNullifierStore::new(), 2, 3).unwrap();
+		let pub_secret = combiner.pub_secret();
+		let (mut card, mut client) = PunchCard::card_setup();
+		for set in [[0, 1], [2, 1], [0, 2]] {
+			let (new_card, proof) = punch(&combiner, &[&signers[set[0]], &signers[set[1]]], card).unwrap();
+			card = client.verify_remask(new_card, pub_secret, proof).unwrap();
+		}
+		//more than the threshold works too
+		let all: Vec<&ThresholdSigner> = signers.iter().collect();
+		let (new_card, proof) = punch(&combiner, &all, card).unwrap();
+		client.verify_remask(new_card, pub_secret, proof).unwrap();
+
+		let (card_secret, card) = client.unmask_redeem();
+		assert_eq!(combiner.server_verify(card, card_secret, 3, |c| partial_cards(&all[1..], c)), Err(PunchCardError::InvalidCard));
+		combiner.server_verify(card, card_secret, 4, |c| partial_cards(&all[1..], c)).unwrap();
+		assert_eq!(combiner.server_verify(card, card_secret, 4, |c| partial_cards(&all[..2], c)), Err(PunchCardError::DoubleRedemption));
+		assert_eq!(combiner.count_cards(), 1);
+	}
+
+	#[test]
+	fn rejects_bad_signers() {
+		let (combiner, signers) = deal(DEFAULT_MERCHANT, MemoryNullifierStore::new(), 2, 3).unwrap();
+		let (card, _) = PunchCard::card_setup();
+		assert_eq!(punch(&combiner, &[&signers[0]], card).err(), Some(PunchCardError::NotEnoughSigners { needed: 2, got: 1 }));
+		assert_eq!(punch(&combiner, &[&signers[1], &signers[1]], card).err(), Some(PunchCardError::DuplicateSigner(2)));
+
+		//a signer from another deal has the wrong share
+		let (_, strangers) = deal(DEFAULT_MERCHANT, MemoryNullifierStore::new(), 2, 3).unwrap();
+		assert_eq!(punch(&combiner, &[&signers[0], &strangers[1]], card).err(), Some(PunchCardError::BadProofAt(1)));
+
+		//an answer to the wrong challenge
+		let (first, first_nonce) = signers[0].partial_punch(card).unwrap();
+		let (second, second_nonce) = signers[1].partial_punch(card).unwrap();
+		let session = combiner.start_punch(card, &[first, second]).unwrap();
+		assert_eq!(session.signers(), vec![1, 2]);
+		let responses = [
+			signers[1].respond(second_nonce, session.challenge()).unwrap(),
+			signers[0].respond(first_nonce, session.challenge() + Scalar::one()).unwrap(),
+		];
+		assert_eq!(session.finish(&responses).err(), Some(PunchCardError::BadProofAt(1)));
+
+		assert_eq!(deal(DEFAULT_MERCHANT, MemoryNullifierStore::new(), 0, 3).err(), Some(PunchCardError::BadThreshold { threshold: 0, signers: 3 }));
+		assert_eq!(deal(DEFAULT_MERCHANT, MemoryNullifierStore::new(), 4, 3).err(), Some(PunchCardError::BadThreshold { threshold: 4, signers: 3 }));
+	}
+
+	#[test]
+	fn split_existing_server() {
+		let (pub_secret, server) = ServerData::server_setup_for(5, MemoryNullifierStore::new());
+		let (card, mut client) = PunchCard::card_setup_for(5);
+		let (new_card, proof) = server.server_punch(card).unwrap();
+		let card = client.verify_remask(new_card, pub_secret, proof).unwrap();
+
+		let (mut combiner, signers) = server.into_threshold(3, 5).unwrap();
+		assert_eq!((combiner.pub_secret(), combiner.merchant()), (pub_secret, 5));
+		let set = [&signers[4], &signers[0], &signers[2]];
+		let (new_card, proof) = punch(&combiner, &set, card).unwrap();
+		client.verify_remask(new_card, pub_secret, proof).unwrap();
+
+		let (card_secret, card) = client.unmask_redeem();
+		combiner.server_verify(card, card_secret, 2, |c| partial_cards(&set, c)).unwrap();
+	}
+}
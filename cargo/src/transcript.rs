@@ -0,0 +1,86 @@
+//Fiat-Shamir transcripts, in the style of Merlin
+//a transcript starts from the protocol name, the transcript version, which proof it is for, the merchant and the epoch,
+//and everything appended after that is framed with a label and its length, so two different sequences of
+//appends never hash the same and a proof made for one context (or one kind of proof) never verifies in another
+//the prover and the verifier append the same things in the same order, then squeeze out the challenge
+
+use sha2::{Digest, Sha512};
+
+pub const PROTOCOL: &[u8] = b"PunchCard";
+//bump when what goes into a transcript changes, so old proofs stop verifying instead of verifying by accident
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+#[derive(Clone)]
+pub struct Transcript {
+	hasher: Sha512,
+}
+
+impl Transcript {
+
+	//a transcript for one kind of proof, e.g. a punch in one group
+	pub fn new(proof: &'static [u8], merchant: u32, epoch: u32) -> Transcript {
+		let mut transcript = Transcript {
+			hasher: Sha512::new(),
+		};
+		transcript.append_message(b"protocol", PROTOCOL);
+		transcript.append_u32(b"version", TRANSCRIPT_VERSION);
+		transcript.append_message(b"proof", proof);
+		transcript.append_u32(b"merchant", merchant);
+		transcript.append_u32(b"epoch", epoch);
+		transcript
+	}
+
+	//label length (u32 little endian) || label || message length (u64 little endian) || message
+	pub fn append_message(&mut self, label: &'static [u8], message: &[u8]) {
+		self.hasher.input((label.len() as u32).to_le_bytes());
+		self.hasher.input(label);
+		self.hasher.input((message.len() as u64).to_le_bytes());
+		self.hasher.input(message);
+	}
+
+	pub fn append_u32(&mut self, label: &'static [u8], value: u32) {
+		self.append_message(label, &value.to_le_bytes());
+	}
+
+	//64 bytes that depend on everything appended so far and on label
+	//the challenge is appended in turn, so a later challenge depends on this one
+	pub fn challenge_bytes(&mut self, label: &'static [u8]) -> [u8; 64] {
+		let mut squeeze = self.hasher.clone();
+		squeeze.input((label.len() as u32).to_le_bytes());
+		squeeze.input(label);
+		squeeze.input(b"challenge");
+		let mut challenge = [0u8; 64];
+		challenge.copy_from_slice(&squeeze.result());
+		self.append_message(label, &challenge);
+		challenge
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn framing_and_context() {
+		let challenge = |t: &Transcript| t.clone().challenge_bytes(b"c");
+		let base = Transcript::new(b"test", 1, 2);
+
+		let mut joined = base.clone();
+		joined.append_message(b"a", b"bc");
+		let mut split = base.clone();
+		split.append_message(b"ab", b"c");
+		assert_ne!(challenge(&joined).to_vec(), challenge(&split).to_vec());
+
+		let mut same = base.clone();
+		same.append_message(b"a", b"bc");
+		assert_eq!(challenge(&joined).to_vec(), challenge(&same).to_vec());
+
+		for other in [Transcript::new(b"tests", 1, 2), Transcript::new(b"test", 2, 2), Transcript::new(b"test", 1, 3)] {
+			assert_ne!(challenge(&base).to_vec(), challenge(&other).to_vec());
+		}
+
+		//squeezing twice gives different challenges
+		let mut twice = base.clone();
+		assert_ne!(twice.challenge_bytes(b"c").to_vec(), twice.challenge_bytes(b"c").to_vec());
+	}
+}
@@ -0,0 +1,73 @@
+//Saving and restoring a client's card so its progress survives the app being killed
+//card format: CARD_MAGIC || scheme tag || version || scheme specific body (see save_state in each backend)
+//restoring checks the saved state for consistency before handing back a usable card
+
+use std::path::Path;
+use super::error::PunchCardError;
+use super::keyfile::{encode_tagged, decode_tagged, write_key_file, read_key_file};
+use super::scheme::PunchCardScheme;
+
+pub const CARD_MAGIC: &[u8; 4] = b"PCCD";
+pub const CARD_VERSION: u8 = 1;
+
+pub(crate) fn encode_card_state(tag: u8, body: &[u8]) -> Vec<u8> {
+	encode_tagged(CARD_MAGIC, tag, CARD_VERSION, body)
+}
+
+pub(crate) fn decode_card_state(tag: u8, bytes: &[u8], body_len: usize) -> Result<(&[u8], &[u8]), PunchCardError> {
+	decode_tagged(CARD_MAGIC, tag, CARD_VERSION, bytes, body_len)
+}
+
+//the card secret is as sensitive as a server key (whoever has it can redeem the card),
+//so the file is written the same way
+pub fn save_card_file<S: PunchCardScheme, P: AsRef<Path>>(client: &S::Client, path: P) -> Result<(), PunchCardError> {
+	write_key_file(path, &S::save_client(client)?)
+}
+
+pub fn load_card_file<S: PunchCardScheme, P: AsRef<Path>>(path: P) -> Result<S::Client, PunchCardError> {
+	S::restore_client(&read_key_file(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::scheme::testing::{both_schemes, punch_more, punched_cards};
+	use std::fs;
+
+	//punch, save, "kill the app", restore, keep punching, redeem
+	fn resume<S: PunchCardScheme>() where S::Store: Default {
+		let path = std::env::temp_dir().join(format!("punchcard-card-{}-{}", S::NAME.replace(' ', "-"), std::process::id()));
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		let mut punch = |card: &S::Card| S::server_punch(&server, card);
+
+		let clients = punched_cards::<S>(S::CARDS_PER_REDEMPTION, &pub_key, S::card_setup, &mut punch).into_iter().map(|client| {
+			save_card_file::<S, _>(&client, &path).unwrap();
+			drop(client);
+
+			let mut client = load_card_file::<S, _>(&path).unwrap();
+			assert_eq!(S::get_count(&client).unwrap(), 1);
+			punch_more::<S>(&mut client, 1, &pub_key, &mut punch);
+			client
+		}).collect();
+		fs::remove_file(&path).unwrap();
+
+		let token = S::unmask_redeem(clients).unwrap();
+		S::server_verify(&mut server, token, 2 * S::CARDS_PER_REDEMPTION).unwrap();
+	}
+
+	//a fresh card whose secret was changed no longer matches its masked card
+	fn rejects_tampering<S: PunchCardScheme>() {
+		let (_, client) = S::card_setup().unwrap();
+		let mut state = S::save_client(&client).unwrap();
+		//skipping the header, merchant and epoch
+		state[14] ^= 1;
+		assert!(S::restore_client(&state).is_err());
+	}
+
+	fn card_resume<S: PunchCardScheme>() where S::Store: Default {
+		resume::<S>();
+		rejects_tampering::<S>();
+	}
+
+	both_schemes!(card_resume, group_card_resume, pairing_card_resume);
+}
@@ -0,0 +1,192 @@
+//Binary encoding for the messages that cross the client/server boundary
+//every message is: scheme tag (1 byte) || version (1 byte) || message type (1 byte) || payload
+//payloads are fixed length for each scheme, so the total length is checked before anything is parsed
+//group elements are length checked here but only validated when the protocol uses them
+
+use super::scheme::PunchCardScheme;
+use super::error::PunchCardError;
+
+pub const WIRE_VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 3;
+
+//scheme tags
+pub const TAG_RISTRETTO: u8 = 1;
+pub const TAG_BLS12_381: u8 = 2;
+
+//message types
+//card setup: the server's public key, which the client needs before it can check any punch
+pub const MSG_CARD_SETUP: u8 = 1;
+//punch request: the client's masked card
+pub const MSG_PUNCH_REQUEST: u8 = 2;
+//punch response: the punched card and the proof that it was punched honestly
+pub const MSG_PUNCH_RESPONSE: u8 = 3;
+//redemption: the unmasked card(s) and card secret(s)
+pub const MSG_REDEMPTION: u8 = 4;
+
+//fixed-length encoding of each scheme's message components
+//readers are always handed exactly *_LEN bytes
+pub trait WireScheme: PunchCardScheme {
+	const SCHEME_TAG: u8;
+	const PUBLIC_KEY_LEN: usize;
+	const CARD_LEN: usize;
+	const PROOF_LEN: usize;
+	const REDEEM_TOKEN_LEN: usize;
+
+	fn write_public_key(pub_key: &Self::PublicKey, out: &mut Vec<u8>);
+	fn read_public_key(bytes: &[u8]) -> Result<Self::PublicKey, PunchCardError>;
+
+	fn write_card(card: &Self::Card, out: &mut Vec<u8>);
+	fn read_card(bytes: &[u8]) -> Result<Self::Card, PunchCardError>;
+
+	fn write_proof(proof: &Self::Proof, out: &mut Vec<u8>);
+	fn read_proof(bytes: &[u8]) -> Result<Self::Proof, PunchCardError>;
+
+	fn write_redeem_token(token: &Self::RedeemToken, out: &mut Vec<u8>);
+	fn read_redeem_token(bytes: &[u8]) -> Result<Self::RedeemToken, PunchCardError>;
+}
+
+//copy a 32 byte field out of a slice that has already been length checked
+pub(crate) fn read_array32(bytes: &[u8]) -> Result<[u8; 32], PunchCardError> {
+	if bytes.len() != 32 {
+		return Err(PunchCardError::BadLength { expected: 32, got: bytes.len() });
+	}
+	let mut out = [0u8; 32];
+	out.copy_from_slice(bytes);
+	Ok(out)
+}
+
+//add the header, let write fill in the payload, and make sure it came out the right size
+fn encode<S: WireScheme>(msg_type: u8, payload_len: usize, write: impl FnOnce(&mut Vec<u8>)) -> Result<Vec<u8>, PunchCardError> {
+	let mut out = Vec::with_capacity(HEADER_LEN + payload_len);
+	out.push(S::SCHEME_TAG);
+	out.push(WIRE_VERSION);
+	out.push(msg_type);
+	write(&mut out);
+	if out.len() != HEADER_LEN + payload_len {
+		return Err(PunchCardError::BadLength { expected: HEADER_LEN + payload_len, got: out.len() });
+	}
+	Ok(out)
+}
+
+//check the header and total length, return the payload
+fn open<S: WireScheme>(bytes: &[u8], msg_type: u8, payload_len: usize) -> Result<&[u8], PunchCardError> {
+	if bytes.len() < HEADER_LEN {
+		return Err(PunchCardError::BadLength { expected: HEADER_LEN + payload_len, got: bytes.len() });
+	}
+	if bytes[0] != S::SCHEME_TAG {
+		return Err(PunchCardError::WrongScheme(bytes[0]));
+	}
+	if bytes[1] != WIRE_VERSION {
+		return Err(PunchCardError::UnsupportedVersion(bytes[1]));
+	}
+	if bytes[2] != msg_type {
+		return Err(PunchCardError::WrongMessageType(bytes[2]));
+	}
+	if bytes.len() != HEADER_LEN + payload_len {
+		return Err(PunchCardError::BadLength { expected: HEADER_LEN + payload_len, got: bytes.len() });
+	}
+	Ok(&bytes[HEADER_LEN..])
+}
+
+pub fn encode_card_setup<S: WireScheme>(pub_key: &S::PublicKey) -> Result<Vec<u8>, PunchCardError> {
+	encode::<S>(MSG_CARD_SETUP, S::PUBLIC_KEY_LEN, |out| S::write_public_key(pub_key, out))
+}
+
+pub fn decode_card_setup<S: WireScheme>(bytes: &[u8]) -> Result<S::PublicKey, PunchCardError> {
+	let payload = open::<S>(bytes, MSG_CARD_SETUP, S::PUBLIC_KEY_LEN)?;
+	S::read_public_key(payload)
+}
+
+pub fn encode_punch_request<S: WireScheme>(card: &S::Card) -> Result<Vec<u8>, PunchCardError> {
+	encode::<S>(MSG_PUNCH_REQUEST, S::CARD_LEN, |out| S::write_card(card, out))
+}
+
+pub fn decode_punch_request<S: WireScheme>(bytes: &[u8]) -> Result<S::Card, PunchCardError> {
+	let payload = open::<S>(bytes, MSG_PUNCH_REQUEST, S::CARD_LEN)?;
+	S::read_card(payload)
+}
+
+pub fn encode_punch_response<S: WireScheme>(card: &S::Card, proof: &S::Proof) -> Result<Vec<u8>, PunchCardError> {
+	encode::<S>(MSG_PUNCH_RESPONSE, S::CARD_LEN + S::PROOF_LEN, |out| {
+		S::write_card(card, out);
+		S::write_proof(proof, out);
+	})
+}
+
+pub fn decode_punch_response<S: WireScheme>(bytes: &[u8]) -> Result<(S::Card, S::Proof), PunchCardError> {
+	let payload = open::<S>(bytes, MSG_PUNCH_RESPONSE, S::CARD_LEN + S::PROOF_LEN)?;
+	let (card, proof) = payload.split_at(S::CARD_LEN);
+	Ok((S::read_card(card)?, S::read_proof(proof)?))
+}
+
+pub fn encode_redemption<S: WireScheme>(token: &S::RedeemToken) -> Result<Vec<u8>, PunchCardError> {
+	encode::<S>(MSG_REDEMPTION, S::REDEEM_TOKEN_LEN, |out| S::write_redeem_token(token, out))
+}
+
+pub fn decode_redemption<S: WireScheme>(bytes: &[u8]) -> Result<S::RedeemToken, PunchCardError> {
+	let payload = open::<S>(bytes, MSG_REDEMPTION, S::REDEEM_TOKEN_LEN)?;
+	S::read_redeem_token(payload)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::crypto::GroupScheme;
+	use super::super::crypto_pairing::PairingScheme;
+	use super::super::scheme::testing::both_schemes;
+
+	//run the whole protocol, sending every message through the wire format
+	fn round_trip<S: WireScheme>() where S::Store: Default {
+		let (pub_key, mut server) = S::server_setup().unwrap();
+		let pub_key = decode_card_setup::<S>(&encode_card_setup::<S>(&pub_key).unwrap()).unwrap();
+
+		let mut clients = Vec::new();
+		for _ in 0..S::CARDS_PER_REDEMPTION {
+			let (mut card, mut client) = S::card_setup().unwrap();
+			for _ in 0..2 {
+				let request = encode_punch_request::<S>(&card).unwrap();
+				let (new_card, proof) = S::server_punch(&server, &decode_punch_request::<S>(&request).unwrap()).unwrap();
+				let response = encode_punch_response::<S>(&new_card, &proof).unwrap();
+				let (new_card, proof) = decode_punch_response::<S>(&response).unwrap();
+				card = S::verify_remask(&mut client, new_card, &pub_key, proof).unwrap();
+			}
+			clients.push(client);
+		}
+
+		let token = S::unmask_redeem(clients).unwrap();
+		let redemption = encode_redemption::<S>(&token).unwrap();
+		assert_eq!(redemption.len(), HEADER_LEN + S::REDEEM_TOKEN_LEN);
+		let token = decode_redemption::<S>(&redemption).unwrap();
+		S::server_verify(&mut server, token, 2 * S::CARDS_PER_REDEMPTION).unwrap();
+	}
+
+	fn rejects_bad_framing<S: WireScheme>() {
+		let (card, _) = S::card_setup().unwrap();
+		let request = encode_punch_request::<S>(&card).unwrap();
+
+		let mut truncated = request.clone();
+		truncated.pop();
+		assert!(matches!(decode_punch_request::<S>(&truncated), Err(PunchCardError::BadLength { .. })));
+
+		let mut extended = request.clone();
+		extended.push(0);
+		assert!(matches!(decode_punch_request::<S>(&extended), Err(PunchCardError::BadLength { .. })));
+
+		let mut wrong_version = request.clone();
+		wrong_version[1] = WIRE_VERSION + 1;
+		assert_eq!(decode_punch_request::<S>(&wrong_version).err(), Some(PunchCardError::UnsupportedVersion(WIRE_VERSION + 1)));
+
+		assert_eq!(decode_redemption::<S>(&request).err(), Some(PunchCardError::WrongMessageType(MSG_PUNCH_REQUEST)));
+		assert!(decode_punch_request::<S>(&[]).is_err());
+	}
+
+	both_schemes!(round_trip, group_round_trip, pairing_round_trip);
+	both_schemes!(rejects_bad_framing, group_rejects_bad_framing, pairing_rejects_bad_framing);
+
+	#[test]
+	fn schemes_are_not_confused() {
+		let (card, _) = <GroupScheme>::card_setup().unwrap();
+		let request = encode_punch_request::<GroupScheme>(&card).unwrap();
+		assert_eq!(decode_punch_request::<PairingScheme>(&request).err(), Some(PunchCardError::WrongScheme(TAG_RISTRETTO)));
+	}
+}